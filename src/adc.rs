@@ -0,0 +1,39 @@
+//! ADC channel markers for embedded-hal `adc::OneShot`
+//!
+//! embedded-hal identifies ADC channels by type, these markers map to IIO voltage
+//! channels (ie. `Voltage0` reads `in_voltage0_raw`) on both local and remote ADCs.
+
+use embedded_hal::adc::Channel;
+
+macro_rules! voltage_channels {
+    ($($name:ident: $id:expr,)*) => {
+        $(
+            #[doc = "IIO voltage channel marker"]
+            pub struct $name;
+
+            impl<A> Channel<A> for $name {
+                type ID = u8;
+
+                fn channel() -> u8 {
+                    $id
+                }
+            }
+        )*
+    }
+}
+
+voltage_channels!(
+    Voltage0: 0,
+    Voltage1: 1,
+    Voltage2: 2,
+    Voltage3: 3,
+    Voltage4: 4,
+    Voltage5: 5,
+    Voltage6: 6,
+    Voltage7: 7,
+);
+
+/// Fetch the IIO channel name for a voltage channel id
+pub(crate) fn voltage_channel(id: u8) -> String {
+    format!("voltage{}", id)
+}
@@ -0,0 +1,69 @@
+//! Non-blocking device traits, mirroring `embedded-hal-async` for futures 0.1
+//!
+//! Operations return futures in place of blocking on completion, allowing drivers to
+//! use remote devices from a single-threaded executor. Buffers are passed by value
+//! as the returned futures cannot borrow from the caller.
+
+use futures::prelude::*;
+
+/// Boxed future returned by non-blocking device operations
+pub type AsyncResult<T, E> = Box<Future<Item=T, Error=E> + Send>;
+
+pub mod spi {
+    use super::AsyncResult;
+
+    pub trait Transfer {
+        type Error;
+
+        /// Write the provided data, resolving to the data read
+        fn transfer(&mut self, data: Vec<u8>) -> AsyncResult<Vec<u8>, Self::Error>;
+    }
+
+    pub trait Write {
+        type Error;
+
+        fn write(&mut self, data: Vec<u8>) -> AsyncResult<(), Self::Error>;
+    }
+}
+
+pub mod i2c {
+    use super::AsyncResult;
+
+    pub trait Read {
+        type Error;
+
+        /// Read `len` bytes from the device at `addr`
+        fn read(&mut self, addr: u8, len: usize) -> AsyncResult<Vec<u8>, Self::Error>;
+    }
+
+    pub trait Write {
+        type Error;
+
+        fn write(&mut self, addr: u8, data: Vec<u8>) -> AsyncResult<(), Self::Error>;
+    }
+
+    pub trait WriteRead {
+        type Error;
+
+        /// Write the provided data then read `len` bytes from the device at `addr`
+        fn write_read(&mut self, addr: u8, data: Vec<u8>, len: usize) -> AsyncResult<Vec<u8>, Self::Error>;
+    }
+}
+
+pub mod digital {
+    use super::AsyncResult;
+
+    pub trait InputPin {
+        type Error;
+
+        fn is_high(&self) -> AsyncResult<bool, Self::Error>;
+        fn is_low(&self) -> AsyncResult<bool, Self::Error>;
+    }
+
+    pub trait OutputPin {
+        type Error;
+
+        fn set_high(&mut self) -> AsyncResult<(), Self::Error>;
+        fn set_low(&mut self) -> AsyncResult<(), Self::Error>;
+    }
+}
@@ -1,5 +1,6 @@
 
 use std::net::ToSocketAddrs;
+use std::time::Duration;
 
 use structopt::StructOpt;
 
@@ -12,8 +13,12 @@ extern crate simplelog;
 use simplelog::{TermLogger, LevelFilter};
 
 extern crate remote_hal;
-use remote_hal::remote::Client;
-use remote_hal::common::RequestKind;
+use remote_hal::remote::{Client, ClientOptions};
+use remote_hal::codec::Codec;
+use remote_hal::transport::ClientTls;
+use remote_hal::manager::Manager;
+use remote_hal::common::{RequestKind, ResponseKind, DeviceKind, I2cScan, CanFrame};
+use remote_hal::common::{SpiConnect, SpiMode, PinMode, PinOptions, SerialConnect, DataBits, Parity, StopBits, FlowControl};
 
 
 #[derive(StructOpt)]
@@ -23,6 +28,62 @@ pub struct Options {
     /// Specify the hostname of the remote-hal server
     hostname: String,
 
+    #[structopt(long = "codec", default_value = "json")]
+    /// Wire codec for communication with the server (json, cbor)
+    codec: Codec,
+
+    #[structopt(long = "tls-ca")]
+    /// CA certificate used to authenticate the server (PEM), enables TLS
+    tls_ca: Option<String>,
+
+    #[structopt(long = "tls-cert")]
+    /// Client certificate (PEM) for servers requiring client authentication
+    tls_cert: Option<String>,
+
+    #[structopt(long = "tls-key")]
+    /// Client private key (PEM)
+    tls_key: Option<String>,
+
+    #[structopt(long = "tls-domain")]
+    /// Server name to verify against the server certificate (defaults to the server hostname)
+    tls_domain: Option<String>,
+
+    #[structopt(long = "timeout", default_value = "3000")]
+    /// Connect and request timeout in milliseconds
+    timeout_ms: u64,
+
+    #[structopt(long = "spi-baud", default_value = "1000000")]
+    /// SPI baud rate used when connecting SPI devices for commands
+    spi_baud: u32,
+
+    #[structopt(long = "spi-mode", default_value = "0")]
+    /// SPI mode (0, 1, 2 or 3) used when connecting SPI devices for commands
+    spi_mode: SpiMode,
+
+    #[structopt(long = "pin-output")]
+    /// Connect pins as outputs for commands (pins are connected as inputs except for `pin-set`)
+    pin_output: bool,
+
+    #[structopt(long = "serial-baud", default_value = "115200")]
+    /// Serial baud rate used when connecting serial ports for commands
+    serial_baud: u32,
+
+    #[structopt(long = "serial-data-bits", default_value = "8")]
+    /// Serial data bits (5, 6, 7 or 8)
+    serial_data_bits: DataBits,
+
+    #[structopt(long = "serial-parity", default_value = "none")]
+    /// Serial parity (none, odd or even)
+    serial_parity: Parity,
+
+    #[structopt(long = "serial-stop-bits", default_value = "1")]
+    /// Serial stop bits (1 or 2)
+    serial_stop_bits: StopBits,
+
+    #[structopt(long = "serial-flow-control", default_value = "none")]
+    /// Serial flow control (none, software or hardware)
+    serial_flow_control: FlowControl,
+
     /// Remote device for target subcommand
     device: String,
 
@@ -45,9 +106,30 @@ fn main() {
     let mut addrs = opts.hostname.to_socket_addrs().expect("could not parse socket addresses");
     let addr = addrs.next().expect("no socket address found");
 
+    let tls = opts.tls_ca.clone().map(|ca| {
+        let domain = opts.tls_domain.clone().unwrap_or(opts.hostname.rsplitn(2, ':').last().unwrap().to_owned());
+        ClientTls{ca, cert: opts.tls_cert.clone(), key: opts.tls_key.clone(), domain}
+    });
+
+    let timeout = Duration::from_millis(opts.timeout_ms);
+    let options = ClientOptions{codec: opts.codec, tls, connect_timeout: timeout, request_timeout: timeout, ..ClientOptions::default()};
     let command = opts.command;
     let device = opts.device;
 
+    // Configurations used to connect devices before sending commands
+    let spi = SpiConnect{baud: opts.spi_baud, mode: Some(opts.spi_mode)};
+    let pin = match (&command, opts.pin_output) {
+        (RequestKind::PinSet(_), _) | (_, true) => PinMode::Output(PinOptions::default()),
+        _ => PinMode::Input(PinOptions::default()),
+    };
+    let serial = SerialConnect{
+        baud: opts.serial_baud,
+        data_bits: opts.serial_data_bits,
+        parity: opts.serial_parity,
+        stop_bits: opts.serial_stop_bits,
+        flow_control: opts.serial_flow_control,
+    };
+
     info!("connecting to remote-hal server: {:?}", &addr);
     debug!("device: {:?}", device);
     debug!("command: {:?}", command);
@@ -55,21 +137,120 @@ fn main() {
     let mut rt = Runtime::new().unwrap();
 
     // Create client
-    let handle = Client::new(addr)
+    let handle = Client::new(addr, options)
     .map_err(|e| {
-        error!("error connecting to remote-hal server: {:?}", e);
+        error!("error connecting to remote-hal server: {}", e);
         std::process::exit(-1);
     })
     .and_then(move |mut c| {
-        info!("connected, sending request: {:?}", command);
-        c.request(&device, command)
+        // CAN dumps stream frames pushed by the server until interrupted
+        if let RequestKind::CanSubscribe(f) = &command {
+            let filters = f.filters.clone();
+            return future::Either::A(c.can(&device).and_then(move |mut can| {
+                can.frames(filters).and_then(move |frames| {
+                    frames.for_each(move |f| {
+                        print_frame(&device, &f);
+                        Ok(())
+                    })
+                }).then(move |r| {
+                    // Hold the interface until the stream ends
+                    drop(can);
+                    r
+                })
+            }));
+        }
+
+        // Devices are released when the connection closes, so commands need
+        // the device (and any SPI chip select pin) to be connected first
+        let mut connect = vec![];
+
+        match &command {
+            RequestKind::SpiConnect(_) | RequestKind::SpiDisconnect => (),
+            RequestKind::PinConnect(_) | RequestKind::PinDisconnect => (),
+            RequestKind::I2cConnect | RequestKind::I2cDisconnect => (),
+            RequestKind::SerialConnect(_) | RequestKind::SerialDisconnect => (),
+            RequestKind::PwmConnect | RequestKind::PwmDisconnect => (),
+            RequestKind::AdcConnect | RequestKind::AdcDisconnect => (),
+            RequestKind::CanConnect | RequestKind::CanDisconnect => (),
+            r => {
+                if let RequestKind::SpiTransaction(t) = r {
+                    if let Some(cs) = &t.cs {
+                        connect.push(c.request(cs, RequestKind::PinConnect(PinMode::Output(PinOptions::default()))));
+                    }
+                }
+
+                match r.device_kind() {
+                    Some(DeviceKind::Spi) => connect.push(c.request(&device, RequestKind::SpiConnect(spi))),
+                    Some(DeviceKind::Pin) => connect.push(c.request(&device, RequestKind::PinConnect(pin))),
+                    Some(DeviceKind::I2c) => connect.push(c.request(&device, RequestKind::I2cConnect)),
+                    Some(DeviceKind::Serial) => connect.push(c.request(&device, RequestKind::SerialConnect(serial))),
+                    Some(DeviceKind::Pwm) => connect.push(c.request(&device, RequestKind::PwmConnect)),
+                    Some(DeviceKind::Adc) => connect.push(c.request(&device, RequestKind::AdcConnect)),
+                    Some(DeviceKind::Can) => connect.push(c.request(&device, RequestKind::CanConnect)),
+                    _ => (),
+                }
+            },
+        };
+
+        let connect = future::join_all(connect);
+
+        future::Either::B(connect.and_then(move |_| {
+            info!("connected, sending request: {:?}", command);
+            let scan = match &command {
+                RequestKind::I2cScan(s) => Some(s.clone()),
+                _ => None,
+            };
+            c.request(&device, command).map(|resp| {
+                match (scan, resp) {
+                    (Some(s), ResponseKind::I2cScan(found)) => print_scan(&s, &found),
+                    (_, resp) => println!("resp: {:#?}", resp),
+                }
+            })
+        }))
     }).map_err(|e| {
-        error!("error sending command to remote-hal server: {:?}", e);
+        error!("error sending command to remote-hal server: {}", e);
         std::process::exit(-2);
-    }).map(|resp| {
-        println!("resp: {:#?}", resp);
     });
 
-    rt.block_on(handle.map(|_| () ).map_err(|e| panic!(e) )).unwrap();
+    rt.block_on(handle.map(|_| () ).map_err(|e| std::panic::panic_any(e) )).unwrap();
 }
 
+
+/// Print I2C scan results in the same grid format as `i2cdetect`
+fn print_scan(scan: &I2cScan, found: &[u8]) {
+    print!("    ");
+    for c in 0..16 {
+        print!("  {:x}", c);
+    }
+    println!();
+
+    for row in (0..0x80u8).step_by(16) {
+        print!("{:02x}:", row);
+        for addr in row..row + 16 {
+            if addr < scan.first || addr > scan.last {
+                print!("   ");
+            } else if found.contains(&addr) {
+                print!(" {:02x}", addr);
+            } else {
+                print!(" --");
+            }
+        }
+        println!();
+    }
+}
+
+/// Print a received CAN frame in the same format as `candump`
+fn print_frame(iface: &str, frame: &CanFrame) {
+    let id = if frame.extended || frame.id > 0x7ff {
+        format!("{:08X}", frame.id)
+    } else {
+        format!("{:03X}", frame.id)
+    };
+
+    if frame.remote {
+        println!("  {}  {}   [{}]  remote request", iface, id, frame.data.len());
+    } else {
+        let data: Vec<_> = frame.data.iter().map(|b| format!("{:02X}", b) ).collect();
+        println!("  {}  {}   [{}]  {}", iface, id, frame.data.len(), data.join(" "));
+    }
+}
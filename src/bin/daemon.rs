@@ -1,5 +1,6 @@
 
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use structopt::StructOpt;
 
@@ -12,7 +13,11 @@ extern crate simplelog;
 use simplelog::{TermLogger, LevelFilter};
 
 extern crate remote_hal;
-use remote_hal::server::Server;
+use remote_hal::server::{Server, ServerOptions, Config};
+use remote_hal::codec::Codec;
+use remote_hal::transport::ServerTls;
+use remote_hal::sim;
+use remote_hal::common::Heartbeat;
 
 #[derive(StructOpt)]
 #[structopt(name = "Remote HAL CLI", about = "A Command Line Interface (CLI) for interacting with a remote-hal server")]
@@ -21,6 +26,54 @@ pub struct Options {
     /// Specify the bind address of the remote-hal server
     bind_addr: SocketAddr,
 
+    #[structopt(long = "codecs", default_value = "json,cbor", raw(use_delimiter = "true"))]
+    /// Wire codecs accepted from clients (json, cbor)
+    codecs: Vec<Codec>,
+
+    #[structopt(long = "max-transfer", default_value = "65536")]
+    /// Maximum data length for a single transfer in bytes
+    max_transfer: u32,
+
+    #[structopt(long = "max-delay-ms", default_value = "1000")]
+    /// Maximum time a single request (ie. a sequence) may delay the server in milliseconds
+    max_delay_ms: u64,
+
+    #[structopt(long = "lease")]
+    /// Idle lease in seconds, connections receiving no requests for this period are closed and their devices released
+    lease: Option<u64>,
+
+    #[structopt(long = "heartbeat-ms")]
+    /// Heartbeat interval required from clients in milliseconds, connections missing heartbeats are closed and their devices released
+    heartbeat_ms: Option<u64>,
+
+    #[structopt(long = "heartbeat-misses", default_value = "3")]
+    /// Number of missed heartbeats before a client is considered dead
+    heartbeat_misses: u32,
+
+    #[structopt(long = "config")]
+    /// Device configuration file, if provided only the configured devices are exposed
+    config: Option<String>,
+
+    #[structopt(long = "tls-cert")]
+    /// Server certificate (PEM), enables TLS when provided with --tls-key
+    tls_cert: Option<String>,
+
+    #[structopt(long = "tls-key")]
+    /// Server private key (PEM)
+    tls_key: Option<String>,
+
+    #[structopt(long = "tls-client-ca")]
+    /// CA certificate for client authentication (PEM), clients must present a certificate signed by this CA
+    tls_client_ca: Option<String>,
+
+    #[structopt(long = "sim")]
+    /// Serve simulated devices instead of local hardware
+    sim: bool,
+
+    #[structopt(long = "sim-config")]
+    /// Simulated device configuration file, implies --sim
+    sim_config: Option<String>,
+
     #[structopt(long = "log-level", default_value = "info")]
     /// Enable verbose logging
     level: LevelFilter,
@@ -33,12 +86,51 @@ fn main() {
     // Setup logging
     TermLogger::init(opts.level, simplelog::Config::default()).unwrap();
 
+    // Load device configuration
+    let config = opts.config.as_ref().map(|c| {
+        Config::load(c).expect("error loading configuration file")
+    });
+
+    // Setup TLS
+    let tls = match (opts.tls_cert.clone(), opts.tls_key.clone()) {
+        (Some(cert), Some(key)) => Some(ServerTls{cert, key, client_ca: opts.tls_client_ca.clone()}),
+        (None, None) => None,
+        _ => {
+            error!("--tls-cert and --tls-key must be provided together");
+            std::process::exit(-1);
+        }
+    };
+
+    // Load simulated devices
+    let sim = match (&opts.sim_config, opts.sim) {
+        (Some(c), _) => Some(sim::Config::load(c).expect("error loading simulation configuration file")),
+        (None, true) => Some(sim::Config::default()),
+        (None, false) => None,
+    };
+
     let mut rt = Runtime::new().unwrap();
 
     let handle = futures::lazy(move || {
         info!("starting remote-hal server (bound to: {})", opts.bind_addr);
 
-        let _server = Server::new(opts.bind_addr);
+        let misses = opts.heartbeat_misses;
+        let options = ServerOptions{
+            codecs: opts.codecs,
+            max_transfer: opts.max_transfer,
+            lease: opts.lease.map(Duration::from_secs),
+            config,
+            tls,
+            max_delay: Duration::from_millis(opts.max_delay_ms),
+            heartbeat: opts.heartbeat_ms.map(|ms| Heartbeat{interval: Duration::from_millis(ms), misses}),
+        };
+
+        let _server = match sim {
+            Some(c) => {
+                info!("using simulated devices");
+                Server::with_backend(opts.bind_addr, options, Box::new(sim::Client::from_config(&c)))
+            },
+            None => Server::new(opts.bind_addr, options),
+        }.expect("error starting server");
 
         info!("remote-hal server running!");
 
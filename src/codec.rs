@@ -0,0 +1,273 @@
+
+use std::io;
+use std::marker::PhantomData;
+
+use bytes::{BytesMut, BufMut};
+use tokio::codec::{Encoder, Decoder};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Error as JsonError};
+use serde_cbor::error::{Error as CborError};
+use simple_error::SimpleError;
+
+use daemon_engine::codecs::json::JsonCodec;
+
+/// Marker byte prefixing binary frames, this can never start a JSON message
+const BINARY_MAGIC: u8 = 0xcb;
+
+/// Binary frame header length (magic + u32 length)
+const BINARY_HEADER_LEN: usize = 5;
+
+/// Allowance for message envelopes (ids, device names, options) on top of transfer data
+const FRAME_OVERHEAD: usize = 64 * 1024;
+
+/// Default maximum frame length, used where no transfer limit is known
+pub const DEFAULT_MAX_FRAME: usize = 16 * 1024 * 1024;
+
+/// Compute the maximum frame length for a given transfer limit, allowing for
+/// data encoded as JSON arrays (up to four bytes per data byte)
+pub fn max_frame(max_transfer: u32) -> usize {
+    max_transfer as usize * 4 + FRAME_OVERHEAD
+}
+
+/// Wire encodings supported by remote-hal
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Codec {
+    /// JSON messages (the default, compatible with all clients and servers)
+    Json,
+    /// Length prefixed CBOR messages
+    Cbor,
+}
+
+impl std::str::FromStr for Codec {
+    type Err = SimpleError;
+
+    fn from_str(codec: &str) -> Result<Self, Self::Err> {
+        match codec {
+            "json" => Ok(Codec::Json),
+            "cbor" => Ok(Codec::Cbor),
+            _ => Err(SimpleError::new("invalid codec")),
+        }
+    }
+}
+
+impl std::fmt::Display for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Codec::Json => write!(f, "json"),
+            Codec::Cbor => write!(f, "cbor"),
+        }
+    }
+}
+
+/// Wire codec supporting both JSON and binary framing
+///
+/// Binary frames are prefixed with a marker byte, allowing servers to detect
+/// the encoding used by a client from the first message received. Responses
+/// are then sent using the same encoding for the life of the connection.
+pub struct WireCodec<ENC, DEC, ERR> {
+    accept: Vec<Codec>,
+    format: Option<Codec>,
+    max_frame: usize,
+    json: JsonCodec<ENC, DEC, ERR>,
+    _enc: PhantomData<ENC>,
+    _dec: PhantomData<DEC>,
+}
+
+impl<ENC, DEC, ERR> WireCodec<ENC, DEC, ERR>
+where
+    DEC: DeserializeOwned + Clone + Send + 'static,
+    ENC: Serialize + Clone + Send + 'static,
+    ERR: From<io::Error> + From<JsonError> + 'static,
+{
+    /// Create a server side codec, detecting the encoding from the accepted list on the first message
+    pub fn server(accept: &[Codec]) -> Self {
+        WireCodec{accept: accept.to_vec(), format: None, max_frame: DEFAULT_MAX_FRAME, json: JsonCodec::new(), _enc: PhantomData, _dec: PhantomData}
+    }
+
+    /// Create a client side codec using a fixed encoding
+    pub fn client(codec: Codec) -> Self {
+        WireCodec{accept: vec![codec], format: Some(codec), max_frame: DEFAULT_MAX_FRAME, json: JsonCodec::new(), _enc: PhantomData, _dec: PhantomData}
+    }
+
+    /// Set the maximum accepted frame length, larger frames fail to decode
+    pub fn max_frame(mut self, len: usize) -> Self {
+        self.max_frame = len;
+        self
+    }
+
+    /// Fetch the encoding in use (if known)
+    pub fn format(&self) -> Option<Codec> {
+        self.format
+    }
+}
+
+impl<ENC, DEC, ERR> Clone for WireCodec<ENC, DEC, ERR>
+where
+    DEC: DeserializeOwned + Clone + Send + 'static,
+    ENC: Serialize + Clone + Send + 'static,
+    ERR: From<io::Error> + From<JsonError> + 'static,
+{
+    fn clone(&self) -> Self {
+        WireCodec{accept: self.accept.clone(), format: self.format, max_frame: self.max_frame, json: JsonCodec::new(), _enc: PhantomData, _dec: PhantomData}
+    }
+}
+
+impl<ENC, DEC, ERR> Decoder for WireCodec<ENC, DEC, ERR>
+where
+    DEC: DeserializeOwned,
+    ERR: From<io::Error> + From<JsonError> + From<CborError>,
+    JsonCodec<ENC, DEC, ERR>: Decoder<Item=DEC, Error=ERR>,
+{
+    type Item = DEC;
+    type Error = ERR;
+
+    fn decode(&mut self, buff: &mut BytesMut) -> Result<Option<DEC>, ERR> {
+        let format = match self.format {
+            Some(f) => f,
+            None => {
+                // Detect the encoding from the first non-whitespace byte
+                let b = match buff.iter().find(|b| !b.is_ascii_whitespace()) {
+                    Some(b) => *b,
+                    None => return Ok(None),
+                };
+
+                let f = if b == BINARY_MAGIC { Codec::Cbor } else { Codec::Json };
+                if !self.accept.contains(&f) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported codec: {:?}", f)).into());
+                }
+
+                debug!("detected codec: {:?}", f);
+                self.format = Some(f);
+                f
+            }
+        };
+
+        match format {
+            Codec::Json => {
+                let r = self.json.decode(buff)?;
+
+                // Incomplete messages must not grow without bound
+                if r.is_none() && buff.len() > self.max_frame {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large").into());
+                }
+
+                Ok(r)
+            },
+            Codec::Cbor => {
+                if buff.len() < BINARY_HEADER_LEN {
+                    return Ok(None);
+                }
+
+                if buff[0] != BINARY_MAGIC {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid binary frame marker").into());
+                }
+
+                let mut len = [0u8; 4];
+                len.copy_from_slice(&buff[1..BINARY_HEADER_LEN]);
+                let len = u32::from_be_bytes(len) as usize;
+
+                // Check the peer-specified length before allocating for it
+                if len > self.max_frame {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame too large ({} bytes)", len)).into());
+                }
+
+                if buff.len() < BINARY_HEADER_LEN + len {
+                    buff.reserve(BINARY_HEADER_LEN + len - buff.len());
+                    return Ok(None);
+                }
+
+                let _header = buff.split_to(BINARY_HEADER_LEN);
+                let data = buff.split_to(len);
+
+                Ok(Some(serde_cbor::from_slice(&data)?))
+            },
+        }
+    }
+}
+
+impl<ENC, DEC, ERR> Encoder for WireCodec<ENC, DEC, ERR>
+where
+    ENC: Serialize,
+    ERR: From<io::Error> + From<JsonError> + From<CborError>,
+    JsonCodec<ENC, DEC, ERR>: Encoder<Item=ENC, Error=ERR>,
+{
+    type Item = ENC;
+    type Error = ERR;
+
+    fn encode(&mut self, item: ENC, buff: &mut BytesMut) -> Result<(), ERR> {
+        match self.format.unwrap_or(Codec::Json) {
+            Codec::Json => self.json.encode(item, buff),
+            Codec::Cbor => {
+                let data = serde_cbor::to_vec(&item)?;
+
+                buff.reserve(BINARY_HEADER_LEN + data.len());
+                buff.put_u8(BINARY_MAGIC);
+                buff.put_u32_be(data.len() as u32);
+                buff.put_slice(&data);
+
+                Ok(())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{Request, RequestKind, Response};
+    use crate::error::Error;
+
+    type ServerCodec = WireCodec<Response, Request, Error>;
+
+    fn frame(codec: Codec) -> BytesMut {
+        let mut buff = BytesMut::new();
+        let mut c = WireCodec::<Request, Response, Error>::client(codec);
+        c.encode(Request{id: 1, device: "spidev0.0".to_owned(), kind: RequestKind::Ping}, &mut buff).unwrap();
+        buff
+    }
+
+    #[test]
+    fn oversized_frames() {
+        let mut c = ServerCodec::server(&[Codec::Cbor]).max_frame(16);
+
+        let mut buff = BytesMut::new();
+        buff.put_u8(BINARY_MAGIC);
+        buff.put_u32_be(u32::MAX);
+        assert!(c.decode(&mut buff).is_err());
+
+        let mut c = ServerCodec::server(&[Codec::Json]).max_frame(16);
+        let mut buff = BytesMut::from(&b"{\"id\": 1, \"device\": \"spidev0.0\""[..]);
+        assert!(c.decode(&mut buff).is_err());
+    }
+
+    #[test]
+    fn truncated_frames() {
+        for codec in &[Codec::Json, Codec::Cbor] {
+            let mut c = ServerCodec::server(&[Codec::Json, Codec::Cbor]);
+            let mut f = frame(*codec);
+            let rest = f.split_off(f.len() / 2);
+
+            assert!(c.decode(&mut f).unwrap().is_none(), "{:?} partial frame decoded", codec);
+
+            f.extend_from_slice(&rest);
+            let req = c.decode(&mut f).unwrap().expect("frame not decoded");
+            assert_eq!(req.id, 1);
+            assert_eq!(c.format(), Some(*codec));
+        }
+    }
+
+    #[test]
+    fn mixed_frames() {
+        // Encodings are fixed by the first frame received
+        let mut c = ServerCodec::server(&[Codec::Json, Codec::Cbor]);
+        let mut buff = frame(Codec::Cbor);
+        buff.extend_from_slice(&frame(Codec::Json));
+
+        assert!(c.decode(&mut buff).unwrap().is_some());
+        assert!(c.decode(&mut buff).is_err());
+
+        // Encodings not accepted by the server are rejected
+        let mut c = ServerCodec::server(&[Codec::Json]);
+        assert!(c.decode(&mut frame(Codec::Cbor)).is_err());
+    }
+}
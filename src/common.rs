@@ -1,10 +1,28 @@
 
+use std::time::Duration;
+
 use rand::random;
 use structopt::StructOpt;
 use hex;
 
 use simple_error::SimpleError;
 
+use crate::codec::Codec;
+
+// Defines the protocol version along with the string form used for CLI defaults
+macro_rules! protocol_version {
+    ($v:tt) => {
+        /// remote-hal wire protocol version, incremented on incompatible changes
+        pub const PROTOCOL_VERSION: u32 = $v;
+        const PROTOCOL_VERSION_STR: &str = stringify!($v);
+    };
+}
+
+protocol_version!(3);
+
+/// remote-hal crate version
+pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Request {
     pub id: u64,
@@ -24,6 +42,10 @@ pub enum RequestKind {
     /// Send a ping message to the remote server
     Ping,
 
+    #[structopt(name = "hello")]
+    /// Exchange protocol versions and fetch server capabilities
+    Hello(Hello),
+
     #[structopt(name = "spi-connect")]
     /// Connect to the specified SPI device
     SpiConnect(SpiConnect),
@@ -41,6 +63,9 @@ pub enum RequestKind {
         /// Data to be written in hexidecimal (ie. `0x112233` or `[00, 12, 01 a1]`)
         write_data: Data
     },
+    #[structopt(name = "spi-transaction")]
+    /// Execute a sequence of operations using a connected SPI device, with chip select held for the duration
+    SpiTransaction(SpiTransaction),
     #[structopt(name = "spi-disconnect")]
     /// Disconnect a connected SPI device
     SpiDisconnect,
@@ -55,6 +80,12 @@ pub enum RequestKind {
     #[structopt(name = "pin-get")]
     /// Fetch the value of the specified pin
     PinGet,
+    #[structopt(name = "pin-subscribe")]
+    /// Subscribe to edge events on the specified pin
+    PinSubscribe(Edge),
+    #[structopt(name = "pin-unsubscribe")]
+    /// Cancel an edge event subscription on the specified pin
+    PinUnsubscribe,
     #[structopt(name = "pin-disconnect")]
     /// Disconnect a connected pin
     PinDisconnect,
@@ -71,33 +102,244 @@ pub enum RequestKind {
     #[structopt(name = "i2c-write-read")]
     /// Write then read data from the provided address using a connected I2C device
     I2cWriteRead(I2cWriteRead),
+    #[structopt(name = "i2c-transaction")]
+    /// Execute a sequence of reads and writes to the provided address (with repeated starts) using a connected I2C device
+    I2cTransaction(I2cTransaction),
+    #[structopt(name = "i2c-scan")]
+    /// Probe a connected I2C bus for responding device addresses
+    I2cScan(I2cScan),
+
+    #[structopt(name = "smbus-read-byte")]
+    /// Read a byte from the provided command register using a connected I2C (SMBus) device
+    SmbusReadByte(SmbusRead),
+    #[structopt(name = "smbus-write-byte")]
+    /// Write a byte to the provided command register using a connected I2C (SMBus) device
+    SmbusWriteByte(SmbusWriteByte),
+    #[structopt(name = "smbus-read-word")]
+    /// Read a word from the provided command register using a connected I2C (SMBus) device
+    SmbusReadWord(SmbusRead),
+    #[structopt(name = "smbus-write-word")]
+    /// Write a word to the provided command register using a connected I2C (SMBus) device
+    SmbusWriteWord(SmbusWriteWord),
+    #[structopt(name = "smbus-read-block")]
+    /// Read a block (up to 32 bytes) from the provided command register using a connected I2C (SMBus) device
+    SmbusReadBlock(SmbusRead),
+    #[structopt(name = "smbus-write-block")]
+    /// Write a block (up to 32 bytes) to the provided command register using a connected I2C (SMBus) device
+    SmbusWriteBlock(SmbusWriteBlock),
+    #[structopt(name = "smbus-process-call")]
+    /// Write a word to then read a word from the provided command register using a connected I2C (SMBus) device
+    SmbusProcessCall(SmbusWriteWord),
     #[structopt(name = "i2c-disconnect")]
     /// Disconnect a connected I2C device
     I2cDisconnect,
+
+    #[structopt(name = "serial-connect")]
+    /// Connect to the specified serial port
+    SerialConnect(SerialConnect),
+    #[structopt(name = "serial-write")]
+    /// Write data using a connected serial port
+    SerialWrite{
+        #[structopt(parse(try_from_str))]
+        /// Data to be written in hexidecimal (ie. `0x112233` or `[00, 12, 01 a1]`)
+        write_data: Data
+    },
+    #[structopt(name = "serial-read")]
+    /// Read available data from a connected serial port
+    SerialRead(SerialRead),
+    #[structopt(name = "serial-flush")]
+    /// Flush pending output on a connected serial port
+    SerialFlush,
+    #[structopt(name = "serial-disconnect")]
+    /// Disconnect a connected serial port
+    SerialDisconnect,
+
+    #[structopt(name = "pwm-connect")]
+    /// Connect to the specified PWM channel (ie. `pwmchip0:1`)
+    PwmConnect,
+    #[structopt(name = "pwm-set-period")]
+    /// Set the period of a connected PWM channel
+    PwmSetPeriod(PwmPeriod),
+    #[structopt(name = "pwm-set-duty")]
+    /// Set the duty cycle (active time) of a connected PWM channel
+    PwmSetDuty(PwmDuty),
+    #[structopt(name = "pwm-set-polarity")]
+    /// Set the output polarity of a connected PWM channel
+    PwmSetPolarity(Polarity),
+    #[structopt(name = "pwm-enable")]
+    /// Enable output on a connected PWM channel
+    PwmEnable,
+    #[structopt(name = "pwm-disable")]
+    /// Disable output on a connected PWM channel
+    PwmDisable,
+    #[structopt(name = "pwm-get")]
+    /// Fetch the state of a connected PWM channel
+    PwmGet,
+    #[structopt(name = "pwm-disconnect")]
+    /// Disconnect a connected PWM channel
+    PwmDisconnect,
+
+    #[structopt(name = "adc-connect")]
+    /// Connect to the specified IIO ADC device (ie. `iio:device0`)
+    AdcConnect,
+    #[structopt(name = "adc-read")]
+    /// Read a single (raw and scaled) value from a channel of a connected ADC
+    AdcRead(AdcRead),
+    #[structopt(name = "adc-capture")]
+    /// Capture a number of samples from channels of a connected ADC using the IIO buffer
+    AdcCapture(AdcCapture),
+    #[structopt(name = "adc-disconnect")]
+    /// Disconnect a connected ADC
+    AdcDisconnect,
+
+    #[structopt(name = "can-connect")]
+    /// Connect to the specified CAN interface (ie. `can0`)
+    CanConnect,
+    #[structopt(name = "can-send")]
+    /// Send a frame using a connected CAN interface
+    CanSend(CanSend),
+    #[structopt(name = "can-set-filters")]
+    /// Set receive filters for a connected CAN interface
+    CanSetFilters(CanFilters),
+    #[structopt(name = "can-receive")]
+    /// Receive a frame from a connected CAN interface
+    CanReceive(CanReceive),
+    #[structopt(name = "can-dump")]
+    /// Subscribe to frames received (and matching the provided filters) on a connected CAN interface
+    CanSubscribe(CanFilters),
+    #[structopt(name = "can-unsubscribe")]
+    /// Cancel a frame subscription on a connected CAN interface
+    CanUnsubscribe,
+    #[structopt(name = "can-disconnect")]
+    /// Disconnect a connected CAN interface
+    CanDisconnect,
+
+    #[structopt(name = "delay")]
+    /// Delay on the server for the specified time
+    Delay(Delay),
+    #[structopt(name = "sequence")]
+    /// Execute a sequence of requests and delays on the server, stopping at the first failure
+    Sequence(Sequence),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
 pub enum PinMode {
     #[structopt(name = "output")]
     /// Configure pin in output mode
-    Output,
+    Output(PinOptions),
     #[structopt(name = "input")]
     /// Configure pin in input mode
-    Input,
+    Input(PinOptions),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+impl PinMode {
+    /// Output mode with default options
+    pub fn output() -> Self {
+        PinMode::Output(PinOptions::default())
+    }
+
+    /// Input mode with default options
+    pub fn input() -> Self {
+        PinMode::Input(PinOptions::default())
+    }
+
+    /// Fetch the options for the pin mode
+    pub fn options(&self) -> &PinOptions {
+        match self {
+            PinMode::Output(o) => o,
+            PinMode::Input(o) => o,
+        }
+    }
+}
+
+/// Pin line options, bias, open-drain and labels are only supported by
+/// the GPIO character device backend
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, StructOpt)]
+pub struct PinOptions {
+    #[structopt(long = "label")]
+    /// Consumer label for the GPIO line
+    #[serde(default)]
+    pub label: Option<String>,
+
+    #[structopt(long = "bias", default_value = "as-is")]
+    /// Line bias (as-is, disable, pull-up or pull-down)
+    #[serde(default)]
+    pub bias: Bias,
+
+    #[structopt(long = "active-low")]
+    /// Invert the logical value of the line
+    #[serde(default)]
+    pub active_low: bool,
+
+    #[structopt(long = "open-drain")]
+    /// Drive the line as open-drain (outputs only)
+    #[serde(default)]
+    pub open_drain: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Bias {
+    /// Leave the line bias unchanged
+    #[default]
+    AsIs,
+    /// Disable line bias
+    Disable,
+    PullUp,
+    PullDown,
+}
+
+impl std::str::FromStr for Bias {
+    type Err = SimpleError;
+
+    fn from_str(bias: &str) -> Result<Self, Self::Err> {
+        match bias {
+            "as-is" => Ok(Bias::AsIs),
+            "disable" => Ok(Bias::Disable),
+            "pull-up" => Ok(Bias::PullUp),
+            "pull-down" => Ok(Bias::PullDown),
+            _ => Err(SimpleError::new("invalid pin bias")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, StructOpt)]
+pub enum Edge {
+    #[structopt(name = "rising")]
+    /// Rising edges (low to high transitions)
+    Rising,
+    #[structopt(name = "falling")]
+    /// Falling edges (high to low transitions)
+    Falling,
+    #[structopt(name = "both")]
+    /// Both rising and falling edges
+    Both,
+}
+
+/// Pin edge event, pushed by the server to subscribed clients
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PinEvent {
+    /// Edge detected (`Edge::Rising` or `Edge::Falling`)
+    pub edge: Edge,
+    /// Event timestamp in nanoseconds
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StructOpt)]
 pub enum SpiMode {
     #[structopt(name = "mode-0")]
+    #[serde(alias = "0")]
     /// Configure SPI device in mode 0 (CPOL: 0, CPHA: 0)
     Mode0,
     #[structopt(name = "mode-1")]
+    #[serde(alias = "1")]
     /// Configure SPI device in mode 1 (CPOL: 0, CPHA: 1)
     Mode1,
     #[structopt(name = "mode-2")]
+    #[serde(alias = "2")]
     /// Configure SPI device in mode 2 (CPOL: 1, CPHA: 0)
     Mode2,
     #[structopt(name = "mode-3")]
+    #[serde(alias = "3")]
     /// Configure SPI device in mode 3 (CPOL: 0, CPHA: 0)
     Mode3,
 }
@@ -116,18 +358,23 @@ impl std::str::FromStr for SpiMode {
     }
 }
 
-impl std::string::ToString for SpiMode {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for SpiMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            SpiMode::Mode0 => format!("0"),
-            SpiMode::Mode1 => format!("1"),
-            SpiMode::Mode2 => format!("2"),
-            SpiMode::Mode3 => format!("3"),
+            SpiMode::Mode0 => write!(f, "0"),
+            SpiMode::Mode1 => write!(f, "1"),
+            SpiMode::Mode2 => write!(f, "2"),
+            SpiMode::Mode3 => write!(f, "3"),
         }
     }
 }
 
 
+/// Response message
+///
+/// In addition to replying to requests, the server may push unsolicited event
+/// responses (ie. `ResponseKind::PinEvent` or `ResponseKind::CanFrame`), these
+/// carry the `id` of the request that created the subscription.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Response {
     pub id: u64,
@@ -137,14 +384,318 @@ pub struct Response {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ResponseKind {
     Ok,
-    Error(String),
+    Error(DeviceError),
     Unhandled,
     DeviceAlreadyBound,
     DeviceNotBound,
 
     SpiTransfer(Vec<u8>),
+    /// Data read by each operation of a transaction (empty for writes and delays)
+    SpiTransaction(Vec<Vec<u8>>),
     PinGet(bool),
     I2cRead(Vec<u8>),
+    /// Data read by each operation of a transaction (empty for writes)
+    I2cTransaction(Vec<Vec<u8>>),
+    /// Addresses responding to an `I2cScan`
+    I2cScan(Vec<u8>),
+    SmbusByte(u8),
+    SmbusWord(u16),
+    SmbusBlock(Vec<u8>),
+    SerialRead(Vec<u8>),
+    PwmState(PwmState),
+    AdcRead(AdcReading),
+    AdcCapture(Vec<AdcChannelData>),
+    /// Frame received by a `CanReceive`, if one arrived prior to the timeout
+    CanReceive(Option<CanFrame>),
+    /// Responses to each step of a `Sequence`, the last response is the failure
+    /// where the sequence did not complete
+    Sequence(Vec<ResponseKind>),
+
+    CanFrame(CanFrame),
+
+    PinEvent(PinEvent),
+
+    Capabilities(Capabilities),
+
+    /// Heartbeat pushed by servers requiring heartbeats (with id 0), clients
+    /// receiving nothing for the heartbeat period consider the server dead
+    Ping,
+}
+
+/// Structured error reported by the server for a failed request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceError {
+    pub category: ErrorCategory,
+    /// OS error number, where the failure originated from a system call
+    pub errno: Option<i32>,
+    pub message: String,
+}
+
+impl DeviceError {
+    pub fn new(category: ErrorCategory, errno: Option<i32>, message: String) -> Self {
+        Self{category, errno, message}
+    }
+}
+
+impl std::fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.errno {
+            Some(n) => write!(f, "{} ({:?}, errno {})", self.message, self.category, n),
+            None => write!(f, "{} ({:?})", self.message, self.category),
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+/// Error category, allowing drivers to handle failures without parsing messages
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ErrorCategory {
+    /// I2C address or data was not acknowledged
+    NoAcknowledge,
+    /// I2C bus arbitration was lost
+    ArbitrationLoss,
+    /// Other IO failure
+    Io(IoErrorKind),
+    PermissionDenied,
+    NotFound,
+    InvalidArgument,
+    Timeout,
+    Unsupported,
+    Other,
+}
+
+/// Serializable equivalent of `std::io::ErrorKind`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum IoErrorKind {
+    NotFound,
+    PermissionDenied,
+    ConnectionRefused,
+    ConnectionReset,
+    ConnectionAborted,
+    NotConnected,
+    AddrInUse,
+    AddrNotAvailable,
+    BrokenPipe,
+    AlreadyExists,
+    WouldBlock,
+    InvalidInput,
+    InvalidData,
+    TimedOut,
+    WriteZero,
+    Interrupted,
+    UnexpectedEof,
+    Other,
+}
+
+impl From<std::io::ErrorKind> for IoErrorKind {
+    fn from(k: std::io::ErrorKind) -> Self {
+        use std::io::ErrorKind as K;
+
+        match k {
+            K::NotFound => IoErrorKind::NotFound,
+            K::PermissionDenied => IoErrorKind::PermissionDenied,
+            K::ConnectionRefused => IoErrorKind::ConnectionRefused,
+            K::ConnectionReset => IoErrorKind::ConnectionReset,
+            K::ConnectionAborted => IoErrorKind::ConnectionAborted,
+            K::NotConnected => IoErrorKind::NotConnected,
+            K::AddrInUse => IoErrorKind::AddrInUse,
+            K::AddrNotAvailable => IoErrorKind::AddrNotAvailable,
+            K::BrokenPipe => IoErrorKind::BrokenPipe,
+            K::AlreadyExists => IoErrorKind::AlreadyExists,
+            K::WouldBlock => IoErrorKind::WouldBlock,
+            K::InvalidInput => IoErrorKind::InvalidInput,
+            K::InvalidData => IoErrorKind::InvalidData,
+            K::TimedOut => IoErrorKind::TimedOut,
+            K::WriteZero => IoErrorKind::WriteZero,
+            K::Interrupted => IoErrorKind::Interrupted,
+            K::UnexpectedEof => IoErrorKind::UnexpectedEof,
+            _ => IoErrorKind::Other,
+        }
+    }
+}
+
+impl From<IoErrorKind> for std::io::ErrorKind {
+    fn from(k: IoErrorKind) -> Self {
+        use std::io::ErrorKind as K;
+
+        match k {
+            IoErrorKind::NotFound => K::NotFound,
+            IoErrorKind::PermissionDenied => K::PermissionDenied,
+            IoErrorKind::ConnectionRefused => K::ConnectionRefused,
+            IoErrorKind::ConnectionReset => K::ConnectionReset,
+            IoErrorKind::ConnectionAborted => K::ConnectionAborted,
+            IoErrorKind::NotConnected => K::NotConnected,
+            IoErrorKind::AddrInUse => K::AddrInUse,
+            IoErrorKind::AddrNotAvailable => K::AddrNotAvailable,
+            IoErrorKind::BrokenPipe => K::BrokenPipe,
+            IoErrorKind::AlreadyExists => K::AlreadyExists,
+            IoErrorKind::WouldBlock => K::WouldBlock,
+            IoErrorKind::InvalidInput => K::InvalidInput,
+            IoErrorKind::InvalidData => K::InvalidData,
+            IoErrorKind::TimedOut => K::TimedOut,
+            IoErrorKind::WriteZero => K::WriteZero,
+            IoErrorKind::Interrupted => K::Interrupted,
+            IoErrorKind::UnexpectedEof => K::UnexpectedEof,
+            IoErrorKind::Other => K::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct Hello {
+    #[structopt(long = "protocol-version", raw(default_value = "PROTOCOL_VERSION_STR"))]
+    /// Client protocol version
+    pub protocol_version: u32,
+
+    #[structopt(long = "client-version", raw(default_value = "CRATE_VERSION"))]
+    /// Client crate version
+    pub client_version: String,
+}
+
+impl Hello {
+    /// Create a hello message for the current protocol and crate versions
+    pub fn new() -> Self {
+        Self{protocol_version: PROTOCOL_VERSION, client_version: CRATE_VERSION.to_owned()}
+    }
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Server capabilities, returned in response to a `Hello` request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Server protocol version
+    pub protocol_version: u32,
+    /// Server crate version
+    pub server_version: String,
+    /// Device types supported by the server
+    pub devices: Vec<DeviceKind>,
+    /// Wire codecs accepted by the server
+    pub codecs: Vec<Codec>,
+    /// Maximum data length for a single transfer in bytes
+    pub max_transfer: u32,
+    /// Heartbeat interval required by the server in milliseconds, connections
+    /// sending no requests for the server's heartbeat period are closed, and
+    /// the server pushes a `ResponseKind::Ping` to clients at this interval
+    #[serde(default)]
+    pub heartbeat_ms: Option<u32>,
+    /// Maximum delay for a single request in microseconds, longer delays must be split
+    #[serde(default)]
+    pub max_delay_us: Option<u64>,
+}
+
+/// Connection heartbeat configuration, a peer is considered dead after `misses`
+/// heartbeat intervals without a ping (server) or ping response (client)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heartbeat {
+    /// Interval between pings
+    pub interval: Duration,
+    /// Number of missed heartbeats before the peer is considered dead
+    pub misses: u32,
+}
+
+impl Heartbeat {
+    /// Period without heartbeats after which the peer is considered dead
+    pub fn period(&self) -> Duration {
+        self.interval * self.misses
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            misses: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DeviceKind {
+    #[serde(alias = "spi")]
+    Spi,
+    #[serde(alias = "i2c")]
+    I2c,
+    #[serde(alias = "pin")]
+    Pin,
+    #[serde(alias = "serial")]
+    Serial,
+    #[serde(alias = "pwm")]
+    Pwm,
+    #[serde(alias = "adc")]
+    Adc,
+    #[serde(alias = "can")]
+    Can,
+}
+
+impl RequestKind {
+    /// Fetch the type of device targeted by a request, if any
+    pub fn device_kind(&self) -> Option<DeviceKind> {
+        use RequestKind::*;
+
+        match self {
+            Ping | Hello(_) | RequestKind::Delay(_) | RequestKind::Sequence(_) => None,
+            SpiConnect(_) | SpiTransfer{..} | SpiWrite{..} | SpiTransaction(_) | SpiDisconnect => Some(DeviceKind::Spi),
+            PinConnect(_) | PinSet(_) | PinGet | PinSubscribe(_) | PinUnsubscribe | PinDisconnect => Some(DeviceKind::Pin),
+            I2cConnect | I2cWrite(_) | I2cRead(_) | I2cWriteRead(_) | I2cTransaction(_) | I2cScan(_) | I2cDisconnect => Some(DeviceKind::I2c),
+            SmbusReadByte(_) | SmbusWriteByte(_) | SmbusReadWord(_) | SmbusWriteWord(_) |
+            SmbusReadBlock(_) | SmbusWriteBlock(_) | SmbusProcessCall(_) => Some(DeviceKind::I2c),
+            SerialConnect(_) | SerialWrite{..} | SerialRead(_) | SerialFlush | SerialDisconnect => Some(DeviceKind::Serial),
+            PwmConnect | PwmSetPeriod(_) | PwmSetDuty(_) | PwmSetPolarity(_) | PwmEnable | PwmDisable | PwmGet | PwmDisconnect => Some(DeviceKind::Pwm),
+            AdcConnect | AdcRead(_) | AdcCapture(_) | AdcDisconnect => Some(DeviceKind::Adc),
+            CanConnect | CanSend(_) | CanSetFilters(_) | CanReceive(_) | CanSubscribe(_) | CanUnsubscribe | CanDisconnect => Some(DeviceKind::Can),
+        }
+    }
+
+    /// Check whether a request may safely be re-sent, ie. where the first attempt
+    /// timed out but may still have been executed by the server
+    ///
+    /// Bus reads are excluded as these may consume data or clear device state.
+    pub fn is_idempotent(&self) -> bool {
+        use RequestKind::*;
+
+        matches!(self, Ping | Hello(_) | PinGet | PinSet(_) | I2cScan(_) | PwmGet | PwmSetPeriod(_) |
+            PwmSetDuty(_) | PwmSetPolarity(_) | PwmEnable | PwmDisable | AdcRead(_) | CanSetFilters(_))
+    }
+
+    /// Fetch the address, PEC flag and operation for SMBus requests
+    pub fn smbus_op(&self) -> Option<(u8, bool, SmbusOp)> {
+        use RequestKind::*;
+
+        let (addr, pec, op) = match self {
+            SmbusReadByte(c) => (c.addr, c.pec, SmbusOp::ReadByte{command: c.command}),
+            SmbusWriteByte(c) => (c.addr, c.pec, SmbusOp::WriteByte{command: c.command, value: c.value}),
+            SmbusReadWord(c) => (c.addr, c.pec, SmbusOp::ReadWord{command: c.command}),
+            SmbusWriteWord(c) => (c.addr, c.pec, SmbusOp::WriteWord{command: c.command, value: c.value}),
+            SmbusReadBlock(c) => (c.addr, c.pec, SmbusOp::ReadBlock{command: c.command}),
+            SmbusWriteBlock(c) => (c.addr, c.pec, SmbusOp::WriteBlock{command: c.command, data: c.data.data.clone()}),
+            SmbusProcessCall(c) => (c.addr, c.pec, SmbusOp::ProcessCall{command: c.command, value: c.value}),
+            _ => return None,
+        };
+
+        Some((addr, pec, op))
+    }
+}
+
+/// Parse a u8 in decimal or hexadecimal (`0x` prefixed) form
+pub fn parse_u8(s: &str) -> Result<u8, std::num::ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(h) => u8::from_str_radix(h, 16),
+        None => s.parse(),
+    }
+}
+
+/// Parse a u16 in decimal or hexadecimal (`0x` prefixed) form
+pub fn parse_u16(s: &str) -> Result<u16, std::num::ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(h) => u16::from_str_radix(h, 16),
+        None => s.parse(),
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
@@ -157,16 +708,16 @@ impl std::str::FromStr for Data {
     type Err = hex::FromHexError;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        let s = s.replace(|v| v == ':' || v == ' ' || v == ',' || v == '[' || v == ']', "");
+        let s = s.replace([':', ' ', ',', '[', ']'], "");
         let s = s.trim_start_matches("0x");
         let d = hex::decode(s)?;
         Ok(Data{data: d})
     }
 }
 
-impl std::string::ToString for Data {
-    fn to_string(&self) -> String {
-        format!("{:x?}", self.data)
+impl std::fmt::Display for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:x?}", self.data)
     }
 }
 
@@ -180,8 +731,64 @@ pub struct SpiConnect {
     /// SPI baud rate in bps
     pub baud: u32,
     
-    /// SPI mode
-    pub mode: SpiMode,
+    /// SPI mode, the configured device default (or mode 0) is used if unset
+    #[serde(default)]
+    pub mode: Option<SpiMode>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct SpiTransaction {
+    #[structopt(long = "cs")]
+    /// Chip select pin (connected as an output), driven low for the duration of the transaction
+    pub cs: Option<String>,
+
+    #[structopt(parse(try_from_str))]
+    /// Operations to execute in order (ie. `write:0x9f read:3`, `transfer:0x0102`, or `delay:100` in microseconds)
+    pub ops: Vec<SpiOp>,
+}
+
+/// SPI transaction operation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SpiOp {
+    /// Write data, discarding data read
+    Write(Vec<u8>),
+    /// Write data, returning data read
+    Transfer(Vec<u8>),
+    /// Read the specified number of bytes (writing zeros)
+    Read(u16),
+    /// Delay in microseconds
+    DelayUs(u32),
+}
+
+impl std::str::FromStr for SpiOp {
+    type Err = SimpleError;
+
+    fn from_str(op: &str) -> Result<Self, Self::Err> {
+        let mut parts = op.splitn(2, ':');
+        let (kind, value) = match (parts.next(), parts.next()) {
+            (Some(k), Some(v)) => (k, v),
+            _ => return Err(SimpleError::new("invalid spi operation, expected `kind:value`")),
+        };
+
+        match kind {
+            "write" => value.parse::<Data>().map(|d| SpiOp::Write(d.data) ).map_err(SimpleError::from),
+            "transfer" => value.parse::<Data>().map(|d| SpiOp::Transfer(d.data) ).map_err(SimpleError::from),
+            "read" => value.parse().map(SpiOp::Read).map_err(SimpleError::from),
+            "delay" => value.parse().map(SpiOp::DelayUs).map_err(SimpleError::from),
+            _ => Err(SimpleError::new("invalid spi operation (expected write, transfer, read or delay)")),
+        }
+    }
+}
+
+impl SpiOp {
+    /// Fetch the length of data transferred by an operation
+    pub fn data_len(&self) -> usize {
+        match self {
+            SpiOp::Write(d) | SpiOp::Transfer(d) => d.len(),
+            SpiOp::Read(n) => *n as usize,
+            SpiOp::DelayUs(_) => 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
@@ -211,3 +818,667 @@ pub struct I2cWriteRead {
     pub write_data: Data,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct I2cScan {
+    #[structopt(long = "method", default_value = "auto")]
+    /// Probe method (quick, read, or auto to use reads for EEPROM ranges as i2cdetect does)
+    pub method: ProbeMethod,
+    #[structopt(long = "first", default_value = "0x08", parse(try_from_str = "parse_u8"))]
+    /// First address to probe
+    pub first: u8,
+    #[structopt(long = "last", default_value = "0x77", parse(try_from_str = "parse_u8"))]
+    /// Last address to probe
+    pub last: u8,
+}
+
+impl Default for I2cScan {
+    fn default() -> Self {
+        Self{method: ProbeMethod::Auto, first: 0x08, last: 0x77}
+    }
+}
+
+/// I2C address probe method
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ProbeMethod {
+    /// Quick write for most addresses, read byte for 0x30-0x37 and 0x50-0x5f
+    Auto,
+    /// SMBus quick write (may corrupt some write-only devices)
+    Quick,
+    /// Read a byte (may lock up some devices)
+    Read,
+}
+
+impl ProbeMethod {
+    /// Resolve the method used for a given address
+    pub fn for_address(&self, addr: u8) -> ProbeMethod {
+        match self {
+            ProbeMethod::Auto if (0x30..=0x37).contains(&addr) || (0x50..=0x5f).contains(&addr) => ProbeMethod::Read,
+            ProbeMethod::Auto => ProbeMethod::Quick,
+            m => *m,
+        }
+    }
+}
+
+impl std::str::FromStr for ProbeMethod {
+    type Err = SimpleError;
+
+    fn from_str(method: &str) -> Result<Self, Self::Err> {
+        match method {
+            "auto" => Ok(ProbeMethod::Auto),
+            "quick" => Ok(ProbeMethod::Quick),
+            "read" => Ok(ProbeMethod::Read),
+            _ => Err(SimpleError::new("invalid probe method (expected auto, quick or read)")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct SmbusRead {
+    #[structopt(parse(try_from_str = "parse_u8"))]
+    /// I2C device address (ie. `0x48`)
+    pub addr: u8,
+    #[structopt(parse(try_from_str = "parse_u8"))]
+    /// SMBus command (register)
+    pub command: u8,
+    #[structopt(long = "pec")]
+    /// Enable Packet Error Checking
+    pub pec: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct SmbusWriteByte {
+    #[structopt(parse(try_from_str = "parse_u8"))]
+    /// I2C device address (ie. `0x48`)
+    pub addr: u8,
+    #[structopt(parse(try_from_str = "parse_u8"))]
+    /// SMBus command (register)
+    pub command: u8,
+    #[structopt(parse(try_from_str = "parse_u8"))]
+    /// Value to be written
+    pub value: u8,
+    #[structopt(long = "pec")]
+    /// Enable Packet Error Checking
+    pub pec: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct SmbusWriteWord {
+    #[structopt(parse(try_from_str = "parse_u8"))]
+    /// I2C device address (ie. `0x48`)
+    pub addr: u8,
+    #[structopt(parse(try_from_str = "parse_u8"))]
+    /// SMBus command (register)
+    pub command: u8,
+    #[structopt(parse(try_from_str = "parse_u16"))]
+    /// Value to be written
+    pub value: u16,
+    #[structopt(long = "pec")]
+    /// Enable Packet Error Checking
+    pub pec: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct SmbusWriteBlock {
+    #[structopt(parse(try_from_str = "parse_u8"))]
+    /// I2C device address (ie. `0x48`)
+    pub addr: u8,
+    #[structopt(parse(try_from_str = "parse_u8"))]
+    /// SMBus command (register)
+    pub command: u8,
+    #[structopt(parse(try_from_str))]
+    /// Data to be written in hexidecimal (ie. `0x112233` or `[00, 12, 01 a1]`)
+    pub data: Data,
+    #[structopt(long = "pec")]
+    /// Enable Packet Error Checking
+    pub pec: bool,
+}
+
+/// SMBus operation, executed by server backends for SMBus requests
+#[derive(Debug, Clone, PartialEq)]
+pub enum SmbusOp {
+    ReadByte{command: u8},
+    WriteByte{command: u8, value: u8},
+    ReadWord{command: u8},
+    WriteWord{command: u8, value: u16},
+    ReadBlock{command: u8},
+    WriteBlock{command: u8, data: Vec<u8>},
+    ProcessCall{command: u8, value: u16},
+}
+
+/// Maximum SMBus block length
+pub const SMBUS_BLOCK_MAX: usize = 32;
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct I2cTransaction {
+    /// I2C device address
+    pub addr: u8,
+
+    #[structopt(parse(try_from_str))]
+    /// Operations to execute in order (ie. `write:0x05 read:2`)
+    pub ops: Vec<I2cOp>,
+}
+
+/// I2C transaction operation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum I2cOp {
+    /// Write data
+    Write(Vec<u8>),
+    /// Read the specified number of bytes
+    Read(u16),
+}
+
+impl std::str::FromStr for I2cOp {
+    type Err = SimpleError;
+
+    fn from_str(op: &str) -> Result<Self, Self::Err> {
+        let mut parts = op.splitn(2, ':');
+        let (kind, value) = match (parts.next(), parts.next()) {
+            (Some(k), Some(v)) => (k, v),
+            _ => return Err(SimpleError::new("invalid i2c operation, expected `kind:value`")),
+        };
+
+        match kind {
+            "write" => value.parse::<Data>().map(|d| I2cOp::Write(d.data) ).map_err(SimpleError::from),
+            "read" => value.parse().map(I2cOp::Read).map_err(SimpleError::from),
+            _ => Err(SimpleError::new("invalid i2c operation (expected write or read)")),
+        }
+    }
+}
+
+impl I2cOp {
+    /// Fetch the length of data transferred by an operation
+    pub fn data_len(&self) -> usize {
+        match self {
+            I2cOp::Write(d) => d.len(),
+            I2cOp::Read(n) => *n as usize,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct SerialConnect {
+    /// Serial baud rate in bps
+    pub baud: u32,
+
+    #[structopt(long = "data-bits", default_value = "8")]
+    /// Number of data bits per character (5, 6, 7 or 8)
+    pub data_bits: DataBits,
+
+    #[structopt(long = "parity", default_value = "none")]
+    /// Parity checking mode (none, odd or even)
+    pub parity: Parity,
+
+    #[structopt(long = "stop-bits", default_value = "1")]
+    /// Number of stop bits (1 or 2)
+    pub stop_bits: StopBits,
+
+    #[structopt(long = "flow-control", default_value = "none")]
+    /// Flow control mode (none, software or hardware)
+    pub flow_control: FlowControl,
+}
+
+impl SerialConnect {
+    /// Create a serial configuration with the provided baud rate and 8N1 framing
+    pub fn new(baud: u32) -> Self {
+        Self {
+            baud,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct SerialRead {
+    /// Maximum number of bytes to read
+    pub max_len: u16,
+
+    #[structopt(long = "timeout-ms", default_value = "0")]
+    /// Time to wait for data to become available in milliseconds
+    pub timeout_ms: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct PwmPeriod {
+    /// PWM period in nanoseconds
+    pub period_ns: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct PwmDuty {
+    /// PWM duty cycle (active time) in nanoseconds, this must not exceed the period
+    pub duty_ns: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, StructOpt)]
+pub enum Polarity {
+    #[structopt(name = "normal")]
+    /// Output is high for the duty cycle
+    #[default]
+    Normal,
+    #[structopt(name = "inversed")]
+    /// Output is low for the duty cycle
+    Inversed,
+}
+
+impl std::str::FromStr for Polarity {
+    type Err = SimpleError;
+
+    fn from_str(polarity: &str) -> Result<Self, Self::Err> {
+        match polarity {
+            "normal" => Ok(Polarity::Normal),
+            "inversed" => Ok(Polarity::Inversed),
+            _ => Err(SimpleError::new("invalid pwm polarity")),
+        }
+    }
+}
+
+/// PWM channel state, returned in response to a `PwmGet` request
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PwmState {
+    /// Period in nanoseconds
+    pub period_ns: u64,
+    /// Duty cycle (active time) in nanoseconds
+    pub duty_ns: u64,
+    pub polarity: Polarity,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct AdcRead {
+    /// IIO channel name (ie. `voltage0` for `in_voltage0_raw`)
+    pub channel: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct AdcCapture {
+    /// Number of samples to capture per channel
+    pub samples: u32,
+
+    /// IIO channel names to capture (ie. `voltage0 voltage1`)
+    pub channels: Vec<String>,
+
+    #[structopt(long = "frequency")]
+    /// Sampling frequency in Hz, the device default is used if unset
+    #[serde(default)]
+    pub frequency: Option<u32>,
+
+    #[structopt(long = "trigger")]
+    /// IIO trigger name, required by devices without a default trigger
+    #[serde(default)]
+    pub trigger: Option<String>,
+}
+
+/// ADC channel reading, scaled values are in the IIO units for the channel
+/// type (ie. millivolts for voltage channels)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdcReading {
+    pub raw: i64,
+    pub scale: f64,
+    pub offset: f64,
+}
+
+impl AdcReading {
+    /// Fetch the scaled channel value, `(raw + offset) * scale`
+    pub fn value(&self) -> f64 {
+        (self.raw as f64 + self.offset) * self.scale
+    }
+}
+
+/// Samples captured from an ADC channel
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdcChannelData {
+    pub channel: String,
+    pub scale: f64,
+    pub offset: f64,
+    /// Raw sample values
+    pub samples: Vec<i64>,
+}
+
+impl AdcChannelData {
+    /// Fetch scaled sample values, `(raw + offset) * scale`
+    pub fn values(&self) -> Vec<f64> {
+        self.samples.iter().map(|r| (*r as f64 + self.offset) * self.scale ).collect()
+    }
+}
+
+/// Maximum data length of a (classic) CAN frame
+pub const CAN_DATA_MAX: usize = 8;
+
+/// CAN frame, in `cansend` form (ie. `123#DEADBEEF`, `12345678#00` or `123#R4`)
+///
+/// Identifiers above 0x7ff are always sent as extended frames, data for remote
+/// frames is ignored other than its length (the DLC).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CanFrame {
+    pub id: u32,
+    #[serde(default)]
+    pub extended: bool,
+    #[serde(default)]
+    pub remote: bool,
+    pub data: Vec<u8>,
+}
+
+impl std::str::FromStr for CanFrame {
+    type Err = SimpleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '#');
+        let id = parts.next().unwrap_or("");
+        let data = parts.next().ok_or_else(|| SimpleError::new("expected <id>#<data>"))?;
+
+        let extended = id.len() == 8;
+        let id = u32::from_str_radix(id, 16).map_err(|_e| SimpleError::new("invalid can id"))?;
+        let extended = extended || id > 0x7ff;
+        if id > 0x1fff_ffff {
+            return Err(SimpleError::new("can id out of range"));
+        }
+
+        if let Some(dlc) = data.strip_prefix('R') {
+            let dlc = match dlc {
+                "" => 0,
+                n => n.parse().map_err(|_e| SimpleError::new("invalid remote frame length"))?,
+            };
+            if dlc > CAN_DATA_MAX {
+                return Err(SimpleError::new("remote frame length exceeds 8 bytes"));
+            }
+            return Ok(CanFrame{id, extended, remote: true, data: vec![0; dlc]});
+        }
+
+        let data = hex::decode(data.replace('.', "")).map_err(|_e| SimpleError::new("invalid can data"))?;
+        if data.len() > CAN_DATA_MAX {
+            return Err(SimpleError::new("can data exceeds 8 bytes"));
+        }
+
+        Ok(CanFrame{id, extended, remote: false, data})
+    }
+}
+
+impl embedded_can::Frame for CanFrame {
+    fn new(id: impl Into<embedded_can::Id>, data: &[u8]) -> Option<Self> {
+        if data.len() > CAN_DATA_MAX {
+            return None;
+        }
+
+        let (id, extended) = can_id(id.into());
+        Some(CanFrame{id, extended, remote: false, data: data.to_vec()})
+    }
+
+    fn new_remote(id: impl Into<embedded_can::Id>, dlc: usize) -> Option<Self> {
+        if dlc > CAN_DATA_MAX {
+            return None;
+        }
+
+        let (id, extended) = can_id(id.into());
+        Some(CanFrame{id, extended, remote: true, data: vec![0; dlc]})
+    }
+
+    fn is_extended(&self) -> bool {
+        self.extended || self.id > 0x7ff
+    }
+
+    fn is_remote_frame(&self) -> bool {
+        self.remote
+    }
+
+    fn id(&self) -> embedded_can::Id {
+        use embedded_can::{Id, StandardId, ExtendedId};
+
+        // Masked identifiers are always within range
+        if self.is_extended() {
+            Id::Extended(ExtendedId::new(self.id & 0x1fff_ffff).unwrap())
+        } else {
+            Id::Standard(StandardId::new(self.id as u16 & 0x7ff).unwrap())
+        }
+    }
+
+    fn dlc(&self) -> usize {
+        self.data.len()
+    }
+
+    fn data(&self) -> &[u8] {
+        if self.remote {
+            &[]
+        } else {
+            &self.data
+        }
+    }
+}
+
+/// Split an embedded-can identifier into raw id and extended flag
+fn can_id(id: embedded_can::Id) -> (u32, bool) {
+    match id {
+        embedded_can::Id::Standard(s) => (s.as_raw() as u32, false),
+        embedded_can::Id::Extended(e) => (e.as_raw(), true),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct CanSend {
+    #[structopt(parse(try_from_str))]
+    /// Frame to send in `cansend` form (ie. `123#DEADBEEF` or `12345678#R`)
+    pub frame: CanFrame,
+}
+
+/// CAN receive filter, frames match where `frame.id & mask == id & mask`
+///
+/// In `candump` form (ie. `123:7ff`), ids and masks are compared without frame format flags.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CanFilter {
+    pub id: u32,
+    pub mask: u32,
+}
+
+impl CanFilter {
+    pub fn matches(&self, frame: &CanFrame) -> bool {
+        frame.id & self.mask == self.id & self.mask
+    }
+
+    /// Check whether a frame is accepted by a set of filters, empty filters accept all frames
+    pub fn accepts(filters: &[CanFilter], frame: &CanFrame) -> bool {
+        filters.is_empty() || filters.iter().any(|f| f.matches(frame) )
+    }
+}
+
+impl std::str::FromStr for CanFilter {
+    type Err = SimpleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let id = parts.next().unwrap_or("");
+        let mask = parts.next().ok_or_else(|| SimpleError::new("expected <id>:<mask>"))?;
+
+        Ok(CanFilter {
+            id: u32::from_str_radix(id, 16).map_err(|_e| SimpleError::new("invalid can filter id"))?,
+            mask: u32::from_str_radix(mask, 16).map_err(|_e| SimpleError::new("invalid can filter mask"))?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, StructOpt)]
+pub struct CanFilters {
+    #[structopt(parse(try_from_str))]
+    /// Receive filters in `candump` form (ie. `123:7ff`), all frames are accepted if none are provided
+    pub filters: Vec<CanFilter>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, StructOpt)]
+pub struct CanReceive {
+    #[structopt(long = "timeout-ms", default_value = "0")]
+    /// Time to wait for a frame to arrive in milliseconds
+    pub timeout_ms: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+impl std::str::FromStr for DataBits {
+    type Err = SimpleError;
+
+    fn from_str(bits: &str) -> Result<Self, Self::Err> {
+        match bits {
+            "5" => Ok(DataBits::Five),
+            "6" => Ok(DataBits::Six),
+            "7" => Ok(DataBits::Seven),
+            "8" => Ok(DataBits::Eight),
+            _ => Err(SimpleError::new("invalid serial data bits")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+impl std::str::FromStr for Parity {
+    type Err = SimpleError;
+
+    fn from_str(parity: &str) -> Result<Self, Self::Err> {
+        match parity {
+            "none" => Ok(Parity::None),
+            "odd" => Ok(Parity::Odd),
+            "even" => Ok(Parity::Even),
+            _ => Err(SimpleError::new("invalid serial parity")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+impl std::str::FromStr for StopBits {
+    type Err = SimpleError;
+
+    fn from_str(bits: &str) -> Result<Self, Self::Err> {
+        match bits {
+            "1" => Ok(StopBits::One),
+            "2" => Ok(StopBits::Two),
+            _ => Err(SimpleError::new("invalid serial stop bits")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FlowControl {
+    None,
+    Software,
+    Hardware,
+}
+
+impl std::str::FromStr for FlowControl {
+    type Err = SimpleError;
+
+    fn from_str(flow: &str) -> Result<Self, Self::Err> {
+        match flow {
+            "none" => Ok(FlowControl::None),
+            "software" => Ok(FlowControl::Software),
+            "hardware" => Ok(FlowControl::Hardware),
+            _ => Err(SimpleError::new("invalid serial flow control")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, StructOpt)]
+pub struct Delay {
+    /// Delay in microseconds
+    pub us: u32,
+}
+
+/// Sequence of requests and delays executed by the server without client round trips,
+/// so timing between steps is enforced next to the hardware
+#[derive(Debug, Clone, Default, Serialize, Deserialize, StructOpt)]
+pub struct Sequence {
+    #[structopt(parse(try_from_str))]
+    /// Steps to execute in order (ie. `gpiochip0:4=0 delay:10000 gpiochip0:4=1` to set pins and delay in microseconds)
+    pub steps: Vec<SequenceStep>,
+}
+
+impl Sequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a request for the provided device
+    pub fn request(mut self, device: &str, kind: RequestKind) -> Self {
+        self.steps.push(SequenceStep::Request{device: device.to_owned(), kind});
+        self
+    }
+
+    /// Append a delay in microseconds
+    pub fn delay_us(mut self, us: u32) -> Self {
+        self.steps.push(SequenceStep::DelayUs(us));
+        self
+    }
+
+    /// Append a delay in milliseconds
+    pub fn delay_ms(self, ms: u32) -> Self {
+        self.delay_us(ms.saturating_mul(1000))
+    }
+}
+
+/// Sequence step
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SequenceStep {
+    /// Request for the specified device
+    Request{device: String, kind: RequestKind},
+    /// Delay in microseconds
+    DelayUs(u32),
+}
+
+impl std::str::FromStr for SequenceStep {
+    type Err = SimpleError;
+
+    /// Parse delays (`delay:US`) and pin sets (`PIN=0` or `PIN=1`), other requests
+    /// are only available via the API
+    fn from_str(step: &str) -> Result<Self, Self::Err> {
+        if let Some(us) = step.strip_prefix("delay:") {
+            return us.parse().map(SequenceStep::DelayUs).map_err(SimpleError::from);
+        }
+
+        let mut parts = step.rsplitn(2, '=');
+        let (value, device) = match (parts.next(), parts.next()) {
+            (Some(v), Some(d)) => (v, d),
+            _ => return Err(SimpleError::new("invalid sequence step, expected `delay:US` or `PIN=VALUE`")),
+        };
+
+        let value = match value {
+            "0" => false,
+            "1" => true,
+            _ => return Err(SimpleError::new("invalid pin value (expected 0 or 1)")),
+        };
+
+        Ok(SequenceStep::Request{device: device.to_owned(), kind: RequestKind::PinSet(Value{value})})
+    }
+}
+
+impl RequestKind {
+    /// Fetch the total time a request delays the server in microseconds
+    pub fn delay_us(&self) -> u64 {
+        match self {
+            RequestKind::Delay(d) => d.us as u64,
+            RequestKind::SpiTransaction(t) => t.ops.iter().map(|o| match o {
+                SpiOp::DelayUs(us) => *us as u64,
+                _ => 0,
+            }).sum(),
+            RequestKind::Sequence(s) => s.steps.iter().map(|s| match s {
+                SequenceStep::Request{kind, ..} => kind.delay_us(),
+                SequenceStep::DelayUs(us) => *us as u64,
+            }).sum(),
+            _ => 0,
+        }
+    }
+}
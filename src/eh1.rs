@@ -0,0 +1,186 @@
+//! embedded-hal 1.0 support
+//!
+//! Local and remote devices implement the embedded-hal 1.0 traits in addition to
+//! the 0.2 traits, these helpers map 1.0 (and 0.2 `Transactional`) operations onto
+//! the existing 0.2 calls and transaction requests.
+
+
+use embedded_hal::blocking::{spi, i2c};
+use embedded_hal_1::spi::Operation as SpiOperation;
+use embedded_hal_1::i2c::Operation as I2cOperation;
+
+use crate::common::{SpiOp, I2cOp, ResponseKind};
+use crate::error::Error;
+
+/// Execute a list of SPI operations as a single full-duplex transfer, so that chip
+/// select remains asserted for the whole transaction
+pub(crate) fn spi_transaction<D>(dev: &mut D, ops: &mut [SpiOperation<u8>]) -> Result<(), Error>
+where
+    D: spi::Transfer<u8>,
+    Error: From<D::Error>,
+{
+    let mut buff = Vec::new();
+
+    for op in ops.iter() {
+        match op {
+            SpiOperation::Read(r) => buff.resize(buff.len() + r.len(), 0),
+            SpiOperation::Write(w) => buff.extend_from_slice(w),
+            SpiOperation::Transfer(r, w) => {
+                buff.extend_from_slice(w);
+                buff.resize(buff.len() + r.len().saturating_sub(w.len()), 0);
+            },
+            SpiOperation::TransferInPlace(d) => buff.extend_from_slice(d),
+            SpiOperation::DelayNs(_) => return Err(Error::Unsupported("delays within SPI transactions".to_owned())),
+        }
+    }
+
+    if buff.is_empty() {
+        return Ok(())
+    }
+
+    dev.transfer(&mut buff)?;
+
+    // Split read data back out to the operations
+    let mut i = 0;
+    for op in ops.iter_mut() {
+        match op {
+            SpiOperation::Read(r) | SpiOperation::TransferInPlace(r) => {
+                r.copy_from_slice(&buff[i..i+r.len()]);
+                i += r.len();
+            },
+            SpiOperation::Write(w) => i += w.len(),
+            SpiOperation::Transfer(r, w) => {
+                r.copy_from_slice(&buff[i..i+r.len()]);
+                i += r.len().max(w.len());
+            },
+            SpiOperation::DelayNs(_) => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Convert SPI operations to transaction operations, delays are rounded up to the next microsecond
+/// and reads are limited to 65535 bytes
+pub(crate) fn spi_ops(ops: &[SpiOperation<u8>]) -> Result<Vec<SpiOp>, Error> {
+    ops.iter().map(|op| {
+        let op = match op {
+            SpiOperation::Read(r) if r.len() > u16::MAX as usize => return Err(Error::TransferTooLarge(r.len())),
+            SpiOperation::Read(r) => SpiOp::Read(r.len() as u16),
+            SpiOperation::Write(w) => SpiOp::Write(w.to_vec()),
+            SpiOperation::Transfer(r, w) => {
+                let mut d = w.to_vec();
+                d.resize(r.len().max(w.len()), 0);
+                SpiOp::Transfer(d)
+            },
+            SpiOperation::TransferInPlace(d) => SpiOp::Transfer(d.to_vec()),
+            SpiOperation::DelayNs(ns) => SpiOp::DelayUs(ns / 1000 + (ns % 1000 != 0) as u32),
+        };
+        Ok(op)
+    }).collect()
+}
+
+/// Copy data read by a transaction back to the SPI operations
+pub(crate) fn spi_results(ops: &mut [SpiOperation<u8>], data: &[Vec<u8>]) -> Result<(), Error> {
+    if ops.len() != data.len() {
+        return Err(Error::InvalidResponse(ResponseKind::SpiTransaction(data.to_vec())));
+    }
+
+    for (op, d) in ops.iter_mut().zip(data) {
+        let r = match op {
+            SpiOperation::Read(r) | SpiOperation::TransferInPlace(r) | SpiOperation::Transfer(r, _) => r,
+            _ => continue,
+        };
+
+        if d.len() < r.len() {
+            return Err(Error::InvalidResponse(ResponseKind::SpiTransaction(data.to_vec())));
+        }
+
+        r.copy_from_slice(&d[..r.len()]);
+    }
+
+    Ok(())
+}
+
+/// Convert I2C operations to transaction operations
+pub(crate) fn i2c_ops(ops: &[I2cOperation]) -> Vec<I2cOp> {
+    ops.iter().map(|op| {
+        match op {
+            I2cOperation::Read(r) => I2cOp::Read(r.len() as u16),
+            I2cOperation::Write(w) => I2cOp::Write(w.to_vec()),
+        }
+    }).collect()
+}
+
+/// Copy data read by a transaction back to the I2C operations
+pub(crate) fn i2c_results(ops: &mut [I2cOperation], data: &[Vec<u8>]) -> Result<(), Error> {
+    if ops.len() != data.len() {
+        return Err(Error::InvalidResponse(ResponseKind::I2cTransaction(data.to_vec())));
+    }
+
+    for (op, d) in ops.iter_mut().zip(data) {
+        if let I2cOperation::Read(r) = op {
+            if d.len() != r.len() {
+                return Err(Error::InvalidResponse(ResponseKind::I2cTransaction(data.to_vec())));
+            }
+            r.copy_from_slice(d);
+        }
+    }
+
+    Ok(())
+}
+
+/// Convert embedded-hal 0.2 `Transactional` I2C operations to transaction operations
+pub(crate) fn i2c_transactional_ops(ops: &[i2c::Operation]) -> Vec<I2cOp> {
+    ops.iter().map(|op| {
+        match op {
+            i2c::Operation::Read(r) => I2cOp::Read(r.len() as u16),
+            i2c::Operation::Write(w) => I2cOp::Write(w.to_vec()),
+        }
+    }).collect()
+}
+
+/// Copy data read by a transaction back to embedded-hal 0.2 `Transactional` I2C operations
+pub(crate) fn i2c_transactional_results(ops: &mut [i2c::Operation], data: &[Vec<u8>]) -> Result<(), Error> {
+    if ops.len() != data.len() {
+        return Err(Error::InvalidResponse(ResponseKind::I2cTransaction(data.to_vec())));
+    }
+
+    for (op, d) in ops.iter_mut().zip(data) {
+        if let i2c::Operation::Read(r) = op {
+            if d.len() != r.len() {
+                return Err(Error::InvalidResponse(ResponseKind::I2cTransaction(data.to_vec())));
+            }
+            r.copy_from_slice(d);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spi_delays() {
+        let ops = [SpiOperation::DelayNs(0), SpiOperation::DelayNs(1), SpiOperation::DelayNs(1000), SpiOperation::DelayNs(u32::MAX)];
+
+        let us: Vec<_> = spi_ops(&ops).unwrap().into_iter().map(|op| match op {
+            SpiOp::DelayUs(us) => us,
+            _ => panic!("unexpected operation: {:?}", op),
+        }).collect();
+
+        assert_eq!(us, vec![0, 1, 1, 4_294_968]);
+    }
+
+    #[test]
+    fn spi_read_length() {
+        let mut buff = vec![0u8; u16::MAX as usize + 1];
+
+        match spi_ops(&[SpiOperation::Read(&mut buff)]) {
+            Err(Error::TransferTooLarge(n)) => assert_eq!(n, buff.len()),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
@@ -1,29 +1,115 @@
 
 use std::io;
+use std::fmt;
 
-use crate::common::ResponseKind;
+use nix::errno::Errno;
+use nix::Error as NixError;
+
+use crate::common::{ResponseKind, DeviceError, ErrorCategory};
 use daemon_engine::DaemonError;
 use serde_json::{Error as JsonError};
+use serde_cbor::error::{Error as CborError};
 use tokio::timer::timeout::Error as TimeoutError;
 use linux_embedded_hal::sysfs_gpio::Error as GpioError;
+use gpio_cdev::errors::{Error as CdevError, ErrorKind as CdevErrorKind};
+use linux_embedded_hal::i2cdev::linux::LinuxI2CError;
+use socketcan::{CANSocketOpenError, ConstructionError as CanConstructionError};
+use embedded_hal_1 as hal1;
 
+/// remote-hal error type
 #[derive(Debug)]
 pub enum Error {
-    Io(io::ErrorKind),
+    Io(io::Error),
     Json(JsonError),
+    Cbor(CborError),
     Timeout,
     Remote(String),
     Daemon(DaemonError),
     InvalidResponse(ResponseKind),
+    DeviceAlreadyBound,
+    DeviceNotBound,
     Gpio(GpioError),
+    /// GPIO character device error, with the underlying error where available
+    /// (IO errors are reported as `Io`)
+    Cdev(String, Option<Box<std::error::Error + Send + Sync>>),
     InvalidSpiMode,
     InvalidRemoteAddress,
-    None(()),
+    IncompatibleServer(String),
+    TransferTooLarge(usize),
+    UnknownDevice(String),
+    NotPermitted(String),
+    Config(String),
+    /// TLS configuration error, with the underlying error where available
+    Tls(String, Option<Box<std::error::Error + Send + Sync>>),
+    Unsupported(String),
+    /// I2C address or data was not acknowledged, with the OS error number if known
+    NoAcknowledge(Option<i32>),
+    /// I2C bus arbitration was lost, with the OS error number if known
+    ArbitrationLoss(Option<i32>),
+    InvalidArgument(String),
+    /// Device error reported by the server without a more specific variant
+    Device(DeviceError),
+    /// Connection to the server was lost before a response was received
+    Disconnected,
+}
+
+impl Error {
+    /// Create an IO error for invalid data read from a device (ie. malformed
+    /// sysfs attributes or checksum mismatches)
+    pub fn invalid_data<S: Into<String>>(message: S) -> Self {
+        Error::Io(io::Error::new(io::ErrorKind::InvalidData, message.into()))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Json(e) => write!(f, "json error: {}", e),
+            Error::Cbor(e) => write!(f, "cbor error: {}", e),
+            Error::Timeout => write!(f, "timeout"),
+            Error::Remote(e) => write!(f, "{}", e),
+            Error::Daemon(e) => write!(f, "daemon error: {:?}", e),
+            Error::InvalidResponse(r) => write!(f, "invalid response: {:?}", r),
+            Error::DeviceAlreadyBound => write!(f, "device already bound"),
+            Error::DeviceNotBound => write!(f, "device not bound"),
+            Error::Gpio(e) => write!(f, "gpio error: {}", e),
+            Error::Cdev(e, _) => write!(f, "gpio error: {}", e),
+            Error::InvalidSpiMode => write!(f, "invalid spi mode"),
+            Error::InvalidRemoteAddress => write!(f, "invalid remote address"),
+            Error::IncompatibleServer(e) => write!(f, "incompatible server: {}", e),
+            Error::TransferTooLarge(n) => write!(f, "transfer of {} bytes exceeds limit", n),
+            Error::UnknownDevice(d) => write!(f, "unknown device: {}", d),
+            Error::NotPermitted(e) => write!(f, "not permitted: {}", e),
+            Error::Config(e) => write!(f, "configuration error: {}", e),
+            Error::Tls(e, _) => write!(f, "tls error: {}", e),
+            Error::Unsupported(e) => write!(f, "unsupported: {}", e),
+            Error::NoAcknowledge(_) => write!(f, "no acknowledge"),
+            Error::ArbitrationLoss(_) => write!(f, "arbitration lost"),
+            Error::InvalidArgument(e) => write!(f, "invalid argument: {}", e),
+            Error::Device(e) => write!(f, "{}", e),
+            Error::Disconnected => write!(f, "disconnected"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Cbor(e) => Some(e),
+            Error::Gpio(e) => Some(e),
+            Error::Cdev(_, Some(e)) | Error::Tls(_, Some(e)) => Some(e.as_ref()),
+            Error::Device(e) => Some(e),
+            _ => None,
+        }
+    }
 }
 
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Self {
-        Error::Io(e.kind())
+        Error::Io(e)
     }
 }
 
@@ -33,15 +119,15 @@ impl From<JsonError> for Error {
     }
 }
 
-impl From<DaemonError> for Error {
-    fn from(e: DaemonError) -> Self {
-        Error::Daemon(e)
+impl From<CborError> for Error {
+    fn from(e: CborError) -> Self {
+        Error::Cbor(e)
     }
 }
 
-impl From<()> for Error {
-    fn from(e: ()) -> Self {
-        Error::None(e)
+impl From<DaemonError> for Error {
+    fn from(e: DaemonError) -> Self {
+        Error::Daemon(e)
     }
 }
 
@@ -51,6 +137,58 @@ impl From<GpioError> for Error {
     }
 }
 
+impl From<CdevError> for Error {
+    fn from(e: CdevError) -> Self {
+        match e.0 {
+            CdevErrorKind::Io(e) => Error::Io(e),
+            CdevErrorKind::Nix(e) => Error::Cdev(e.to_string(), Some(Box::new(e))),
+            k => Error::Cdev(k.to_string(), None),
+        }
+    }
+}
+
+/// System call errors are reported as IO errors
+impl From<NixError> for Error {
+    fn from(e: NixError) -> Self {
+        match e.as_errno() {
+            Some(errno) => Error::Io(io::Error::from_raw_os_error(errno as i32)),
+            None => Error::Io(io::Error::other(e)),
+        }
+    }
+}
+
+/// I2C errors are mapped per the i2c-dev fault codes, NACKs (ENXIO or EREMOTEIO)
+/// and arbitration loss (EAGAIN) have dedicated variants
+impl From<LinuxI2CError> for Error {
+    fn from(e: LinuxI2CError) -> Self {
+        let e: io::Error = e.into();
+
+        let errno = e.raw_os_error();
+
+        match errno.map(Errno::from_i32) {
+            Some(Errno::ENXIO) | Some(Errno::EREMOTEIO) => Error::NoAcknowledge(errno),
+            Some(Errno::EAGAIN) => Error::ArbitrationLoss(errno),
+            Some(Errno::ETIMEDOUT) => Error::Timeout,
+            _ => Error::Io(e),
+        }
+    }
+}
+
+impl From<CANSocketOpenError> for Error {
+    fn from(e: CANSocketOpenError) -> Self {
+        match e {
+            CANSocketOpenError::IOError(e) => Error::Io(e),
+            e => Error::UnknownDevice(e.to_string()),
+        }
+    }
+}
+
+impl From<CanConstructionError> for Error {
+    fn from(e: CanConstructionError) -> Self {
+        Error::InvalidArgument(e.to_string())
+    }
+}
+
 impl From<TimeoutError<Error>> for Error {
     fn from(e: TimeoutError<Error>) -> Self {
         if e.is_inner() {
@@ -59,4 +197,85 @@ impl From<TimeoutError<Error>> for Error {
             Error::Timeout
         }
     }
-}
\ No newline at end of file
+}
+
+/// Convert errors to structured errors for reporting to clients
+impl From<Error> for DeviceError {
+    fn from(e: Error) -> Self {
+        use crate::common::IoErrorKind;
+
+        let message = e.to_string();
+
+        let (category, errno) = match e {
+            Error::NoAcknowledge(errno) => (ErrorCategory::NoAcknowledge, errno),
+            Error::ArbitrationLoss(errno) => (ErrorCategory::ArbitrationLoss, errno),
+            Error::Io(e) => {
+                let category = match e.kind() {
+                    io::ErrorKind::NotFound => ErrorCategory::NotFound,
+                    io::ErrorKind::PermissionDenied => ErrorCategory::PermissionDenied,
+                    io::ErrorKind::InvalidInput => ErrorCategory::InvalidArgument,
+                    io::ErrorKind::TimedOut => ErrorCategory::Timeout,
+                    k => ErrorCategory::Io(IoErrorKind::from(k)),
+                };
+                (category, e.raw_os_error())
+            },
+            Error::Timeout => (ErrorCategory::Timeout, None),
+            Error::NotPermitted(_) => (ErrorCategory::PermissionDenied, None),
+            Error::UnknownDevice(_) => (ErrorCategory::NotFound, None),
+            Error::InvalidArgument(_) | Error::InvalidSpiMode | Error::TransferTooLarge(_) => (ErrorCategory::InvalidArgument, None),
+            Error::Unsupported(_) => (ErrorCategory::Unsupported, None),
+            Error::Device(d) => return d,
+            _ => (ErrorCategory::Other, None),
+        };
+
+        DeviceError::new(category, errno, message)
+    }
+}
+
+/// Convert structured errors reported by the server to typed errors
+impl From<DeviceError> for Error {
+    fn from(e: DeviceError) -> Self {
+        match e.category {
+            ErrorCategory::NoAcknowledge => Error::NoAcknowledge(e.errno),
+            ErrorCategory::ArbitrationLoss => Error::ArbitrationLoss(e.errno),
+            ErrorCategory::Timeout => Error::Timeout,
+            ErrorCategory::PermissionDenied => Error::NotPermitted(e.message),
+            ErrorCategory::NotFound => Error::UnknownDevice(e.message),
+            ErrorCategory::InvalidArgument => Error::InvalidArgument(e.message),
+            ErrorCategory::Unsupported => Error::Unsupported(e.message),
+            ErrorCategory::Io(_) | ErrorCategory::Other => Error::Device(e),
+        }
+    }
+}
+
+// embedded-hal 1.0 error kinds, only I2C NACKs and arbitration loss have a direct equivalent
+
+impl hal1::digital::Error for Error {
+    fn kind(&self) -> hal1::digital::ErrorKind {
+        hal1::digital::ErrorKind::Other
+    }
+}
+
+impl hal1::spi::Error for Error {
+    fn kind(&self) -> hal1::spi::ErrorKind {
+        hal1::spi::ErrorKind::Other
+    }
+}
+
+impl hal1::i2c::Error for Error {
+    fn kind(&self) -> hal1::i2c::ErrorKind {
+        use hal1::i2c::{ErrorKind, NoAcknowledgeSource};
+
+        match self {
+            Error::NoAcknowledge(_) => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+            Error::ArbitrationLoss(_) => ErrorKind::ArbitrationLoss,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl embedded_can::Error for Error {
+    fn kind(&self) -> embedded_can::ErrorKind {
+        embedded_can::ErrorKind::Other
+    }
+}
@@ -1,3 +1,6 @@
+// Trait objects are written without `dyn` throughout
+#![allow(bare_trait_objects)]
+
 
 extern crate serde;
 #[macro_use]
@@ -9,19 +12,38 @@ extern crate tokio;
 extern crate rand;
 extern crate futures_timer;
 extern crate embedded_hal;
+extern crate embedded_hal_1;
 #[macro_use]
 extern crate log;
 extern crate hex;
 extern crate try_from;
+extern crate serialport;
+extern crate nb;
+extern crate gpio_cdev;
+extern crate i2cdev;
+extern crate socketcan;
+extern crate embedded_can;
+extern crate nix;
+extern crate serde_cbor;
+extern crate bytes;
+extern crate toml;
+extern crate tokio_rustls;
+extern crate tokio_threadpool;
 
 extern crate daemon_engine;
 extern crate rr_mux;
 
 pub mod common;
+pub mod codec;
+pub mod transport;
 pub mod manager;
+pub mod asynch;
+pub mod eh1;
+pub mod adc;
 pub mod error;
 pub mod server;
 pub mod local;
+pub mod sim;
 pub mod remote;
 
 
@@ -0,0 +1,442 @@
+
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use embedded_hal::adc::{Channel, OneShot};
+use nix::poll::{poll, PollFd, PollFlags};
+
+use crate::adc::voltage_channel;
+use crate::common::{AdcReading, AdcCapture, AdcChannelData};
+use crate::server::backend::AdcDevice;
+use crate::error::Error;
+
+/// Base path for IIO devices
+const IIO_DEVICES_PATH: &str = "/sys/bus/iio/devices";
+
+/// Additional time allowed for buffered captures to complete
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Local ADC using the Linux IIO subsystem
+///
+/// Paths are IIO device names (ie. `iio:device0`) or absolute sysfs device
+/// directories, with buffered captures read from the matching `/dev` node.
+pub struct Adc {
+    path: PathBuf,
+    dev: PathBuf,
+}
+
+impl Adc {
+    pub fn new(path: &str) -> Result<Self, Error> {
+        let sysfs = if path.starts_with('/') {
+            PathBuf::from(path)
+        } else {
+            Path::new(IIO_DEVICES_PATH).join(path)
+        };
+
+        let name = match sysfs.file_name() {
+            Some(n) => n.to_owned(),
+            None => return Err(Error::UnknownDevice(path.to_owned())),
+        };
+
+        if !sysfs.is_dir() {
+            return Err(Error::UnknownDevice(path.to_owned()));
+        }
+
+        Ok(Self::with_paths(sysfs, Path::new("/dev").join(name)))
+    }
+
+    /// Create an ADC using the provided sysfs directory and buffer device
+    /// (ie. for testing against a fake IIO tree)
+    pub fn with_paths<P: AsRef<Path>, Q: AsRef<Path>>(sysfs: P, dev: Q) -> Self {
+        Self{path: sysfs.as_ref().to_owned(), dev: dev.as_ref().to_owned()}
+    }
+
+    /// Read a single value from the named channel
+    pub fn read(&mut self, channel: &str) -> Result<AdcReading, Error> {
+        let raw = self.read_attr(&format!("in_{}_raw", channel))?;
+        let raw = raw.parse().map_err(|_e| Error::invalid_data(format!("invalid {} raw value: {}", channel, raw)) )?;
+        let (scale, offset) = self.scale_offset(channel)?;
+
+        Ok(AdcReading{raw, scale, offset})
+    }
+
+    /// Capture samples from the requested channels using the IIO buffer, failing
+    /// with `Error::Timeout` if the capture does not complete within `limit`
+    pub fn capture(&mut self, capture: &AdcCapture, limit: Duration) -> Result<Vec<AdcChannelData>, Error> {
+        if capture.channels.is_empty() || capture.samples == 0 {
+            return Err(Error::NotPermitted("captures require at least one channel and sample".to_owned()));
+        }
+
+        // Ensure the buffer is idle before configuring
+        self.write_attr("buffer/enable", "0")?;
+
+        let elements = self.scan_elements(&capture.channels)?;
+
+        if let Some(t) = &capture.trigger {
+            self.write_attr("trigger/current_trigger", t)?;
+        }
+        if let Some(f) = capture.frequency {
+            self.write_attr("sampling_frequency", &format!("{}", f))?;
+        }
+        self.write_attr("buffer/length", &format!("{}", capture.samples))?;
+
+        // Compute the scan layout, elements are ordered by index and aligned to their storage size
+        let mut layout = Vec::with_capacity(elements.len());
+        let mut scan_size: usize = 0;
+        for e in &elements {
+            let n = e.format.storage_bytes();
+            scan_size = scan_size.div_ceil(n) * n;
+            layout.push(scan_size);
+            scan_size += n;
+        }
+        let align = elements.iter().map(|e| e.format.storage_bytes() ).max().unwrap_or(1);
+        let scan_size = scan_size.div_ceil(align) * align;
+
+        let mut timeout = CAPTURE_TIMEOUT;
+        if let Some(f) = capture.frequency {
+            timeout += Duration::from_millis(capture.samples as u64 * 1000 / f.max(1) as u64);
+        }
+        let timeout = timeout.min(limit);
+
+        self.write_attr("buffer/enable", "1")?;
+        let res = self.read_buffer(scan_size * capture.samples as usize, timeout);
+        self.write_attr("buffer/enable", "0")?;
+        let buff = res?;
+
+        // Split scans into per-channel samples in the requested order
+        let mut data = Vec::with_capacity(capture.channels.len());
+        for c in &capture.channels {
+            let (i, e) = match elements.iter().enumerate().find(|(_i, e)| &e.channel == c ) {
+                Some(v) => v,
+                None => return Err(Error::UnknownDevice(format!("no scan element for channel {}", c))),
+            };
+
+            let samples = buff.chunks(scan_size).map(|scan| {
+                let n = e.format.storage_bytes();
+                e.format.decode(&scan[layout[i]..layout[i]+n])
+            }).collect();
+
+            let (scale, offset) = self.scale_offset(c)?;
+            data.push(AdcChannelData{channel: c.clone(), scale, offset, samples});
+        }
+
+        Ok(data)
+    }
+
+    /// Enable scan elements for the requested channels (disabling all others),
+    /// returning the enabled elements ordered by scan index
+    fn scan_elements(&self, channels: &[String]) -> Result<Vec<ScanElement>, Error> {
+        let mut elements = vec![];
+
+        for entry in fs::read_dir(self.path.join("scan_elements"))? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if !name.ends_with("_en") {
+                continue;
+            }
+
+            let channel = name.trim_start_matches("in_").trim_end_matches("_en").to_owned();
+            let enabled = name.starts_with("in_") && channels.contains(&channel);
+            self.write_attr(&format!("scan_elements/{}", name), if enabled { "1" } else { "0" })?;
+
+            if enabled {
+                let index = self.read_attr(&format!("scan_elements/in_{}_index", channel))?;
+                let format = self.read_attr(&format!("scan_elements/in_{}_type", channel))?;
+                elements.push(ScanElement{
+                    index: index.parse().map_err(|_e| Error::invalid_data(format!("invalid {} index: {}", channel, index)) )?,
+                    format: format.parse()?,
+                    channel,
+                });
+            }
+        }
+
+        for c in channels {
+            if !elements.iter().any(|e| &e.channel == c ) {
+                return Err(Error::UnknownDevice(format!("no scan element for channel {}", c)));
+            }
+        }
+
+        elements.sort_by_key(|e| e.index );
+
+        Ok(elements)
+    }
+
+    /// Read `len` bytes from the buffer device, failing if data does not arrive within the timeout
+    fn read_buffer(&self, len: usize, timeout: Duration) -> Result<Vec<u8>, Error> {
+        let mut f = File::open(&self.dev)?;
+        let mut buff = vec![0u8; len];
+        let mut n = 0;
+        let start = Instant::now();
+
+        while n < len {
+            let remaining = match timeout.checked_sub(start.elapsed()) {
+                Some(r) => r,
+                None => return Err(Error::Timeout),
+            };
+
+            let mut fds = [PollFd::new(f.as_raw_fd(), PollFlags::POLLIN)];
+            if poll(&mut fds, remaining.as_millis() as i32)? == 0 {
+                return Err(Error::Timeout);
+            }
+
+            match f.read(&mut buff[n..])? {
+                0 => return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, format!("unexpected end of buffer after {} of {} bytes", n, len)))),
+                r => n += r,
+            }
+        }
+
+        Ok(buff)
+    }
+
+    /// Fetch the scale and offset for a channel, falling back to values shared
+    /// by the channel type (ie. `in_voltage_scale`) then defaults
+    fn scale_offset(&self, channel: &str) -> Result<(f64, f64), Error> {
+        let kind = channel.trim_end_matches(|c: char| c.is_ascii_digit() );
+
+        let mut values = [1.0, 0.0];
+        for (v, attr) in values.iter_mut().zip(&["scale", "offset"]) {
+            let s = match self.read_attr(&format!("in_{}_{}", channel, attr)) {
+                Ok(s) => s,
+                Err(_) => match self.read_attr(&format!("in_{}_{}", kind, attr)) {
+                    Ok(s) => s,
+                    Err(_) => continue,
+                },
+            };
+
+            *v = s.parse().map_err(|_e| Error::invalid_data(format!("invalid {} {}: {}", channel, attr, s)) )?;
+        }
+
+        Ok((values[0], values[1]))
+    }
+
+    fn read_attr(&self, attr: &str) -> Result<String, Error> {
+        let s = fs::read_to_string(self.path.join(attr))?;
+        Ok(s.trim().to_owned())
+    }
+
+    fn write_attr(&self, attr: &str, value: &str) -> Result<(), Error> {
+        fs::write(self.path.join(attr), value)?;
+        Ok(())
+    }
+}
+
+/// Enabled IIO scan element
+struct ScanElement {
+    channel: String,
+    index: u32,
+    format: ScanFormat,
+}
+
+/// IIO scan element format, parsed from `[be|le]:[s|u]BITS/STORAGEBITS[XREPEAT][>>SHIFT]`
+#[derive(Debug, Clone, PartialEq)]
+struct ScanFormat {
+    big_endian: bool,
+    signed: bool,
+    bits: u32,
+    storage_bits: u32,
+    shift: u32,
+}
+
+impl ScanFormat {
+    fn storage_bytes(&self) -> usize {
+        (self.storage_bits as usize).div_ceil(8)
+    }
+
+    /// Decode a sample from its storage bytes
+    fn decode(&self, d: &[u8]) -> i64 {
+        let mut v = if self.big_endian {
+            d.iter().fold(0u64, |v, b| (v << 8) | *b as u64 )
+        } else {
+            d.iter().rev().fold(0u64, |v, b| (v << 8) | *b as u64 )
+        };
+
+        v >>= self.shift;
+        if self.bits < 64 {
+            v &= (1 << self.bits) - 1;
+        }
+
+        if self.signed && self.bits < 64 && v & (1 << (self.bits - 1)) != 0 {
+            (v as i64) - (1 << self.bits)
+        } else {
+            v as i64
+        }
+    }
+}
+
+impl std::str::FromStr for ScanFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::invalid_data(format!("invalid scan element type: {}", s));
+
+        let mut parts = s.splitn(2, ':');
+        let big_endian = match parts.next() {
+            Some("be") => true,
+            Some("le") => false,
+            _ => return Err(invalid()),
+        };
+
+        let f = parts.next().ok_or_else(invalid)?;
+        let signed = match f.chars().next() {
+            Some('s') => true,
+            Some('u') => false,
+            _ => return Err(invalid()),
+        };
+
+        let (f, shift) = match f[1..].find(">>") {
+            Some(i) => (&f[1..i+1], f[i+3..].parse().map_err(|_e| invalid() )?),
+            None => (&f[1..], 0),
+        };
+
+        // Repeated elements are not supported
+        if f.contains('X') {
+            return Err(Error::Unsupported(format!("repeated scan elements ({})", s)));
+        }
+
+        let mut bits = f.splitn(2, '/');
+        let bits_v = bits.next().and_then(|b| b.parse().ok() ).ok_or_else(invalid)?;
+        let storage_bits = bits.next().and_then(|b| b.parse().ok() ).ok_or_else(invalid)?;
+
+        if bits_v == 0 || bits_v > 64 || storage_bits > 64 {
+            return Err(invalid());
+        }
+
+        Ok(Self{big_endian, signed, bits: bits_v, storage_bits, shift})
+    }
+}
+
+impl<P> OneShot<Adc, i32, P> for Adc
+where
+    P: Channel<Adc, ID=u8>,
+{
+    type Error = Error;
+
+    fn read(&mut self, _pin: &mut P) -> nb::Result<i32, Error> {
+        let r = Adc::read(self, &voltage_channel(P::channel())).map_err(nb::Error::Other)?;
+        Ok(r.raw as i32)
+    }
+}
+
+impl<P> OneShot<Adc, u16, P> for Adc
+where
+    P: Channel<Adc, ID=u8>,
+{
+    type Error = Error;
+
+    fn read(&mut self, _pin: &mut P) -> nb::Result<u16, Error> {
+        let r = Adc::read(self, &voltage_channel(P::channel())).map_err(nb::Error::Other)?;
+        Ok(r.raw as u16)
+    }
+}
+
+impl AdcDevice for Adc {
+    fn read(&mut self, channel: &str) -> Result<AdcReading, Error> {
+        Adc::read(self, channel)
+    }
+
+    fn capture(&mut self, capture: &AdcCapture, timeout: Duration) -> Result<Vec<AdcChannelData>, Error> {
+        Adc::capture(self, capture, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::OpenOptions;
+    use nix::sys::stat::Mode;
+    use nix::unistd::mkfifo;
+
+    /// Create a fake IIO device with two 12-bit channels
+    fn fake_iio(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("remote-hal-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("scan_elements")).unwrap();
+        fs::create_dir_all(dir.join("buffer")).unwrap();
+
+        let attrs = [
+            ("in_voltage0_raw", "1234"),
+            ("in_voltage0_scale", "0.5"),
+            ("in_voltage1_raw", "42"),
+            ("in_voltage_scale", "0.25"),
+            ("in_voltage_offset", "10"),
+            ("buffer/enable", "0"),
+            ("buffer/length", "0"),
+            ("scan_elements/in_voltage0_en", "0"),
+            ("scan_elements/in_voltage0_index", "0"),
+            ("scan_elements/in_voltage0_type", "le:u12/16>>0"),
+            ("scan_elements/in_voltage1_en", "0"),
+            ("scan_elements/in_voltage1_index", "1"),
+            ("scan_elements/in_voltage1_type", "be:s12/16>>4"),
+        ];
+        for (a, v) in &attrs {
+            fs::write(dir.join(a), v).unwrap();
+        }
+
+        dir
+    }
+
+    fn capture(channels: &[&str], samples: u32) -> AdcCapture {
+        AdcCapture{samples, channels: channels.iter().map(|c| c.to_string() ).collect(), frequency: None, trigger: None}
+    }
+
+    #[test]
+    fn fake_iio_read() {
+        let dir = fake_iio("adc-read");
+        let mut adc = Adc::with_paths(&dir, dir.join("dev"));
+
+        let r = adc.read("voltage0").unwrap();
+        assert_eq!((r.raw, r.scale, r.offset), (1234, 0.5, 10.0));
+
+        // Shared channel type attributes apply where channel attributes are missing
+        let r = adc.read("voltage1").unwrap();
+        assert_eq!((r.raw, r.scale, r.offset), (42, 0.25, 10.0));
+
+        assert!(adc.read("voltage2").is_err());
+    }
+
+    #[test]
+    fn fake_iio_capture() {
+        let dir = fake_iio("adc-capture");
+        let mut adc = Adc::with_paths(&dir, dir.join("dev"));
+
+        // Two scans of voltage0 (le u12) and voltage1 (be s12, shifted by 4)
+        fs::write(dir.join("dev"), [0x01, 0x02, 0xff, 0xf0, 0xff, 0x0f, 0x00, 0x10]).unwrap();
+
+        let data = adc.capture(&capture(&["voltage1", "voltage0"], 2), Duration::from_secs(1)).unwrap();
+        assert_eq!(data[0].channel, "voltage1");
+        assert_eq!(data[0].samples, vec![-1, 1]);
+        assert_eq!(data[1].channel, "voltage0");
+        assert_eq!(data[1].samples, vec![0x201, 0xfff]);
+
+        assert_eq!(fs::read_to_string(dir.join("scan_elements/in_voltage0_en")).unwrap(), "1");
+        assert_eq!(fs::read_to_string(dir.join("buffer/length")).unwrap(), "2");
+        assert_eq!(fs::read_to_string(dir.join("buffer/enable")).unwrap(), "0");
+
+        // Captures fail where the buffer ends early
+        assert!(adc.capture(&capture(&["voltage0"], 8), Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn fake_iio_capture_timeout() {
+        let dir = fake_iio("adc-timeout");
+        let dev = dir.join("dev");
+        mkfifo(&dev, Mode::S_IRWXU).unwrap();
+
+        // Hold the fifo open so the buffer can be opened without data arriving
+        let _writer = OpenOptions::new().read(true).write(true).open(&dev).unwrap();
+
+        let mut adc = Adc::with_paths(&dir, &dev);
+        let c = AdcCapture{frequency: Some(1), ..capture(&["voltage0"], 10)};
+
+        // Captures are bounded by the provided limit rather than the expected capture time
+        let start = Instant::now();
+        match adc.capture(&c, Duration::from_millis(100)) {
+            Err(Error::Timeout) => (),
+            r => panic!("unexpected capture result: {:?}", r),
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
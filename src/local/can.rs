@@ -0,0 +1,185 @@
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use socketcan::{CANSocket, CANFrame, CANFilter};
+
+use crate::common::{CanFrame, CanFilter};
+use crate::server::backend::CanDevice;
+use crate::error::Error;
+
+/// Interval at which subscription threads check for cancellation
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Local CAN interface using SocketCAN
+///
+/// Paths are interface names (ie. `can0` or `vcan0`). Frames sent by the interface
+/// are not received by it, subscriptions use a separate socket and thus receive
+/// frames independently of `receive` calls.
+pub struct Can {
+    iface: String,
+    socket: CANSocket,
+    subscription: Option<Subscription>,
+}
+
+/// Running frame subscription, stopped on drop
+struct Subscription {
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
+
+impl Can {
+    pub fn new(iface: &str) -> Result<Self, Error> {
+        let socket = CANSocket::open(iface)?;
+
+        Ok(Self{iface: iface.to_owned(), socket, subscription: None})
+    }
+
+    pub fn send(&mut self, frame: &CanFrame) -> Result<(), Error> {
+        self.socket.write_frame_insist(&to_socketcan(frame)?)?;
+        Ok(())
+    }
+
+    /// Set receive filters, an empty list accepts all frames
+    pub fn set_filters(&mut self, filters: &[CanFilter]) -> Result<(), Error> {
+        set_filters(&self.socket, filters)
+    }
+
+    /// Receive a frame, waiting at most `timeout` for one to arrive
+    pub fn receive(&mut self, timeout: Duration) -> Result<Option<CanFrame>, Error> {
+        // A zero read timeout blocks indefinitely, so zero timeouts use non-blocking reads
+        if timeout == Duration::from_millis(0) {
+            self.socket.set_nonblocking(true)?;
+        } else {
+            self.socket.set_nonblocking(false)?;
+            self.socket.set_read_timeout(timeout)?;
+        }
+
+        match self.socket.read_frame() {
+            Ok(f) => Ok(Some(from_socketcan(&f))),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Subscribe to received frames matching the provided filters, calling the
+    /// provided handler for each frame. This replaces any existing subscription.
+    pub fn subscribe<F>(&mut self, filters: &[CanFilter], mut handler: F) -> Result<(), Error>
+    where
+        F: FnMut(CanFrame) + Send + 'static,
+    {
+        self.unsubscribe();
+
+        let socket = CANSocket::open(&self.iface)?;
+        set_filters(&socket, filters)?;
+        socket.set_read_timeout(POLL_TIMEOUT)?;
+
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+
+        let handle = thread::spawn(move || {
+            while r.load(Ordering::SeqCst) {
+                match socket.read_frame() {
+                    Ok(f) => handler(from_socketcan(&f)),
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => (),
+                    Err(e) => {
+                        error!("can receive error: {:?}", e);
+                        break;
+                    }
+                }
+            }
+        });
+
+        self.subscription = Some(Subscription{running, handle: Some(handle)});
+
+        Ok(())
+    }
+
+    /// Cancel an existing frame subscription
+    pub fn unsubscribe(&mut self) {
+        self.subscription.take();
+    }
+}
+
+fn set_filters(socket: &CANSocket, filters: &[CanFilter]) -> Result<(), Error> {
+    if filters.is_empty() {
+        socket.filter_accept_all()?;
+        return Ok(());
+    }
+
+    let filters = filters.iter().map(|f| CANFilter::new(f.id, f.mask) ).collect::<Result<Vec<_>, _>>()?;
+    socket.set_filter(&filters)?;
+
+    Ok(())
+}
+
+/// SocketCAN selects the frame format by identifier, so extended frames with
+/// standard range identifiers are not supported
+fn to_socketcan(frame: &CanFrame) -> Result<CANFrame, Error> {
+    if frame.extended && frame.id <= 0x7ff {
+        return Err(Error::Unsupported("extended frames with identifiers below 0x800".to_owned()));
+    }
+
+    Ok(CANFrame::new(frame.id, &frame.data, frame.remote, false)?)
+}
+
+fn from_socketcan(frame: &CANFrame) -> CanFrame {
+    CanFrame {
+        id: frame.id(),
+        extended: frame.is_extended(),
+        remote: frame.is_rtr(),
+        data: frame.data().to_vec(),
+    }
+}
+
+/// Blocking CAN, `receive` waits until a frame arrives
+impl embedded_can::blocking::Can for Can {
+    type Frame = CanFrame;
+    type Error = Error;
+
+    fn transmit(&mut self, frame: &CanFrame) -> Result<(), Error> {
+        self.send(frame)
+    }
+
+    fn receive(&mut self) -> Result<CanFrame, Error> {
+        loop {
+            if let Some(f) = Can::receive(self, POLL_TIMEOUT)? {
+                return Ok(f);
+            }
+        }
+    }
+}
+
+impl CanDevice for Can {
+    fn send(&mut self, frame: &CanFrame) -> Result<(), Error> {
+        Can::send(self, frame)
+    }
+
+    fn set_filters(&mut self, filters: &[CanFilter]) -> Result<(), Error> {
+        Can::set_filters(self, filters)
+    }
+
+    fn receive(&mut self, timeout: Duration) -> Result<Option<CanFrame>, Error> {
+        Can::receive(self, timeout)
+    }
+
+    fn subscribe(&mut self, filters: &[CanFilter], handler: Box<FnMut(CanFrame) + Send>) -> Result<(), Error> {
+        Can::subscribe(self, filters, handler)
+    }
+
+    fn unsubscribe(&mut self) {
+        Can::unsubscribe(self)
+    }
+}
@@ -0,0 +1,29 @@
+
+use std::thread;
+use std::time::Duration;
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal_1 as hal1;
+
+/// Local delay implementation, this may also be used with remote devices
+/// though delays are subject to network latency
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Delay;
+
+impl DelayMs<u32> for Delay {
+    fn delay_ms(&mut self, ms: u32) {
+        thread::sleep(Duration::from_millis(ms as u64));
+    }
+}
+
+impl DelayUs<u32> for Delay {
+    fn delay_us(&mut self, us: u32) {
+        thread::sleep(Duration::from_micros(us as u64));
+    }
+}
+
+impl hal1::delay::DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        thread::sleep(Duration::from_nanos(ns as u64));
+    }
+}
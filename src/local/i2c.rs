@@ -1,28 +1,144 @@
 
+use std::os::unix::io::AsRawFd;
+
+use nix::libc;
+use nix::errno::Errno;
 use linux_embedded_hal::{I2cdev, i2cdev::linux::LinuxI2CError};
+use i2cdev::core::{I2CDevice, I2CTransfer, I2CMessage};
+use i2cdev::linux::{LinuxI2CBus, LinuxI2CDevice, LinuxI2CMessage};
 
+use crate::common::{I2cOp, SmbusOp, ProbeMethod, ResponseKind};
+use crate::server::backend::I2cDevice;
+use crate::eh1;
 use crate::error::Error;
 
+/// SMBus packet error checking ioctl request (not exposed by i2cdev)
+const I2C_PEC: libc::c_ulong = 0x0708;
+
 pub struct I2c {
+    path: String,
     dev: I2cdev,
+    bus: LinuxI2CBus,
+    /// Device handle (and address) for SMBus operations
+    smbus: Option<(u8, LinuxI2CDevice)>,
 }
 
 impl I2c {
     pub fn new(path: &str) -> Result<Self, Error> {
-        let dev = I2cdev::new(path)
-            .map_err(|e| Error::Remote(format!("{:?}", e)) )?;
+        let dev = I2cdev::new(path)?;
+
+        // Bus handle for combined (I2C_RDWR) transactions
+        let bus = LinuxI2CBus::new(path)?;
+
+        Ok(Self{path: path.to_owned(), dev, bus, smbus: None})
+    }
+
+    /// Fetch an SMBus device handle for the provided address
+    fn smbus_dev(&mut self, addr: u8) -> Result<&mut LinuxI2CDevice, Error> {
+        let reopen = match &self.smbus {
+            Some((a, _d)) => *a != addr,
+            None => true,
+        };
+        if reopen {
+            self.smbus = None;
+            self.smbus = Some((addr, LinuxI2CDevice::new(&self.path, addr as u16)?));
+        }
 
-        Ok(Self{dev})
+        match &mut self.smbus {
+            Some((_a, d)) => Ok(d),
+            None => unreachable!(),
+        }
+    }
+
+    /// Probe for a device at the provided address using SMBus quick write or read byte
+    /// commands (as `i2cdetect` does), addresses in use by kernel drivers are not probed
+    /// and reported as not responding
+    pub fn probe(&mut self, addr: u8, method: ProbeMethod) -> Result<bool, Error> {
+        let dev = match self.smbus_dev(addr) {
+            Ok(d) => d,
+            Err(e) => {
+                debug!("unable to probe i2c address 0x{:02x}: {:?}", addr, e);
+                return Ok(false);
+            }
+        };
+
+        let r = match method.for_address(addr) {
+            ProbeMethod::Read => dev.smbus_read_byte().map(|_| () ),
+            _ => dev.smbus_write_quick(false),
+        };
+
+        Ok(r.is_ok())
+    }
+
+    /// Execute an SMBus operation using the kernel SMBus interface
+    pub fn smbus(&mut self, addr: u8, pec: bool, op: &SmbusOp) -> Result<ResponseKind, Error> {
+        let dev = self.smbus_dev(addr)?;
+
+        set_pec(dev, pec)?;
+
+        let resp = match op {
+            SmbusOp::ReadByte{command} => ResponseKind::SmbusByte(dev.smbus_read_byte_data(*command)?),
+            SmbusOp::WriteByte{command, value} => {
+                dev.smbus_write_byte_data(*command, *value)?;
+                ResponseKind::Ok
+            },
+            SmbusOp::ReadWord{command} => ResponseKind::SmbusWord(dev.smbus_read_word_data(*command)?),
+            SmbusOp::WriteWord{command, value} => {
+                dev.smbus_write_word_data(*command, *value)?;
+                ResponseKind::Ok
+            },
+            SmbusOp::ReadBlock{command} => ResponseKind::SmbusBlock(dev.smbus_read_block_data(*command)?),
+            SmbusOp::WriteBlock{command, data} => {
+                dev.smbus_write_block_data(*command, data)?;
+                ResponseKind::Ok
+            },
+            SmbusOp::ProcessCall{command, value} => ResponseKind::SmbusWord(dev.smbus_process_word(*command, *value)?),
+        };
+
+        Ok(resp)
+    }
+
+    /// Execute a sequence of operations as a single I2C_RDWR transfer, with repeated
+    /// starts between operations
+    pub fn transaction(&mut self, addr: u8, ops: &[I2cOp]) -> Result<Vec<Vec<u8>>, Error> {
+        let mut data: Vec<Vec<u8>> = ops.iter().map(|op| {
+            match op {
+                I2cOp::Write(d) => d.clone(),
+                I2cOp::Read(n) => vec![0u8; *n as usize],
+            }
+        }).collect();
+
+        {
+            let mut msgs: Vec<_> = ops.iter().zip(data.iter_mut()).map(|(op, d)| {
+                let m = match op {
+                    I2cOp::Write(_) => LinuxI2CMessage::write(d),
+                    I2cOp::Read(_) => LinuxI2CMessage::read(d),
+                };
+                m.with_address(addr as u16)
+            }).collect();
+
+            self.bus.transfer(&mut msgs)?;
+        }
+
+        let data = ops.iter().zip(data).map(|(op, d)| {
+            match op {
+                I2cOp::Read(_) => d,
+                _ => vec![],
+            }
+        }).collect();
+
+        Ok(data)
     }
 }
 
 use embedded_hal::blocking::i2c;
+use embedded_hal_1 as hal1;
 
 impl i2c::Read for I2c {
     type Error = LinuxI2CError;
 
     fn read(&mut self, addr: u8, buff: &mut [u8]) -> Result<(), Self::Error> {
-        self.dev.read(addr, buff)
+        i2c::Read::read(&mut self.dev, addr, buff)
     }
 }
 
@@ -30,7 +146,7 @@ impl i2c::Write for I2c {
     type Error = LinuxI2CError;
 
     fn write(&mut self, addr: u8, data: &[u8]) -> Result<(), Self::Error> {
-        self.dev.write(addr, data)
+        i2c::Write::write(&mut self.dev, addr, data)
     }
 }
 
@@ -39,6 +155,60 @@ impl i2c::WriteRead for I2c {
     type Error = LinuxI2CError;
 
     fn write_read(&mut self, addr: u8, data: &[u8], buff: &mut [u8]) -> Result<(), Self::Error> {
-        self.dev.write_read(addr, data, buff)
+        i2c::WriteRead::write_read(&mut self.dev, addr, data, buff)
+    }
+}
+
+impl I2cDevice for I2c {
+    fn read(&mut self, addr: u8, buff: &mut [u8]) -> Result<(), Error> {
+        i2c::Read::read(self, addr, buff).map_err(Error::from)
+    }
+
+    fn write(&mut self, addr: u8, data: &[u8]) -> Result<(), Error> {
+        i2c::Write::write(self, addr, data).map_err(Error::from)
+    }
+
+    fn write_read(&mut self, addr: u8, data: &[u8], buff: &mut [u8]) -> Result<(), Error> {
+        i2c::WriteRead::write_read(self, addr, data, buff).map_err(Error::from)
+    }
+
+    fn transaction(&mut self, addr: u8, ops: &[I2cOp]) -> Result<Vec<Vec<u8>>, Error> {
+        I2c::transaction(self, addr, ops)
+    }
+
+    fn probe(&mut self, addr: u8, method: ProbeMethod) -> Result<bool, Error> {
+        I2c::probe(self, addr, method)
     }
-}
\ No newline at end of file
+
+    fn smbus(&mut self, addr: u8, pec: bool, op: &SmbusOp) -> Result<ResponseKind, Error> {
+        I2c::smbus(self, addr, pec, op)
+    }
+}
+
+impl hal1::i2c::ErrorType for I2c {
+    type Error = Error;
+}
+
+impl i2c::Transactional for I2c {
+    type Error = Error;
+
+    fn exec<'a>(&mut self, address: u8, operations: &mut [i2c::Operation<'a>]) -> Result<(), Error> {
+        let data = self.transaction(address, &eh1::i2c_transactional_ops(operations))?;
+        eh1::i2c_transactional_results(operations, &data)
+    }
+}
+
+impl hal1::i2c::I2c for I2c {
+    fn transaction(&mut self, address: u8, operations: &mut [hal1::i2c::Operation<'_>]) -> Result<(), Error> {
+        let data = I2c::transaction(self, address, &eh1::i2c_ops(operations))?;
+        eh1::i2c_results(operations, &data)
+    }
+}
+
+/// Enable or disable SMBus packet error checking for a device handle
+fn set_pec(dev: &LinuxI2CDevice, pec: bool) -> Result<(), Error> {
+    // Safe as I2C_PEC takes an integer argument
+    let r = unsafe { libc::ioctl(dev.as_raw_fd(), I2C_PEC as _, pec as libc::c_ulong) };
+    Errno::result(r)?;
+    Ok(())
+}
@@ -4,6 +4,7 @@ use futures::future::{ok, err};
 
 use crate::common::*;
 use crate::manager::Manager;
+use crate::server::backend::{Backend, SpiDevice, I2cDevice, PinDevice, SerialDevice, PwmDevice, AdcDevice, CanDevice};
 use crate::error::Error;
 
 pub mod i2c;
@@ -12,9 +13,20 @@ pub mod spi;
 pub use spi::Spi;
 pub mod pin;
 pub use pin::Pin;
+pub mod serial;
+pub use serial::Serial;
+pub mod pwm;
+pub use pwm::Pwm;
+pub mod adc;
+pub use adc::Adc;
+pub mod can;
+pub use can::Can;
+pub mod delay;
+pub use delay::Delay;
 
 
 /// Fake client impl for connecting to local devices
+#[derive(Default)]
 pub struct Client {
     _reserved: (),
 }
@@ -30,6 +42,10 @@ impl Manager for Client {
     type Spi = Spi;
     type Pin = Pin;
     type I2c = I2c;
+    type Serial = Serial;
+    type Pwm = Pwm;
+    type Adc = Adc;
+    type Can = Can;
 
     /// Connect to a new Spi instance
     fn spi(&mut self, path: &str, baud: u32, mode: SpiMode) -> Box<Future<Item=Spi, Error=Error>+ Send> {
@@ -60,4 +76,74 @@ impl Manager for Client {
         };
         Box::new(d)
     }
+
+    /// Connect to a new Serial instance
+    fn serial(&mut self, path: &str, config: SerialConnect) -> Box<Future<Item=Serial, Error=Error> + Send> {
+        debug!("attempting connection to Serial port: {}", path);
+        let d = match Serial::new(path, &config) {
+            Ok(d) => ok(d),
+            Err(e) => err(e),
+        };
+        Box::new(d)
+    }
+
+    /// Connect to a new Pwm instance
+    fn pwm(&mut self, path: &str) -> Box<Future<Item=Pwm, Error=Error> + Send> {
+        debug!("attempting connection to PWM channel: {}", path);
+        let d = match Pwm::new(path) {
+            Ok(d) => ok(d),
+            Err(e) => err(e),
+        };
+        Box::new(d)
+    }
+
+    /// Connect to a new Adc instance
+    fn adc(&mut self, path: &str) -> Box<Future<Item=Adc, Error=Error> + Send> {
+        debug!("attempting connection to ADC: {}", path);
+        let d = match Adc::new(path) {
+            Ok(d) => ok(d),
+            Err(e) => err(e),
+        };
+        Box::new(d)
+    }
+
+    /// Connect to a new Can instance
+    fn can(&mut self, path: &str) -> Box<Future<Item=Can, Error=Error> + Send> {
+        debug!("attempting connection to CAN interface: {}", path);
+        let d = match Can::new(path) {
+            Ok(d) => ok(d),
+            Err(e) => err(e),
+        };
+        Box::new(d)
+    }
+}
+
+impl Backend for Client {
+    fn spi(&mut self, path: &str, baud: u32, mode: SpiMode) -> Result<Box<SpiDevice>, Error> {
+        Ok(Box::new(Spi::new(path, baud, mode)?))
+    }
+
+    fn i2c(&mut self, path: &str) -> Result<Box<I2cDevice>, Error> {
+        Ok(Box::new(I2c::new(path)?))
+    }
+
+    fn pin(&mut self, path: &str, mode: PinMode) -> Result<Box<PinDevice>, Error> {
+        Ok(Box::new(Pin::new(path, mode)?))
+    }
+
+    fn serial(&mut self, path: &str, config: &SerialConnect) -> Result<Box<SerialDevice>, Error> {
+        Ok(Box::new(Serial::new(path, config)?))
+    }
+
+    fn pwm(&mut self, path: &str) -> Result<Box<PwmDevice>, Error> {
+        Ok(Box::new(Pwm::new(path)?))
+    }
+
+    fn adc(&mut self, path: &str) -> Result<Box<AdcDevice>, Error> {
+        Ok(Box::new(Adc::new(path)?))
+    }
+
+    fn can(&mut self, path: &str) -> Result<Box<CanDevice>, Error> {
+        Ok(Box::new(Can::new(path)?))
+    }
 }
@@ -1,70 +1,526 @@
-use embedded_hal::digital;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::os::unix::io::AsRawFd;
+
+use embedded_hal::digital::v2 as digital;
+use embedded_hal_1 as hal1;
 
 use linux_embedded_hal::{Pin as PinDev};
-use linux_embedded_hal::sysfs_gpio::Direction;
+use linux_embedded_hal::sysfs_gpio::{Direction, Edge as PinEdge};
+
+use gpio_cdev::{Chip, Line, LineHandle, LineEventHandle, LineRequestFlags, EventRequestFlags, EventType};
+use nix::poll::{poll, PollFd, PollFlags};
 
-use crate::common::PinMode;
+use crate::common::{PinMode, PinOptions, PinEvent, Edge, Bias};
+use crate::server::backend::PinDevice;
 use crate::error::Error;
 
+/// Interval at which subscription threads check for cancellation
+const POLL_TIMEOUT_MS: isize = 100;
+
+/// Default consumer label for character device lines
+const DEFAULT_LABEL: &str = "remote-hal";
+
+// Line bias flags (from linux/gpio.h), not yet exposed by gpio-cdev
+const GPIOHANDLE_REQUEST_BIAS_PULL_UP: u32 = 1 << 5;
+const GPIOHANDLE_REQUEST_BIAS_PULL_DOWN: u32 = 1 << 6;
+const GPIOHANDLE_REQUEST_BIAS_DISABLE: u32 = 1 << 7;
+
+/// Local GPIO pin
+///
+/// Paths of the form `gpiochipN:OFFSET` (or `/dev/gpiochipN:OFFSET`) use the GPIO
+/// character device, all other paths use the (deprecated) sysfs GPIO interface.
 pub struct Pin {
-    dev: PinDev,
+    backend: Backend,
+    output: bool,
+    subscription: Option<Subscription>,
+}
+
+enum Backend {
+    Sysfs(PinDev),
+    Cdev(Cdev),
+}
+
+struct Cdev {
+    line: Line,
+    flags: LineRequestFlags,
+    label: String,
+    handle: CdevHandle,
+    edge: Option<EventRequestFlags>,
+    /// Last value written, restored when the line is re-requested as an output
+    value: u8,
+}
+
+/// Character device lines are either requested as plain handles or for events,
+/// event handles are shared with the subscription thread
+enum CdevHandle {
+    Line(LineHandle),
+    Events(Arc<Mutex<LineEventHandle>>),
+    None,
+}
+
+/// Running edge event subscription, stopped on drop
+struct Subscription {
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
 }
 
 impl Pin {
     pub fn new(path: &str, mode: PinMode) -> Result<Self, Error> {
+        let backend = match parse_cdev_path(path) {
+            Some((chip, offset)) => Backend::Cdev(Cdev::new(&chip, offset, &mode)?),
+            None => Backend::Sysfs(sysfs_pin(path, &mode)?),
+        };
+
+        let output = match mode {
+            PinMode::Output(_) => true,
+            PinMode::Input(_) => false,
+        };
 
-        // Patch to export if pin follows a sensible layout
-        let p = path.replace("/sys/class/gpio/gpio", "");
-        if let Ok(id) = p.parse::<u64>() {
-            let dev = PinDev::new(id);
-            dev.export()?;
+        Ok(Self{backend, output, subscription: None})
+    }
+
+    /// Subscribe to edge events on the pin, calling the provided handler for each event.
+    ///
+    /// This replaces any existing subscription. Character device events carry kernel
+    /// timestamps, sysfs events are timestamped (relative to the UNIX epoch) on detection.
+    pub fn subscribe<F>(&mut self, edge: Edge, handler: F) -> Result<(), Error>
+    where
+        F: FnMut(PinEvent) + Send + 'static,
+    {
+        if self.output {
+            return Err(Error::NotPermitted("cannot subscribe to an output pin".to_owned()));
         }
 
-        let dev = PinDev::from_path(path)
-            .map_err(|e| Error::Remote(format!("{:?}", e)) )?;
+        self.unsubscribe();
+
+        let running = Arc::new(AtomicBool::new(true));
 
-        // export fails because you can't open the path before exporting...
-        // docs recommend using pin by number...
-        //dev.export()?;
+        let handle = match &mut self.backend {
+            Backend::Sysfs(dev) => subscribe_sysfs(dev, edge, running.clone(), handler)?,
+            Backend::Cdev(dev) => subscribe_cdev(dev, edge, running.clone(), handler)?,
+        };
 
-        match mode {
-            PinMode::Input => dev.set_direction(Direction::In)?,
-            PinMode::Output => dev.set_direction(Direction::Out)?,
+        self.subscription = Some(Subscription{running, handle: Some(handle)});
+
+        Ok(())
+    }
+
+    /// Cancel an existing edge event subscription
+    pub fn unsubscribe(&mut self) {
+        if self.subscription.take().is_none() {
+            return;
         }
 
-        Ok(Self{dev})
+        match &mut self.backend {
+            Backend::Sysfs(dev) => {
+                let _ = dev.set_edge(PinEdge::NoInterrupt);
+            },
+            Backend::Cdev(dev) => {
+                if let Err(e) = dev.request_line() {
+                    error!("error re-requesting gpio line: {:?}", e);
+                }
+            },
+        }
+    }
+
+    fn get(&self) -> Result<bool, Error> {
+        match &self.backend {
+            Backend::Sysfs(dev) => Ok(dev.get_value()? != 0),
+            Backend::Cdev(dev) => dev.get(),
+        }
+    }
+
+    fn set(&mut self, value: bool) -> Result<(), Error> {
+        match &mut self.backend {
+            Backend::Sysfs(dev) => Ok(dev.set_value(value as u8)?),
+            Backend::Cdev(dev) => dev.set(value),
+        }
     }
 }
 
 impl Drop for Pin {
     fn drop(&mut self) {
-        // unexport disabled as export doesn't _really_ work
-        self.dev.unexport().unwrap();
+        self.unsubscribe();
+
+        // Character device lines are released when the handle is dropped
+        if let Backend::Sysfs(dev) = &self.backend {
+            if let Err(e) = dev.unexport() {
+                warn!("error unexporting pin: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Parse character device paths of the form `gpiochipN:OFFSET` or `/dev/gpiochipN:OFFSET`
+fn parse_cdev_path(path: &str) -> Option<(String, u32)> {
+    let mut parts = path.rsplitn(2, ':');
+    let offset = parts.next()?.parse::<u32>().ok()?;
+    let chip = parts.next()?;
+
+    let chip = chip.trim_start_matches("/dev/");
+    if !chip.starts_with("gpiochip") {
+        return None;
+    }
+
+    Some((format!("/dev/{}", chip), offset))
+}
+
+fn sysfs_pin(path: &str, mode: &PinMode) -> Result<PinDev, Error> {
+    // Patch to export if pin follows a sensible layout
+    let p = path.replace("/sys/class/gpio/gpio", "");
+    if let Ok(id) = p.parse::<u64>() {
+        let dev = PinDev::new(id);
+        dev.export()?;
+    }
+
+    let dev = PinDev::from_path(path)?;
+
+    // export fails because you can't open the path before exporting...
+    // docs recommend using pin by number...
+    //dev.export()?;
+
+    let opts = mode.options();
+    if opts.bias != Bias::AsIs || opts.open_drain || opts.label.is_some() {
+        warn!("sysfs gpio does not support bias, open-drain or label options (pin: {})", path);
+    }
+
+    match mode {
+        PinMode::Input(_) => dev.set_direction(Direction::In)?,
+        PinMode::Output(_) => dev.set_direction(Direction::Out)?,
+    }
+
+    dev.set_active_low(opts.active_low)?;
+
+    Ok(dev)
+}
+
+fn subscribe_sysfs<F>(dev: &mut PinDev, edge: Edge, running: Arc<AtomicBool>, mut handler: F) -> Result<JoinHandle<()>, Error>
+where
+    F: FnMut(PinEvent) + Send + 'static,
+{
+    dev.set_edge(match edge {
+        Edge::Rising => PinEdge::RisingEdge,
+        Edge::Falling => PinEdge::FallingEdge,
+        Edge::Both => PinEdge::BothEdges,
+    })?;
+
+    let mut poller = dev.get_poller()?;
+
+    let handle = thread::spawn(move || {
+        while running.load(Ordering::SeqCst) {
+            match poller.poll(POLL_TIMEOUT_MS) {
+                Ok(Some(v)) => {
+                    let edge = if v == 0 { Edge::Falling } else { Edge::Rising };
+                    handler(PinEvent{edge, timestamp: timestamp()});
+                },
+                Ok(None) => (),
+                Err(e) => {
+                    error!("pin poll error: {:?}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(handle)
+}
+
+fn subscribe_cdev<F>(dev: &mut Cdev, edge: Edge, running: Arc<AtomicBool>, mut handler: F) -> Result<JoinHandle<()>, Error>
+where
+    F: FnMut(PinEvent) + Send + 'static,
+{
+    let events = dev.request_events(edge)?;
+    let fd = events.lock().unwrap().as_raw_fd();
+
+    let handle = thread::spawn(move || {
+        while running.load(Ordering::SeqCst) {
+            let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+
+            match poll(&mut fds, POLL_TIMEOUT_MS as i32) {
+                Ok(0) => continue,
+                Ok(_) => (),
+                Err(e) => {
+                    error!("pin poll error: {:?}", e);
+                    break;
+                }
+            }
+
+            let e = match events.lock().unwrap().get_event() {
+                Ok(e) => e,
+                Err(e) => {
+                    error!("pin event error: {:?}", e);
+                    break;
+                }
+            };
+
+            let edge = match e.event_type() {
+                EventType::RisingEdge => Edge::Rising,
+                EventType::FallingEdge => Edge::Falling,
+            };
+
+            handler(PinEvent{edge, timestamp: e.timestamp()});
+        }
+    });
+
+    Ok(handle)
+}
+
+impl Cdev {
+    fn new(chip: &str, offset: u32, mode: &PinMode) -> Result<Self, Error> {
+        let mut chip = Chip::new(chip)?;
+        let line = chip.get_line(offset)?;
+
+        let opts = mode.options();
+
+        let mut flags = match mode {
+            PinMode::Input(_) => LineRequestFlags::INPUT,
+            PinMode::Output(_) => LineRequestFlags::OUTPUT,
+        };
+        flags |= line_flags(opts);
+
+        let label = opts.label.clone().unwrap_or(DEFAULT_LABEL.to_owned());
+
+        let mut dev = Cdev{line, flags, label, handle: CdevHandle::None, edge: None, value: 0};
+        dev.request_line()?;
+
+        Ok(dev)
+    }
+
+    /// (Re)request the line as a plain handle
+    fn request_line(&mut self) -> Result<(), Error> {
+        self.request(None).map(|_h| () )
+    }
+
+    /// Re-request the line for edge events
+    fn request_events(&mut self, edge: Edge) -> Result<Arc<Mutex<LineEventHandle>>, Error> {
+        let edge = match edge {
+            Edge::Rising => EventRequestFlags::RISING_EDGE,
+            Edge::Falling => EventRequestFlags::FALLING_EDGE,
+            Edge::Both => EventRequestFlags::BOTH_EDGES,
+        };
+
+        match self.request(Some(edge))? {
+            Some(h) => Ok(h),
+            None => unreachable!(),
+        }
+    }
+
+    /// Replace the current line request, returning the event handle for event requests.
+    ///
+    /// Lines can only be requested once, so the existing request is released first and
+    /// restored if the new request fails, leaving the pin usable.
+    fn request(&mut self, edge: Option<EventRequestFlags>) -> Result<Option<Arc<Mutex<LineEventHandle>>>, Error> {
+        let previous = self.edge;
+        self.handle = CdevHandle::None;
+
+        match self.request_handle(edge) {
+            Ok(h) => Ok(h),
+            Err(e) => {
+                if let Err(e) = self.request_handle(previous) {
+                    error!("error restoring gpio line request: {:?}", e);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn request_handle(&mut self, edge: Option<EventRequestFlags>) -> Result<Option<Arc<Mutex<LineEventHandle>>>, Error> {
+        let h = match edge {
+            Some(edge) => {
+                // Event requests are always inputs
+                let flags = (self.flags - LineRequestFlags::OUTPUT) | LineRequestFlags::INPUT;
+
+                let h = Arc::new(Mutex::new(self.line.events(flags, edge, &self.label)?));
+                self.handle = CdevHandle::Events(h.clone());
+                Some(h)
+            },
+            None => {
+                let h = self.line.request(self.flags, self.value, &self.label)?;
+                self.handle = CdevHandle::Line(h);
+                None
+            },
+        };
+
+        self.edge = edge;
+
+        Ok(h)
+    }
+
+    fn get(&self) -> Result<bool, Error> {
+        let v = match &self.handle {
+            CdevHandle::Line(h) => h.get_value()?,
+            CdevHandle::Events(h) => h.lock().unwrap().get_value()?,
+            CdevHandle::None => return Err(Error::Io(io::Error::new(io::ErrorKind::NotConnected, "gpio line not requested"))),
+        };
+        Ok(v != 0)
+    }
+
+    fn set(&mut self, value: bool) -> Result<(), Error> {
+        match &self.handle {
+            CdevHandle::Line(h) => {
+                h.set_value(value as u8)?;
+                self.value = value as u8;
+                Ok(())
+            },
+            _ => Err(Error::NotPermitted("gpio line not requested as output".to_owned())),
+        }
+    }
+}
+
+/// Build line request flags from pin options
+fn line_flags(opts: &PinOptions) -> LineRequestFlags {
+    let mut flags = LineRequestFlags::empty();
+
+    if opts.active_low {
+        flags |= LineRequestFlags::ACTIVE_LOW;
+    }
+    if opts.open_drain {
+        flags |= LineRequestFlags::OPEN_DRAIN;
+    }
+
+    let bias = match opts.bias {
+        Bias::AsIs => 0,
+        Bias::Disable => GPIOHANDLE_REQUEST_BIAS_DISABLE,
+        Bias::PullUp => GPIOHANDLE_REQUEST_BIAS_PULL_UP,
+        Bias::PullDown => GPIOHANDLE_REQUEST_BIAS_PULL_DOWN,
+    };
+
+    // Safe as these are valid request flags, gpio-cdev just doesn't name them
+    flags | unsafe { LineRequestFlags::from_bits_unchecked(bias) }
+}
+
+/// Fetch the current time in nanoseconds since the UNIX epoch
+fn timestamp() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64,
+        Err(_) => 0,
     }
 }
 
 impl digital::InputPin for Pin {
-    //type Error = Error;
+    type Error = Error;
 
-    fn is_high(&self) -> bool {
-        self.dev.is_high()
+    fn is_high(&self) -> Result<bool, Error> {
+        self.get()
     }
 
-    fn is_low(&self) -> bool {
-        self.dev.is_low()
+    fn is_low(&self) -> Result<bool, Error> {
+        self.get().map(|v| !v )
     }
 }
 
 impl digital::OutputPin for Pin {
-    //type Error = Error;
+    type Error = Error;
+
+    fn set_high(&mut self) -> Result<(), Error> {
+        self.set(true)
+    }
+
+    fn set_low(&mut self) -> Result<(), Error> {
+        self.set(false)
+    }
+}
+
+impl hal1::digital::ErrorType for Pin {
+    type Error = Error;
+}
+
+impl hal1::digital::InputPin for Pin {
+    fn is_high(&mut self) -> Result<bool, Error> {
+        self.get()
+    }
+
+    fn is_low(&mut self) -> Result<bool, Error> {
+        self.get().map(|v| !v )
+    }
+}
+
+impl hal1::digital::OutputPin for Pin {
+    fn set_high(&mut self) -> Result<(), Error> {
+        self.set(true)
+    }
+
+    fn set_low(&mut self) -> Result<(), Error> {
+        self.set(false)
+    }
+}
 
-    fn set_high(&mut self) {
-        self.dev.set_high();
+impl hal1::digital::StatefulOutputPin for Pin {
+    fn is_set_high(&mut self) -> Result<bool, Error> {
+        self.get()
     }
 
-    fn set_low(&mut self) {
-        self.dev.set_low();
+    fn is_set_low(&mut self) -> Result<bool, Error> {
+        self.get().map(|v| !v )
     }
 }
 
+impl PinDevice for Pin {
+    fn get(&self) -> Result<bool, Error> {
+        Pin::get(self)
+    }
+
+    fn set(&mut self, value: bool) -> Result<(), Error> {
+        Pin::set(self, value)
+    }
+
+    fn subscribe(&mut self, edge: Edge, handler: Box<FnMut(PinEvent) + Send>) -> Result<(), Error> {
+        Pin::subscribe(self, edge, handler)
+    }
+
+    fn unsubscribe(&mut self) {
+        Pin::unsubscribe(self)
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    /// Requires a gpio-mockup chip (ie. `modprobe gpio-mockup gpio_mockup_ranges=-1,4`) with
+    /// debugfs mounted, the chip may be set with `GPIO_MOCKUP_CHIP` (default `gpiochip0`)
+    #[test]
+    #[ignore]
+    fn gpio_mockup_subscribe() {
+        let chip = env::var("GPIO_MOCKUP_CHIP").unwrap_or("gpiochip0".to_owned());
+
+        // Output pins can not be subscribed to, and remain usable (and driven) after the attempt
+        let mut output = Pin::new(&format!("{}:0", chip), PinMode::Output(PinOptions::default())).unwrap();
+        output.set(true).unwrap();
+        assert!(output.subscribe(Edge::Both, |_e| ()).is_err());
+        assert!(output.get().unwrap());
+        output.set(false).unwrap();
+
+        // Input pins deliver edge events and remain readable while subscribed
+        let mut input = Pin::new(&format!("{}:1", chip), PinMode::Input(PinOptions::default())).unwrap();
+        let (tx, rx) = channel();
+        input.subscribe(Edge::Rising, move |e| tx.send(e).unwrap() ).unwrap();
+
+        fs::write(format!("/sys/kernel/debug/gpio-mockup/{}/1", chip), "1").unwrap();
+
+        let e = rx.recv_timeout(Duration::from_secs(1)).expect("timeout waiting for pin event");
+        assert_eq!(e.edge, Edge::Rising);
+        assert!(input.get().unwrap());
+
+        // Unsubscribing returns the line to a plain request
+        input.unsubscribe();
+        assert!(input.get().unwrap());
+    }
+}
@@ -0,0 +1,210 @@
+
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use embedded_hal::{Pwm as PwmTrait, PwmPin};
+
+use crate::common::{Polarity, PwmState};
+use crate::server::backend::PwmDevice;
+use crate::error::Error;
+
+/// Base path for the PWM sysfs class
+const PWM_CLASS_PATH: &str = "/sys/class/pwm";
+
+/// Attempts to wait for exported channel attributes to become available
+const EXPORT_RETRIES: usize = 10;
+
+/// Local PWM channel using the sysfs PWM class
+///
+/// Paths are of the form `pwmchipN:CHANNEL` (or `/sys/class/pwm/pwmchipN:CHANNEL`).
+/// Channels are exported if required and left exported (and in their current state)
+/// on drop, so that outputs persist between connections.
+pub struct Pwm {
+    path: PathBuf,
+}
+
+impl Pwm {
+    pub fn new(path: &str) -> Result<Self, Error> {
+        let (chip, channel) = match parse_pwm_path(path) {
+            Some(v) => v,
+            None => return Err(Error::UnknownDevice(format!("invalid pwm path {} (expected pwmchipN:CHANNEL)", path))),
+        };
+
+        let chip = PathBuf::from(PWM_CLASS_PATH).join(chip);
+        let path = chip.join(format!("pwm{}", channel));
+
+        if !path.exists() {
+            debug!("exporting pwm channel: {}", path.display());
+            fs::write(chip.join("export"), format!("{}", channel))?;
+
+            // Attributes are created asynchronously (and permissions applied by udev)
+            for _i in 0..EXPORT_RETRIES {
+                if fs::metadata(path.join("enable")).map(|m| !m.permissions().readonly() ).unwrap_or(false) {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        Ok(Self{path})
+    }
+
+    /// Set the channel period in nanoseconds
+    pub fn set_period(&mut self, period_ns: u64) -> Result<(), Error> {
+        self.write("period", &format!("{}", period_ns))
+    }
+
+    /// Set the channel duty cycle (active time) in nanoseconds
+    pub fn set_duty(&mut self, duty_ns: u64) -> Result<(), Error> {
+        self.write("duty_cycle", &format!("{}", duty_ns))
+    }
+
+    /// Set the channel polarity, this is not supported by all drivers and
+    /// may only be changed while the channel is disabled
+    pub fn set_polarity(&mut self, polarity: Polarity) -> Result<(), Error> {
+        let p = match polarity {
+            Polarity::Normal => "normal",
+            Polarity::Inversed => "inversed",
+        };
+        self.write("polarity", p)
+    }
+
+    /// Enable or disable the channel output
+    pub fn set_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        self.write("enable", if enabled { "1" } else { "0" })
+    }
+
+    /// Fetch the current channel state
+    pub fn state(&self) -> Result<PwmState, Error> {
+        let polarity = match self.read("polarity")?.as_str() {
+            "inversed" => Polarity::Inversed,
+            _ => Polarity::Normal,
+        };
+
+        Ok(PwmState {
+            period_ns: self.read_u64("period")?,
+            duty_ns: self.read_u64("duty_cycle")?,
+            polarity,
+            enabled: self.read_u64("enable")? != 0,
+        })
+    }
+
+    fn write(&self, attr: &str, value: &str) -> Result<(), Error> {
+        fs::write(self.path.join(attr), value)?;
+        Ok(())
+    }
+
+    fn read(&self, attr: &str) -> Result<String, Error> {
+        let s = fs::read_to_string(self.path.join(attr))?;
+        Ok(s.trim().to_owned())
+    }
+
+    fn read_u64(&self, attr: &str) -> Result<u64, Error> {
+        let s = self.read(attr)?;
+        s.parse().map_err(|_e| Error::invalid_data(format!("invalid pwm {} value: {}", attr, s)) )
+    }
+}
+
+/// Parse a `pwmchipN:CHANNEL` path into chip name and channel
+fn parse_pwm_path(path: &str) -> Option<(String, u32)> {
+    let path = path.trim_start_matches(PWM_CLASS_PATH).trim_start_matches('/');
+
+    let mut parts = path.splitn(2, ':');
+    let chip = parts.next()?;
+    let channel = parts.next()?.parse().ok()?;
+
+    if !chip.starts_with("pwmchip") {
+        return None;
+    }
+
+    Some((chip.to_owned(), channel))
+}
+
+/// Single channel PWM, duty is in nanoseconds (with the maximum duty equal to the period)
+///
+/// As the embedded-hal traits are infallible, sysfs errors cause a panic
+impl PwmPin for Pwm {
+    type Duty = u64;
+
+    fn disable(&mut self) {
+        self.set_enabled(false).unwrap()
+    }
+
+    fn enable(&mut self) {
+        self.set_enabled(true).unwrap()
+    }
+
+    fn get_duty(&self) -> u64 {
+        self.state().unwrap().duty_ns
+    }
+
+    fn get_max_duty(&self) -> u64 {
+        self.state().unwrap().period_ns
+    }
+
+    fn set_duty(&mut self, duty: u64) {
+        Pwm::set_duty(self, duty).unwrap()
+    }
+}
+
+impl PwmTrait for Pwm {
+    type Channel = ();
+    type Time = Duration;
+    type Duty = u64;
+
+    fn disable(&mut self, _channel: ()) {
+        PwmPin::disable(self)
+    }
+
+    fn enable(&mut self, _channel: ()) {
+        PwmPin::enable(self)
+    }
+
+    fn get_period(&self) -> Duration {
+        Duration::from_nanos(self.state().unwrap().period_ns)
+    }
+
+    fn get_duty(&self, _channel: ()) -> u64 {
+        PwmPin::get_duty(self)
+    }
+
+    fn get_max_duty(&self) -> u64 {
+        PwmPin::get_max_duty(self)
+    }
+
+    fn set_duty(&mut self, _channel: (), duty: u64) {
+        PwmPin::set_duty(self, duty)
+    }
+
+    fn set_period<P>(&mut self, period: P)
+    where
+        P: Into<Duration>,
+    {
+        let p = period.into();
+        Pwm::set_period(self, p.as_secs() * 1_000_000_000 + p.subsec_nanos() as u64).unwrap()
+    }
+}
+
+impl PwmDevice for Pwm {
+    fn set_period(&mut self, period_ns: u64) -> Result<(), Error> {
+        Pwm::set_period(self, period_ns)
+    }
+
+    fn set_duty(&mut self, duty_ns: u64) -> Result<(), Error> {
+        Pwm::set_duty(self, duty_ns)
+    }
+
+    fn set_polarity(&mut self, polarity: Polarity) -> Result<(), Error> {
+        Pwm::set_polarity(self, polarity)
+    }
+
+    fn set_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        Pwm::set_enabled(self, enabled)
+    }
+
+    fn state(&self) -> Result<PwmState, Error> {
+        Pwm::state(self)
+    }
+}
@@ -0,0 +1,151 @@
+
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use embedded_hal::serial;
+use serialport::{self, SerialPort, SerialPortSettings};
+
+use crate::common::*;
+use crate::server::backend::SerialDevice;
+use crate::error::Error;
+
+pub struct Serial {
+    port: Box<SerialPort>,
+}
+
+impl Serial {
+    pub fn new(path: &str, config: &SerialConnect) -> Result<Self, Error> {
+        let settings = SerialPortSettings {
+            baud_rate: config.baud,
+            data_bits: match config.data_bits {
+                DataBits::Five => serialport::DataBits::Five,
+                DataBits::Six => serialport::DataBits::Six,
+                DataBits::Seven => serialport::DataBits::Seven,
+                DataBits::Eight => serialport::DataBits::Eight,
+            },
+            flow_control: match config.flow_control {
+                FlowControl::None => serialport::FlowControl::None,
+                FlowControl::Software => serialport::FlowControl::Software,
+                FlowControl::Hardware => serialport::FlowControl::Hardware,
+            },
+            parity: match config.parity {
+                Parity::None => serialport::Parity::None,
+                Parity::Odd => serialport::Parity::Odd,
+                Parity::Even => serialport::Parity::Even,
+            },
+            stop_bits: match config.stop_bits {
+                StopBits::One => serialport::StopBits::One,
+                StopBits::Two => serialport::StopBits::Two,
+            },
+            timeout: Duration::from_millis(0),
+        };
+
+        let port = serialport::open_with_settings(path, &settings)
+            .map_err(io::Error::from)?;
+
+        Ok(Self{port})
+    }
+
+    /// Wrap an already opened serial port
+    pub fn from_port(port: Box<SerialPort>) -> Self {
+        Self{port}
+    }
+
+    /// Read up to `buff.len()` bytes, waiting at most `timeout` for data to arrive
+    pub fn read_timeout(&mut self, buff: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        self.port.set_timeout(timeout).map_err(io::Error::from)?;
+
+        match self.port.read(buff) {
+            Ok(n) => Ok(n),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write all provided data to the serial port
+    pub fn write_all(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.port.write_all(data)?;
+        Ok(())
+    }
+
+    /// Block until all pending output has been written
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.port.flush()?;
+        Ok(())
+    }
+}
+
+impl serial::Read<u8> for Serial {
+    type Error = io::Error;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        let mut b = [0u8; 1];
+
+        match self.port.read(&mut b) {
+            Ok(1) => Ok(b[0]),
+            Ok(_) => Err(nb::Error::WouldBlock),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => Err(nb::Error::WouldBlock),
+            Err(e) => Err(nb::Error::Other(e)),
+        }
+    }
+}
+
+impl serial::Write<u8> for Serial {
+    type Error = io::Error;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        match self.port.write(&[word]) {
+            Ok(1) => Ok(()),
+            Ok(_) => Err(nb::Error::WouldBlock),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Err(nb::Error::WouldBlock),
+            Err(e) => Err(nb::Error::Other(e)),
+        }
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.port.flush().map_err(nb::Error::Other)
+    }
+}
+
+impl SerialDevice for Serial {
+    fn read_timeout(&mut self, buff: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        Serial::read_timeout(self, buff, timeout)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Error> {
+        Serial::write_all(self, data)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Serial::flush(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::posix::TTYPort;
+
+    #[test]
+    fn pty_pair_read_write() {
+        let (master, slave) = TTYPort::pair().expect("error creating pty pair");
+        let mut a = Serial::from_port(Box::new(master));
+        let mut b = Serial::from_port(Box::new(slave));
+
+        a.write_all(b"hello").unwrap();
+        a.flush().unwrap();
+
+        let mut data = Vec::new();
+        let mut buff = [0u8; 16];
+        while data.len() < 5 {
+            let n = b.read_timeout(&mut buff, Duration::from_millis(500)).unwrap();
+            assert!(n > 0, "timeout waiting for data");
+            data.extend_from_slice(&buff[..n]);
+        }
+        assert_eq!(&data[..], b"hello");
+
+        // Reads with no pending data return nothing once the timeout expires
+        let n = b.read_timeout(&mut buff, Duration::from_millis(10)).unwrap();
+        assert_eq!(n, 0);
+    }
+}
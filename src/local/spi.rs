@@ -1,9 +1,15 @@
 use std::io;
+use std::thread;
+use std::time::Duration;
 
 use embedded_hal::blocking::spi;
+use embedded_hal_1 as hal1;
 use linux_embedded_hal::{spidev, Spidev};
+use linux_embedded_hal::spidev::SpidevTransfer;
 
 use crate::common::*;
+use crate::server::backend::SpiDevice;
+use crate::eh1;
 use crate::error::Error;
 
 pub struct Spi {
@@ -47,3 +53,103 @@ impl spi::Write<u8> for Spi {
     }
 }
 
+impl SpiDevice for Spi {
+    fn transfer(&mut self, data: &mut [u8]) -> Result<(), Error> {
+        spi::Transfer::transfer(self, data)?;
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        spi::Write::write(self, data)?;
+        Ok(())
+    }
+
+    /// Execute operations as a single spidev message, so hardware chip select is
+    /// held throughout and delays are applied by the kernel between transfers
+    fn transaction(&mut self, ops: &[SpiOp]) -> Result<Vec<Vec<u8>>, Error> {
+        let tx: Vec<Vec<u8>> = ops.iter().map(|op| {
+            match op {
+                SpiOp::Write(d) | SpiOp::Transfer(d) => d.clone(),
+                _ => vec![0u8; op.data_len()],
+            }
+        }).collect();
+        let mut rx: Vec<Vec<u8>> = tx.iter().map(|d| vec![0u8; d.len()] ).collect();
+
+        {
+            // Delays prior to the first transfer are executed before the message
+            let mut leading = 0u64;
+            let mut transfers = Vec::new();
+
+            for ((op, t), r) in ops.iter().zip(&tx).zip(rx.iter_mut()) {
+                let us = match op {
+                    SpiOp::DelayUs(us) => *us,
+                    _ => {
+                        transfers.push(SpidevTransfer::read_write(t, r));
+                        continue;
+                    }
+                };
+
+                match transfers.last_mut() {
+                    Some(t) => {
+                        let delay = t.delay_usecs as u32 + us;
+                        if delay > u16::MAX as u32 {
+                            return Err(Error::Unsupported(format!("delays over {}us between transfers", u16::MAX)));
+                        }
+                        t.delay_usecs = delay as u16;
+                    },
+                    None => leading += us as u64,
+                }
+            }
+
+            if leading > 0 {
+                thread::sleep(Duration::from_micros(leading));
+            }
+
+            self.dev.0.transfer_multiple(&mut transfers)?;
+        }
+
+        let data = ops.iter().zip(rx).map(|(op, r)| {
+            match op {
+                SpiOp::Transfer(_) | SpiOp::Read(_) => r,
+                _ => vec![],
+            }
+        }).collect();
+
+        Ok(data)
+    }
+}
+
+impl hal1::spi::ErrorType for Spi {
+    type Error = Error;
+}
+
+impl hal1::spi::SpiDevice<u8> for Spi {
+    fn transaction(&mut self, operations: &mut [hal1::spi::Operation<'_, u8>]) -> Result<(), Error> {
+        let data = SpiDevice::transaction(self, &eh1::spi_ops(operations)?)?;
+        eh1::spi_results(operations, &data)
+    }
+}
+
+impl hal1::spi::SpiBus<u8> for Spi {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        eh1::spi_transaction(self, &mut [hal1::spi::Operation::Read(words)])
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+        spi::Write::write(self, words)?;
+        Ok(())
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+        eh1::spi_transaction(self, &mut [hal1::spi::Operation::Transfer(read, write)])
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        spi::Transfer::transfer(self, words)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -8,8 +8,16 @@ pub trait Manager {
     type Spi;
     type I2c;
     type Pin;
+    type Serial;
+    type Pwm;
+    type Adc;
+    type Can;
 
     fn spi(&mut self, path: &str, baud: u32, mode: SpiMode) -> Box<Future<Item=Self::Spi, Error=Error> + Send>;
     fn pin(&mut self, path: &str, mode: PinMode) -> Box<Future<Item=Self::Pin, Error=Error> + Send>;
     fn i2c(&mut self, path: &str) -> Box<Future<Item=Self::I2c, Error=Error> + Send>;
+    fn serial(&mut self, path: &str, config: SerialConnect) -> Box<Future<Item=Self::Serial, Error=Error> + Send>;
+    fn pwm(&mut self, path: &str) -> Box<Future<Item=Self::Pwm, Error=Error> + Send>;
+    fn adc(&mut self, path: &str) -> Box<Future<Item=Self::Adc, Error=Error> + Send>;
+    fn can(&mut self, path: &str) -> Box<Future<Item=Self::Can, Error=Error> + Send>;
 }
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use embedded_hal::adc::{Channel, OneShot};
+
+use futures::prelude::*;
+
+use crate::adc::voltage_channel;
+use crate::common::*;
+use crate::error::Error;
+use super::{Mux, Requester, Binding};
+
+/// Remote ADC
+#[derive(Clone)]
+pub struct Adc {
+    device: String,
+    mux: Mux,
+    /// Disconnects the device once the last handle is dropped
+    _binding: Arc<Binding>,
+}
+
+impl Adc {
+    pub (crate) fn new(device: String, mux: Mux) -> Self {
+        let _binding = Binding::new(&device, mux.clone(), RequestKind::AdcDisconnect);
+        Adc{device, mux, _binding}
+    }
+
+    /// Read a single (raw and scaled) value from the named channel (ie. `voltage0`)
+    pub fn read(&mut self, channel: &str) -> impl Future<Item=AdcReading, Error=Error> {
+        self.mux.do_request(&self.device, RequestKind::AdcRead(AdcRead{channel: channel.to_owned()})).and_then(|resp| {
+            match resp {
+                ResponseKind::AdcRead(r) => Ok(r),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    /// Capture samples from the requested channels using the IIO buffer,
+    /// returning the samples for each channel in the requested order
+    pub fn capture(&mut self, capture: AdcCapture) -> impl Future<Item=Vec<AdcChannelData>, Error=Error> {
+        self.mux.do_request(&self.device, RequestKind::AdcCapture(capture)).and_then(|resp| {
+            match resp {
+                ResponseKind::AdcCapture(d) => Ok(d),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+}
+
+impl<P> OneShot<Adc, i32, P> for Adc
+where
+    P: Channel<Adc, ID=u8>,
+{
+    type Error = Error;
+
+    fn read(&mut self, _pin: &mut P) -> nb::Result<i32, Error> {
+        let r = Adc::read(self, &voltage_channel(P::channel())).wait().map_err(nb::Error::Other)?;
+        Ok(r.raw as i32)
+    }
+}
+
+impl<P> OneShot<Adc, u16, P> for Adc
+where
+    P: Channel<Adc, ID=u8>,
+{
+    type Error = Error;
+
+    fn read(&mut self, _pin: &mut P) -> nb::Result<u16, Error> {
+        let r = Adc::read(self, &voltage_channel(P::channel())).wait().map_err(nb::Error::Other)?;
+        Ok(r.raw as u16)
+    }
+}
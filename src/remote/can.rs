@@ -0,0 +1,148 @@
+
+use std::sync::Arc;
+
+use futures::prelude::*;
+use futures::sync::mpsc::{unbounded, UnboundedReceiver};
+
+use crate::common::*;
+use crate::error::Error;
+use super::{Mux, Requester, Subscriptions, Binding};
+
+/// Timeout for each receive request made by the blocking `receive`
+const RECEIVE_TIMEOUT_MS: u32 = 1000;
+
+/// Remote CAN interface
+#[derive(Clone)]
+pub struct Can {
+    device: String,
+    mux: Mux,
+    /// Disconnects the device once the last handle is dropped
+    _binding: Arc<Binding>,
+    subscriptions: Subscriptions,
+}
+
+/// Stream of frames pushed by the server for a subscribed CAN interface
+pub struct CanFrames {
+    id: u64,
+    rx: UnboundedReceiver<ResponseKind>,
+    subscriptions: Subscriptions,
+}
+
+impl Stream for CanFrames {
+    type Item = CanFrame;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<CanFrame>, Error> {
+        loop {
+            match self.rx.poll() {
+                Ok(Async::Ready(Some(ResponseKind::CanFrame(f)))) => return Ok(Async::Ready(Some(f))),
+                Ok(Async::Ready(Some(_))) => continue,
+                Ok(Async::Ready(None)) | Err(_) => return Ok(Async::Ready(None)),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+impl Drop for CanFrames {
+    fn drop(&mut self) {
+        self.subscriptions.remove(self.id);
+    }
+}
+
+impl Can {
+    pub (crate) fn new(device: String, mux: Mux, subscriptions: Subscriptions) -> Self {
+        let _binding = Binding::new(&device, mux.clone(), RequestKind::CanDisconnect);
+        Can{device, mux, _binding, subscriptions}
+    }
+
+    /// Send a frame
+    pub fn send(&mut self, frame: CanFrame) -> impl Future<Item=(), Error=Error> {
+        self.mux.do_request(&self.device, RequestKind::CanSend(CanSend{frame})).and_then(|resp| {
+            match resp {
+                ResponseKind::Ok => Ok(()),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    /// Set receive filters, an empty list accepts all frames
+    pub fn set_filters(&mut self, filters: Vec<CanFilter>) -> impl Future<Item=(), Error=Error> {
+        self.mux.do_request(&self.device, RequestKind::CanSetFilters(CanFilters{filters})).and_then(|resp| {
+            match resp {
+                ResponseKind::Ok => Ok(()),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    /// Receive a frame, waiting at most `timeout_ms` on the server for one to arrive.
+    ///
+    /// Timeouts should be shorter than the client request timeout.
+    pub fn receive(&mut self, timeout_ms: u32) -> impl Future<Item=Option<CanFrame>, Error=Error> {
+        self.mux.do_request(&self.device, RequestKind::CanReceive(CanReceive{timeout_ms})).and_then(|resp| {
+            match resp {
+                ResponseKind::CanReceive(f) => Ok(f),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    /// Subscribe to frames matching the provided filters, returning a stream of frames pushed by the server.
+    ///
+    /// Only one subscription is active per interface, subscribing again replaces the existing subscription.
+    pub fn frames(&mut self, filters: Vec<CanFilter>) -> impl Future<Item=CanFrames, Error=Error> {
+        let req = Request::new(self.device.clone(), RequestKind::CanSubscribe(CanFilters{filters}));
+        let id = req.id;
+
+        // Register prior to sending so no frames are missed
+        let (tx, rx) = unbounded();
+        self.subscriptions.register(id, tx);
+
+        let subscriptions = self.subscriptions.clone();
+
+        self.mux.send_request(req).then(move |res| {
+            let resp = match res {
+                Err(e) => {
+                    subscriptions.remove(id);
+                    return Err(e)
+                },
+                Ok(r) => r,
+            };
+            match resp {
+                ResponseKind::Ok => Ok(CanFrames{id, rx, subscriptions}),
+                _ => {
+                    subscriptions.remove(id);
+                    Err(Error::InvalidResponse(resp))
+                },
+            }
+        })
+    }
+
+    /// Cancel an existing frame subscription
+    pub fn unsubscribe(&mut self) -> Result<(), Error> {
+        let resp = self.mux.do_request(&self.device, RequestKind::CanUnsubscribe).wait()?;
+        match resp {
+            ResponseKind::Ok => Ok(()),
+             _ => Err(Error::InvalidResponse(resp)),
+        }
+    }
+}
+
+/// Blocking CAN, `receive` polls the server until a frame arrives
+impl embedded_can::blocking::Can for Can {
+    type Frame = CanFrame;
+    type Error = Error;
+
+    fn transmit(&mut self, frame: &CanFrame) -> Result<(), Error> {
+        self.send(frame.clone()).wait()
+    }
+
+    fn receive(&mut self) -> Result<CanFrame, Error> {
+        loop {
+            if let Some(f) = Can::receive(self, RECEIVE_TIMEOUT_MS).wait()? {
+                return Ok(f);
+            }
+        }
+    }
+}
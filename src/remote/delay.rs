@@ -0,0 +1,84 @@
+
+use std::thread;
+use std::time::Duration;
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal_1 as hal1;
+
+use futures::prelude::*;
+
+use crate::common::*;
+use crate::error::Error;
+use super::{Mux, Requester};
+
+/// Maximum delay for a single request, longer delays are split into multiple
+/// requests to remain within server delay limits and client timeouts
+const MAX_REQUEST_US: u64 = 500_000;
+
+/// Remote delay, delays are executed on the server
+///
+/// A delay alone still waits for the response to return to the client, for
+/// accurate timing between operations use a `Sequence` via `Client::sequence`.
+#[derive(Clone)]
+pub struct Delay {
+    mux: Mux,
+    /// Maximum delay per request, limited by the server where advertised
+    max_us: u64,
+}
+
+impl Delay {
+    pub (crate) fn new(mux: Mux, server_max_us: Option<u64>) -> Self {
+        let max_us = server_max_us.unwrap_or(MAX_REQUEST_US).clamp(1, MAX_REQUEST_US);
+        Delay{mux, max_us}
+    }
+
+    /// Delay on the server for the specified time in microseconds
+    pub fn delay(&mut self, us: u32) -> impl Future<Item=(), Error=Error> {
+        self.mux.do_request("", RequestKind::Delay(crate::common::Delay{us})).and_then(|resp| {
+            match resp {
+                ResponseKind::Ok => Ok(()),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    /// Delay on the server for the specified time in microseconds, blocking until
+    /// complete and splitting long delays into requests within the server limit
+    pub fn delay_blocking(&mut self, mut us: u64) -> Result<(), Error> {
+        while us > 0 {
+            let n = us.min(self.max_us);
+            self.delay(n as u32).wait()?;
+            us -= n;
+        }
+
+        Ok(())
+    }
+
+    /// Blocking delay for the infallible embedded-hal traits, on failure the error
+    /// is logged and the delay completed locally so the minimum delay still holds
+    fn delay_or_sleep(&mut self, us: u64) {
+        if let Err(e) = self.delay_blocking(us) {
+            error!("remote delay failed, delaying locally: {}", e);
+            thread::sleep(Duration::from_micros(us));
+        }
+    }
+}
+
+/// As the embedded-hal traits are infallible, request errors are logged and the delay completed locally
+impl DelayMs<u32> for Delay {
+    fn delay_ms(&mut self, ms: u32) {
+        self.delay_or_sleep(ms as u64 * 1000)
+    }
+}
+
+impl DelayUs<u32> for Delay {
+    fn delay_us(&mut self, us: u32) {
+        self.delay_or_sleep(us as u64)
+    }
+}
+
+impl hal1::delay::DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        self.delay_or_sleep((ns as u64).div_ceil(1000))
+    }
+}
@@ -1,27 +1,183 @@
 
+use std::sync::Arc;
+use std::time::Duration;
+
 use embedded_hal::blocking::i2c;
+use embedded_hal_1 as hal1;
 
 use futures::prelude::*;
 
 use crate::common::*;
+use crate::asynch::{self, AsyncResult};
+use crate::eh1;
 use crate::error::Error;
-use super::{Mux, Requester};
+use super::{Mux, Requester, Binding};
 
 #[derive(Clone)]
 pub struct I2c {
     device: String,
     mux: Mux,
+    /// Disconnects the device once the last handle is dropped
+    _binding: Arc<Binding>,
+    pec: bool,
 }
 
 impl I2c {
     pub (crate) fn new(device: String, mux: Mux) -> Self {
-        I2c{device, mux}
+        let _binding = Binding::new(&device, mux.clone(), RequestKind::I2cDisconnect);
+        I2c{device, mux, _binding, pec: false}
+    }
+
+    /// Override the client request timeout for requests from this handle
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.mux.timeout = timeout;
+    }
+
+    /// Override the number of retries for idempotent requests from this handle
+    pub fn set_retries(&mut self, retries: u32) {
+        self.mux.retries = retries;
+    }
+
+    /// Probe the bus for responding device addresses
+    pub fn scan(&mut self, scan: I2cScan) -> impl Future<Item=Vec<u8>, Error=Error> {
+        self.mux.do_request(&self.device, RequestKind::I2cScan(scan)).and_then(|resp| {
+            match resp {
+                ResponseKind::I2cScan(d) => Ok(d),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    /// Enable or disable SMBus Packet Error Checking for subsequent SMBus operations
+    pub fn set_pec(&mut self, pec: bool) {
+        self.pec = pec;
+    }
+
+    /// Read a byte from an SMBus command register
+    pub fn smbus_read_byte(&mut self, addr: u8, command: u8) -> impl Future<Item=u8, Error=Error> {
+        let req = RequestKind::SmbusReadByte(SmbusRead{addr, command, pec: self.pec});
+        self.mux.do_request(&self.device, req).and_then(|resp| {
+            match resp {
+                ResponseKind::SmbusByte(v) => Ok(v),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    /// Write a byte to an SMBus command register
+    pub fn smbus_write_byte(&mut self, addr: u8, command: u8, value: u8) -> impl Future<Item=(), Error=Error> {
+        let req = RequestKind::SmbusWriteByte(SmbusWriteByte{addr, command, value, pec: self.pec});
+        self.mux.do_request(&self.device, req).and_then(|resp| {
+            match resp {
+                ResponseKind::Ok => Ok(()),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    /// Read a word from an SMBus command register
+    pub fn smbus_read_word(&mut self, addr: u8, command: u8) -> impl Future<Item=u16, Error=Error> {
+        let req = RequestKind::SmbusReadWord(SmbusRead{addr, command, pec: self.pec});
+        self.mux.do_request(&self.device, req).and_then(|resp| {
+            match resp {
+                ResponseKind::SmbusWord(v) => Ok(v),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    /// Write a word to an SMBus command register
+    pub fn smbus_write_word(&mut self, addr: u8, command: u8, value: u16) -> impl Future<Item=(), Error=Error> {
+        let req = RequestKind::SmbusWriteWord(SmbusWriteWord{addr, command, value, pec: self.pec});
+        self.mux.do_request(&self.device, req).and_then(|resp| {
+            match resp {
+                ResponseKind::Ok => Ok(()),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    /// Read a block (of up to 32 bytes) from an SMBus command register
+    pub fn smbus_read_block(&mut self, addr: u8, command: u8) -> impl Future<Item=Vec<u8>, Error=Error> {
+        let req = RequestKind::SmbusReadBlock(SmbusRead{addr, command, pec: self.pec});
+        self.mux.do_request(&self.device, req).and_then(|resp| {
+            match resp {
+                ResponseKind::SmbusBlock(d) => Ok(d),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    /// Write a block (of up to 32 bytes) to an SMBus command register
+    pub fn smbus_write_block(&mut self, addr: u8, command: u8, data: &[u8]) -> impl Future<Item=(), Error=Error> {
+        let req = RequestKind::SmbusWriteBlock(SmbusWriteBlock{addr, command, data: Data{data: data.to_vec()}, pec: self.pec});
+        self.mux.do_request(&self.device, req).and_then(|resp| {
+            match resp {
+                ResponseKind::Ok => Ok(()),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    /// Write a word to then read a word from an SMBus command register
+    pub fn smbus_process_call(&mut self, addr: u8, command: u8, value: u16) -> impl Future<Item=u16, Error=Error> {
+        let req = RequestKind::SmbusProcessCall(SmbusWriteWord{addr, command, value, pec: self.pec});
+        self.mux.do_request(&self.device, req).and_then(|resp| {
+            match resp {
+                ResponseKind::SmbusWord(v) => Ok(v),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    /// Execute a sequence of operations (with repeated starts) in a single request,
+    /// returning the data read by each operation (empty for writes)
+    pub fn transaction(&mut self, addr: u8, ops: Vec<I2cOp>) -> impl Future<Item=Vec<Vec<u8>>, Error=Error> {
+        self.mux.do_request(&self.device, RequestKind::I2cTransaction(I2cTransaction{addr, ops})).and_then(|resp| {
+            match resp {
+                ResponseKind::I2cTransaction(d) => Ok(d),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
     }
 }
 
-impl Drop for I2c {
-    fn drop(&mut self) {
-        self.mux.do_request(&self.device, RequestKind::I2cDisconnect).wait().unwrap();
+impl asynch::i2c::Read for I2c {
+    type Error = Error;
+
+    fn read(&mut self, addr: u8, len: usize) -> AsyncResult<Vec<u8>, Error> {
+        Box::new(self.mux.do_request(&self.device, RequestKind::I2cRead(I2cRead{addr, read_len: len as u16})).and_then(|resp| {
+            match resp {
+                ResponseKind::I2cRead(d) => Ok(d),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        }))
+    }
+}
+
+impl asynch::i2c::Write for I2c {
+    type Error = Error;
+
+    fn write(&mut self, addr: u8, data: Vec<u8>) -> AsyncResult<(), Error> {
+        Box::new(self.mux.do_request(&self.device, RequestKind::I2cWrite(I2cWrite{addr, write_data: Data{data}})).and_then(|resp| {
+            match resp {
+                ResponseKind::Ok => Ok(()),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        }))
+    }
+}
+
+impl asynch::i2c::WriteRead for I2c {
+    type Error = Error;
+
+    fn write_read(&mut self, addr: u8, data: Vec<u8>, len: usize) -> AsyncResult<Vec<u8>, Error> {
+        Box::new(self.mux.do_request(&self.device, RequestKind::I2cWriteRead(I2cWriteRead{addr, write_data: Data{data}, read_len: len as u16})).and_then(|resp| {
+            match resp {
+                ResponseKind::I2cRead(d) => Ok(d),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        }))
     }
 }
 
@@ -29,14 +185,9 @@ impl i2c::Read for I2c {
     type Error = Error;
 
     fn read(&mut self, addr: u8, buff: &mut [u8]) -> Result<(), Error> {
-        let resp = self.mux.do_request(&self.device, RequestKind::I2cRead(I2cRead{addr, read_len: buff.len() as u16})).wait()?;
-        match resp {
-            ResponseKind::I2cRead(d) => {
-                buff.clone_from_slice(&d);
-                Ok(())
-            },
-            _ => Err(Error::InvalidResponse(resp)),
-        }
+        let d = asynch::i2c::Read::read(self, addr, buff.len()).wait()?;
+        buff.clone_from_slice(&d);
+        Ok(())
     }
 }
 
@@ -44,11 +195,7 @@ impl i2c::Write for I2c {
     type Error = Error;
 
     fn write(&mut self, addr: u8, data: &[u8]) -> Result<(), Error> {
-        let resp = self.mux.do_request(&self.device, RequestKind::I2cWrite(I2cWrite{addr, write_data: Data{data: data.to_vec()}})).wait()?;
-        match resp {
-            ResponseKind::Ok => Ok(()),
-            _ => Err(Error::InvalidResponse(resp)),
-        }
+        asynch::i2c::Write::write(self, addr, data.to_vec()).wait()
     }
 }
 
@@ -57,13 +204,28 @@ impl i2c::WriteRead for I2c {
     type Error = Error;
 
     fn write_read(&mut self, addr: u8, data: &[u8], buff: &mut [u8]) -> Result<(), Error> {
-        let resp = self.mux.do_request(&self.device, RequestKind::I2cWriteRead(I2cWriteRead{addr, write_data: Data{data: data.to_vec()}, read_len: buff.len() as u16})).wait()?;
-        match resp {
-            ResponseKind::I2cRead(d) => {
-                buff.clone_from_slice(&d);
-                Ok(())
-            },
-            _ => Err(Error::InvalidResponse(resp)),
-        }
-    }
-}
\ No newline at end of file
+        let d = asynch::i2c::WriteRead::write_read(self, addr, data.to_vec(), buff.len()).wait()?;
+        buff.clone_from_slice(&d);
+        Ok(())
+    }
+}
+
+impl hal1::i2c::ErrorType for I2c {
+    type Error = Error;
+}
+
+impl i2c::Transactional for I2c {
+    type Error = Error;
+
+    fn exec<'a>(&mut self, address: u8, operations: &mut [i2c::Operation<'a>]) -> Result<(), Error> {
+        let data = self.transaction(address, eh1::i2c_transactional_ops(operations)).wait()?;
+        eh1::i2c_transactional_results(operations, &data)
+    }
+}
+
+impl hal1::i2c::I2c for I2c {
+    fn transaction(&mut self, address: u8, operations: &mut [hal1::i2c::Operation<'_>]) -> Result<(), Error> {
+        let data = I2c::transaction(self, address, eh1::i2c_ops(operations)).wait()?;
+        eh1::i2c_results(operations, &data)
+    }
+}
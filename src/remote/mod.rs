@@ -1,15 +1,22 @@
 
 use std::net::{SocketAddr};
 use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::{HashMap, HashSet};
 
 use futures::prelude::*;
 use tokio::prelude::*;
 
-use daemon_engine::{TcpConnection};
-use daemon_engine::codecs::json::{JsonCodec};
+use futures::future::{Loop, Shared};
 use rr_mux::{Mux as BaseMux, Connector};
+use futures::sync::mpsc::{unbounded, UnboundedSender, UnboundedReceiver};
+use futures::sync::oneshot;
+use rand::random;
 
 use crate::common::*;
+use crate::codec::Codec;
+use crate::transport::ClientTls;
 use crate::manager::Manager;
 use crate::error::Error;
 
@@ -19,18 +26,252 @@ pub mod i2c;
 use i2c::I2c;
 pub mod pin;
 use pin::Pin;
+pub mod serial;
+use serial::Serial;
+pub mod pwm;
+use pwm::Pwm;
+pub mod adc;
+use adc::Adc;
+pub mod can;
+use can::Can;
+pub mod delay;
+use delay::Delay;
+pub mod session;
+pub use session::{ReconnectPolicy, ConnectionState};
+use session::{Session, Bindings, Closed};
+
+type RequestMux = BaseMux<u64, (), Request, Response, Error, ()>;
+
+/// Default connect and request timeout
+pub const TIMEOUT: Duration = Duration::from_secs(3);
 
-type Mux = BaseMux<u64, (), Request, Response, Error, ()>;
+/// Request multiplexer shared by the client and device handles, with the
+/// timeout and retries applied to requests from each handle
+#[derive(Clone)]
+pub (crate) struct Mux {
+    inner: RequestMux,
+    timeout: Duration,
+    retries: u32,
+    /// Resolves when the current connection is lost, failing pending requests
+    lost: Arc<Mutex<Option<Shared<oneshot::Receiver<()>>>>>,
+    /// Set while connected to the server
+    connected: Arc<AtomicBool>,
+    /// Devices bound via this mux, re-bound on reconnection
+    bindings: Bindings,
+    /// Disconnect requests queued by dropped handles
+    releases: UnboundedSender<(String, RequestKind)>,
+    /// Requests awaiting responses
+    pending: Pending,
+}
 
+impl Mux {
+    fn new(timeout: Duration, retries: u32) -> (Self, UnboundedReceiver<(String, RequestKind)>) {
+        let (releases, rx) = unbounded();
+        let mux = Mux{
+            inner: RequestMux::new(), timeout, retries,
+            lost: Arc::new(Mutex::new(None)),
+            connected: Arc::new(AtomicBool::new(false)),
+            bindings: Bindings::default(),
+            releases,
+            pending: Pending::default(),
+        };
+        (mux, rx)
+    }
 
-pub const TIMEOUT: Duration = Duration::from_secs(3);
+    /// Send disconnect requests queued by dropped handles until the client is closed
+    fn release(&self, rx: UnboundedReceiver<(String, RequestKind)>, closed: Closed) -> impl Future<Item=(), Error=()> {
+        let mux = self.clone();
+
+        rx.for_each(move |(device, disconnect)| {
+            let bindings = mux.bindings.clone();
+
+            // Errors can not be returned to the dropped handle, so are logged and ignored
+            mux.clone().do_request(&device, disconnect.clone()).then(move |r| {
+                match r {
+                    Ok(_) | Err(Error::DeviceNotBound) => (),
+                    Err(Error::Disconnected) => bindings.forget(&device, &disconnect),
+                    Err(e) => warn!("error disconnecting device {}: {}", device, e),
+                }
+                Ok(())
+            })
+        }).select2(closed).then(|_r| Ok(()) )
+    }
+}
+
+/// Device binding shared by clones of a device handle, the device is
+/// disconnected on the server once the last handle is dropped
+pub (crate) struct Binding {
+    device: String,
+    mux: Mux,
+    disconnect: RequestKind,
+}
+
+impl Binding {
+    pub (crate) fn new(device: &str, mux: Mux, disconnect: RequestKind) -> Arc<Self> {
+        Arc::new(Binding{device: device.to_owned(), mux, disconnect})
+    }
+}
+
+impl Drop for Binding {
+    fn drop(&mut self) {
+        // The server releases devices when a connection is lost, so while disconnected
+        // the binding is forgotten (and not re-bound) rather than queueing a request
+        if !self.mux.connected.load(Ordering::SeqCst) {
+            debug!("forgetting binding for device {} while disconnected", self.device);
+            self.mux.bindings.forget(&self.device, &self.disconnect);
+            return;
+        }
+
+        // Disconnects are queued rather than waited on, as handles may be dropped
+        // on the runtime (or reactor) thread the request is executed by
+        if self.mux.releases.unbounded_send((self.device.clone(), self.disconnect.clone())).is_err() {
+            self.mux.bindings.forget(&self.device, &self.disconnect);
+        }
+    }
+}
 
 /// Remote client for connecting to remote-hal server peripherals
 /// 
-/// THIS MUST BE RUN IN A MULTI-THREADED TOKIO CONTEXT
+/// Device handles implement both the blocking `embedded-hal` traits and the
+/// non-blocking `asynch` traits. The blocking traits wait on the underlying
+/// requests, and thus MUST BE RUN IN A MULTI-THREADED TOKIO CONTEXT.
+///
+/// Where `ClientOptions::reconnect` is set the client reconnects after losing
+/// the connection, re-binding connected devices (and event subscriptions) so
+/// existing handles continue to work. Requests issued while disconnected fail
+/// or time out, and device state (ie. pin outputs) is not restored.
 pub struct Client {
-    connection: TcpConnection<JsonCodec<Request, Response, Error>>,
+    connection: Connection,
     mux: Mux,
+    subscriptions: Subscriptions,
+    session: Session,
+    capabilities: Capabilities,
+}
+
+/// Handle used to close the client connection
+struct Connection {
+    close: oneshot::Sender<()>,
+}
+
+impl Connection {
+    fn close(self) {
+        let _ = self.close.send(());
+    }
+}
+
+/// Ids of requests awaiting responses, responses to requests that have since been
+/// dropped (ie. timed out, or sent prior to a reconnection) are discarded as the
+/// underlying mux panics on responses without a receiver
+#[derive(Clone, Default)]
+pub (crate) struct Pending(Arc<Mutex<HashSet<u64>>>);
+
+impl Pending {
+    /// Track a request future, the request is no longer pending once this is dropped
+    fn track<F: Future>(&self, id: u64, inner: F) -> Tracked<F> {
+        self.0.lock().unwrap().insert(id);
+        Tracked{id, pending: self.clone(), inner}
+    }
+
+    /// Deliver a response via the provided function where the request is pending,
+    /// the request can not be dropped while this is executing
+    pub (crate) fn deliver<F: FnOnce() -> Result<(), Error>>(&self, id: u64, f: F) -> Result<(), Error> {
+        let pending = self.0.lock().unwrap();
+        if !pending.contains(&id) {
+            debug!("discarding response to request {} with no pending receiver", id);
+            return Ok(());
+        }
+        f()
+    }
+}
+
+/// Request future tracked as pending until dropped
+struct Tracked<F> {
+    id: u64,
+    pending: Pending,
+    inner: F,
+}
+
+impl <F: Future> Future for Tracked<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        self.inner.poll()
+    }
+}
+
+impl <F> Drop for Tracked<F> {
+    fn drop(&mut self) {
+        // Runs prior to dropping the inner future (and response receiver)
+        self.pending.0.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Registry of event subscriptions, keyed by the id of the subscribing request
+#[derive(Clone, Default)]
+pub (crate) struct Subscriptions(Arc<Mutex<HashMap<u64, UnboundedSender<ResponseKind>>>>);
+
+impl Subscriptions {
+    pub (crate) fn register(&self, id: u64, tx: UnboundedSender<ResponseKind>) {
+        self.0.lock().unwrap().insert(id, tx);
+    }
+
+    pub (crate) fn remove(&self, id: u64) {
+        self.0.lock().unwrap().remove(&id);
+    }
+
+    /// Dispatch a pushed event to the matching subscriber
+    fn dispatch(&self, id: u64, event: ResponseKind) {
+        let mut subs = self.0.lock().unwrap();
+
+        let closed = match subs.get(&id) {
+            Some(tx) => tx.unbounded_send(event).is_err(),
+            None => {
+                debug!("received event for unknown subscription: {}", id);
+                false
+            }
+        };
+
+        // Drop subscriptions where the receiver has gone away
+        if closed {
+            subs.remove(&id);
+        }
+    }
+}
+
+/// Remote client options
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// Wire codec used to communicate with the server
+    pub codec: Codec,
+    /// TLS configuration, the connection is unencrypted if unset
+    pub tls: Option<ClientTls>,
+    /// Reconnection policy, the client does not reconnect if unset
+    pub reconnect: Option<ReconnectPolicy>,
+    /// Timeout for establishing a connection to the server
+    pub connect_timeout: Duration,
+    /// Default timeout for each request, may be overridden per device handle
+    pub request_timeout: Duration,
+    /// Number of times idempotent requests (ie. pings and pin or PWM gets / sets)
+    /// are re-sent following a timeout
+    pub retries: u32,
+    /// Heartbeats sent to detect a dead connection, if unset heartbeats are
+    /// only sent where required by the server
+    pub heartbeat: Option<Heartbeat>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            codec: Codec::Json,
+            tls: None,
+            reconnect: None,
+            connect_timeout: TIMEOUT,
+            request_timeout: TIMEOUT,
+            retries: 0,
+            heartbeat: None,
+        }
+    }
 }
 
 unsafe impl Sync for Client {}
@@ -38,16 +279,46 @@ unsafe impl Send for Client {}
 
 pub trait Requester {
     fn do_request(&mut self, path: &str, req: RequestKind) -> Box<Future<Item=ResponseKind, Error=Error> + Send + 'static>;
+    fn send_request(&mut self, req: Request) -> Box<Future<Item=ResponseKind, Error=Error> + Send + 'static>;
 }
 
 impl Requester for Mux {
     fn do_request(&mut self, path: &str, req: RequestKind) -> Box<Future<Item=ResponseKind, Error=Error> + Send + 'static> {
-        let req = Request::new(path.to_owned(), req);
+        self.send_request(Request::new(path.to_owned(), req))
+    }
+
+    fn send_request(&mut self, req: Request) -> Box<Future<Item=ResponseKind, Error=Error> + Send + 'static> {
         info!("sending request {:?}", req);
-        Box::new(self.request((), req.id, (), req)
-        .timeout(TIMEOUT)
-        .map_err(|e| e.into() )
-        .then(|r| {
+
+        // Requests are not queued while disconnected, as these would otherwise be
+        // sent on reconnection
+        if !self.connected.load(Ordering::SeqCst) {
+            return Box::new(future::err(Error::Disconnected));
+        }
+
+        let mux = self.inner.clone();
+        let pending = self.pending.clone();
+        let timeout = self.timeout;
+        let retries = if req.kind.is_idempotent() { self.retries } else { 0 };
+
+        // Timed out idempotent requests are re-sent with a fresh id, late responses
+        // to earlier attempts are discarded as these are no longer pending
+        let f = future::loop_fn(0, move |attempt| {
+            let id = if attempt == 0 { req.id } else { random() };
+            let req = Request{id, ..req.clone()};
+            pending.track(id, mux.clone().request((), id, (), req)).timeout(timeout).map_err(Error::from).then(move |r| {
+                match r {
+                    Err(Error::Timeout) if attempt < retries => {
+                        warn!("request {} timed out, retrying ({}/{})", id, attempt + 1, retries);
+                        Ok(Loop::Continue(attempt + 1))
+                    },
+                    Err(e) => Err(e),
+                    Ok(v) => Ok(Loop::Break(v)),
+                }
+            })
+        });
+
+        let f = f.then(|r| {
             let resp = match r {
                 Err(e) => return Err(e),
                 Ok(v) => v,
@@ -56,39 +327,72 @@ impl Requester for Mux {
             info!("received response {:?}", resp);
 
             match resp.0.kind {
-                ResponseKind::Error(e) => Err(Error::Remote(e)),
+                ResponseKind::Error(e) => Err(e.into()),
+                ResponseKind::DeviceAlreadyBound => Err(Error::DeviceAlreadyBound),
+                ResponseKind::DeviceNotBound => Err(Error::DeviceNotBound),
                 _ => Ok(resp.0.kind),
             }
-        }))
+        });
+
+        // Fail immediately if the connection is lost rather than waiting for the timeout
+        let lost = self.lost.lock().unwrap().clone();
+        match lost {
+            Some(l) => {
+                let l = l.then(|_r| Err::<ResponseKind, _>(Error::Disconnected) );
+                Box::new(f.select(l).map(|(v, _)| v ).map_err(|(e, _)| e ))
+            },
+            None => Box::new(f),
+        }
     }
 }
 
 impl Client {
     /// Create a new remote-hal instance
-    pub fn new(addr: SocketAddr) -> impl Future<Item=Self, Error=Error> {
-        info!("client connecting to: {}", addr);
-
-        TcpConnection::<JsonCodec<Request, Response, Error>>::new(&addr, JsonCodec::new()).map_err(|e| e.into() ).timeout(TIMEOUT).map_err(|e| e.into() ).map(|connection| {
-
-            let (tx, rx) = connection.clone().split();
-
-            info!("client connected");
-
-            let mux = Mux::new();
-
-            // Map mux output to tx
-            let m = mux.clone();
-            let tx_handle = tx.send_all(m.map(|(_req_id, _target, msg, _ctx)| msg.req().unwrap() ).map_err(|e| panic!(e) ));
-            tokio::spawn(tx_handle.map(|_v| () ).map_err(|e| panic!(e) ));
+    pub fn new(addr: SocketAddr, options: ClientOptions) -> impl Future<Item=Self, Error=Error> {
+        info!("client connecting to: {} (options: {:?})", addr, options);
+
+        // Resolves when the client is closed, dropping the client without closing
+        // leaves the connection open for any remaining device handles
+        let (close, closed) = oneshot::channel::<()>();
+        let closed: Box<Future<Item=(), Error=()> + Send> = Box::new(closed.then(|r| {
+            use futures::future::Either::{A, B};
+            match r {
+                Ok(_) => A(future::ok::<(), ()>(())),
+                Err(_) => B(future::empty()),
+            }
+        }));
+        let connection = Connection{close};
+
+        let closed = closed.shared();
+
+        let (mux, releases) = Mux::new(options.request_timeout, options.retries);
+        let subscriptions = Subscriptions::default();
+        let session = Session::new(addr, options, mux.clone(), subscriptions.clone(), closed.clone());
+
+        session.connect().then(move |res| {
+            match res {
+                Ok(c) => {
+                    info!("connected to server version {} (capabilities: {:?})", c.server_version, c);
+                    tokio::spawn(mux.release(releases, closed));
+                    Ok(Self{connection, mux, subscriptions, session, capabilities: c})
+                },
+                Err(e) => {
+                    error!("{}", e);
+                    connection.close();
+                    Err(e)
+                },
+            }
+        })
+    }
 
-            // Map rx to mux input
-            let mut m = mux.clone();
-            let rx_handle = rx.for_each(move |resp| m.handle_resp(resp.id, (), resp, ()) );
-            tokio::spawn(rx_handle.map(|_v| () ).map_err(|e| panic!(e) ));
-            
-            Self{connection, mux}
+    /// Set a handler called on connection state changes (ie. to log or count reconnections)
+    pub fn on_state_change<F: Fn(ConnectionState) + Send + 'static>(&mut self, handler: F) {
+        self.session.on_state_change(Box::new(handler));
+    }
 
-        })
+    /// Fetch the capabilities reported by the server on connection
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
     }
 
     // Close a remote-hal instance
@@ -100,18 +404,57 @@ impl Client {
     pub fn request(&mut self, device: &str, request: RequestKind) -> impl Future<Item=ResponseKind, Error=Error> {
         self.mux.do_request(device, request)
     }
+
+    /// Create a delay executed on the server
+    pub fn delay(&self) -> Delay {
+        Delay::new(self.mux.clone(), self.capabilities.max_delay_us)
+    }
+
+    /// Execute a sequence of requests and delays on the server, returning the
+    /// response to each step or the first failure
+    pub fn sequence(&mut self, sequence: Sequence) -> impl Future<Item=Vec<ResponseKind>, Error=Error> {
+        let steps = sequence.steps.len();
+
+        self.mux.do_request("", RequestKind::Sequence(sequence)).and_then(move |resp| {
+            let resps = match resp {
+                ResponseKind::Sequence(r) => r,
+                _ => return Err(Error::InvalidResponse(resp)),
+            };
+
+            let err = match resps.last() {
+                Some(ResponseKind::Error(e)) => Some(e.clone().into()),
+                Some(ResponseKind::DeviceAlreadyBound) => Some(Error::DeviceAlreadyBound),
+                Some(ResponseKind::DeviceNotBound) => Some(Error::DeviceNotBound),
+                _ => None,
+            };
+
+            match err {
+                Some(e) => Err(e),
+                None if resps.len() != steps => Err(Error::InvalidResponse(ResponseKind::Sequence(resps))),
+                None => Ok(resps),
+            }
+        })
+    }
 }
 
 pub enum InitRequest{
     Spi{path: String, baud: u32, mode: SpiMode},
     Pin{path: String, mode: PinMode},
     I2c{path: String},
+    Serial{path: String, config: SerialConnect},
+    Pwm{path: String},
+    Adc{path: String},
+    Can{path: String},
 }
 
 pub enum InitResponse {
     Spi(Spi),
     Pin(Pin),
     I2c(I2c),
+    Serial(Serial),
+    Pwm(Pwm),
+    Adc(Adc),
+    Can(Can),
 }
 
 impl InitResponse {
@@ -135,6 +478,34 @@ impl InitResponse {
             _ => None,
         }
     }
+
+    pub fn serial(self) -> Option<Serial> {
+        match self {
+            InitResponse::Serial(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn pwm(self) -> Option<Pwm> {
+        match self {
+            InitResponse::Pwm(p) => Some(p),
+            _ => None,
+        }
+    }
+
+    pub fn adc(self) -> Option<Adc> {
+        match self {
+            InitResponse::Adc(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn can(self) -> Option<Can> {
+        match self {
+            InitResponse::Can(c) => Some(c),
+            _ => None,
+        }
+    }
 }
 
 impl Client {
@@ -144,13 +515,25 @@ impl Client {
 
         match request {
             InitRequest::Spi{path, baud, mode} => {
-                A(A(self.spi(&path, *baud, mode.clone()).map(|v| InitResponse::Spi(v) )))
+                A(A(self.spi(path, *baud, mode.clone()).map(InitResponse::Spi)))
             },
             InitRequest::Pin{path, mode} => {
-                A(B(self.pin(&path, mode.clone()).map(|v| InitResponse::Pin(v) )))
+                A(B(self.pin(path, mode.clone()).map(InitResponse::Pin)))
             },
             InitRequest::I2c{path} => {
-                B(self.i2c(&path).map(|v| InitResponse::I2c(v) ))
+                B(A(self.i2c(path).map(InitResponse::I2c)))
+            },
+            InitRequest::Serial{path, config} => {
+                B(B(A(self.serial(path, config.clone()).map(InitResponse::Serial))))
+            },
+            InitRequest::Pwm{path} => {
+                B(B(B(A(self.pwm(path).map(InitResponse::Pwm)))))
+            },
+            InitRequest::Adc{path} => {
+                B(B(B(B(A(self.adc(path).map(InitResponse::Adc))))))
+            },
+            InitRequest::Can{path} => {
+                B(B(B(B(B(self.can(path).map(InitResponse::Can))))))
             },
         }
     }
@@ -168,13 +551,17 @@ impl Manager for Client {
     type Spi = Spi;
     type Pin = Pin;
     type I2c = I2c;
+    type Serial = Serial;
+    type Pwm = Pwm;
+    type Adc = Adc;
+    type Can = Can;
 
     /// Connect to a new Spi instance
     fn spi(&mut self, path: &str, baud: u32, mode: SpiMode) -> Box<Future<Item=Spi, Error=Error> + Send> {
         debug!("attempting connection to SPI device: {}", path);
         let device = path.to_owned();
         let mux = self.mux.clone();
-        Box::new(self.mux.do_request(path, RequestKind::SpiConnect(SpiConnect{baud, mode}))
+        Box::new(self.mux.do_request(path, RequestKind::SpiConnect(SpiConnect{baud, mode: Some(mode)}))
         .then(|res| {
             let resp = match res {
                 Err(e) => return Err(e),
@@ -192,6 +579,7 @@ impl Manager for Client {
         debug!("attempting connection to Pin: {}", path);
         let device = path.to_owned();
         let mux = self.mux.clone();
+        let subscriptions = self.subscriptions.clone();
         Box::new(self.mux.do_request(path, RequestKind::PinConnect(mode))
         .then(|res| {
             let resp = match res {
@@ -199,7 +587,7 @@ impl Manager for Client {
                 Ok(r) => r,
             };
             match resp {
-                ResponseKind::Ok => Ok(Pin::new(device, mux)),
+                ResponseKind::Ok => Ok(Pin::new(device, mux, subscriptions)),
                 _ => Err(Error::InvalidResponse(resp)),
             }
         }))
@@ -222,4 +610,77 @@ impl Manager for Client {
             }
         }))
     }
+
+    /// Connect to a new Serial instance
+    fn serial(&mut self, path: &str, config: SerialConnect) -> Box<Future<Item=Serial, Error=Error> + Send> {
+        debug!("attempting connection to Serial port: {}", path);
+        let device = path.to_owned();
+        let mux = self.mux.clone();
+        Box::new(self.mux.do_request(path, RequestKind::SerialConnect(config))
+        .then(|res| {
+            let resp = match res {
+                Err(e) => return Err(e),
+                Ok(r) => r,
+            };
+            match resp {
+                ResponseKind::Ok => Ok(Serial::new(device, mux)),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        }))
+    }
+
+    /// Connect to a new Pwm instance
+    fn pwm(&mut self, path: &str) -> Box<Future<Item=Pwm, Error=Error> + Send> {
+        debug!("attempting connection to PWM channel: {}", path);
+        let device = path.to_owned();
+        let mux = self.mux.clone();
+        Box::new(self.mux.do_request(path, RequestKind::PwmConnect)
+        .then(|res| {
+            let resp = match res {
+                Err(e) => return Err(e),
+                Ok(r) => r,
+            };
+            match resp {
+                ResponseKind::Ok => Ok(Pwm::new(device, mux)),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        }))
+    }
+
+    /// Connect to a new Adc instance
+    fn adc(&mut self, path: &str) -> Box<Future<Item=Adc, Error=Error> + Send> {
+        debug!("attempting connection to ADC: {}", path);
+        let device = path.to_owned();
+        let mux = self.mux.clone();
+        Box::new(self.mux.do_request(path, RequestKind::AdcConnect)
+        .then(|res| {
+            let resp = match res {
+                Err(e) => return Err(e),
+                Ok(r) => r,
+            };
+            match resp {
+                ResponseKind::Ok => Ok(Adc::new(device, mux)),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        }))
+    }
+
+    /// Connect to a new Can instance
+    fn can(&mut self, path: &str) -> Box<Future<Item=Can, Error=Error> + Send> {
+        debug!("attempting connection to CAN interface: {}", path);
+        let device = path.to_owned();
+        let mux = self.mux.clone();
+        let subscriptions = self.subscriptions.clone();
+        Box::new(self.mux.do_request(path, RequestKind::CanConnect)
+        .then(|res| {
+            let resp = match res {
+                Err(e) => return Err(e),
+                Ok(r) => r,
+            };
+            match resp {
+                ResponseKind::Ok => Ok(Can::new(device, mux, subscriptions)),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        }))
+    }
 }
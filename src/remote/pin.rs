@@ -1,69 +1,211 @@
 
-use embedded_hal::digital;
+use std::sync::Arc;
+use std::time::Duration;
+
+use embedded_hal::digital::v2 as digital;
+use embedded_hal_1 as hal1;
 
 use futures::prelude::*;
+use futures::sync::mpsc::{unbounded, UnboundedReceiver};
 
 use crate::common::*;
+use crate::asynch::{self, AsyncResult};
 use crate::error::Error;
-use super::{Mux, Requester};
+use super::{Mux, Requester, Subscriptions, Binding};
 
 #[derive(Clone)]
 pub struct Pin {
     device: String,
     mux: Mux,
+    /// Disconnects the device once the last handle is dropped
+    _binding: Arc<Binding>,
+    subscriptions: Subscriptions,
+}
+
+/// Stream of edge events pushed by the server for a subscribed pin
+pub struct PinEvents {
+    id: u64,
+    rx: UnboundedReceiver<ResponseKind>,
+    subscriptions: Subscriptions,
+}
+
+impl Stream for PinEvents {
+    type Item = PinEvent;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<PinEvent>, Error> {
+        loop {
+            match self.rx.poll() {
+                Ok(Async::Ready(Some(ResponseKind::PinEvent(e)))) => return Ok(Async::Ready(Some(e))),
+                Ok(Async::Ready(Some(_))) => continue,
+                Ok(Async::Ready(None)) | Err(_) => return Ok(Async::Ready(None)),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+impl Drop for PinEvents {
+    fn drop(&mut self) {
+        self.subscriptions.remove(self.id);
+    }
 }
 
 impl Pin {
-    pub (crate) fn new(device: String, mux: Mux) -> Self {
-        Pin{device, mux}
+    pub (crate) fn new(device: String, mux: Mux, subscriptions: Subscriptions) -> Self {
+        let _binding = Binding::new(&device, mux.clone(), RequestKind::PinDisconnect);
+        Pin{device, mux, _binding, subscriptions}
     }
 
-    fn set(&mut self, value: bool) -> Result<(), Error> {
-        let resp = self.mux.do_request(&self.device, RequestKind::PinSet(Value{value})).wait()?;
-        match resp {
-            ResponseKind::Ok => Ok(()),
-             _ => Err(Error::InvalidResponse(resp)),
-        }
+    /// Override the client request timeout for requests from this handle
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.mux.timeout = timeout;
     }
 
-    fn get(&self) -> Result<bool, Error> {
+    /// Override the number of retries for idempotent requests from this handle
+    pub fn set_retries(&mut self, retries: u32) {
+        self.mux.retries = retries;
+    }
+
+    /// Subscribe to edge events on the pin, returning a stream of events pushed by the server.
+    ///
+    /// Only one subscription is active per pin, subscribing again replaces the existing subscription.
+    pub fn events(&mut self, edge: Edge) -> impl Future<Item=PinEvents, Error=Error> {
+        let req = Request::new(self.device.clone(), RequestKind::PinSubscribe(edge));
+        let id = req.id;
+
+        // Register prior to sending so no events are missed
+        let (tx, rx) = unbounded();
+        self.subscriptions.register(id, tx);
+
+        let subscriptions = self.subscriptions.clone();
+
+        self.mux.send_request(req).then(move |res| {
+            let resp = match res {
+                Err(e) => {
+                    subscriptions.remove(id);
+                    return Err(e)
+                },
+                Ok(r) => r,
+            };
+            match resp {
+                ResponseKind::Ok => Ok(PinEvents{id, rx, subscriptions}),
+                _ => {
+                    subscriptions.remove(id);
+                    Err(Error::InvalidResponse(resp))
+                },
+            }
+        })
+    }
+
+    /// Cancel an existing edge event subscription
+    pub fn unsubscribe(&mut self) -> impl Future<Item=(), Error=Error> {
+        self.mux.do_request(&self.device, RequestKind::PinUnsubscribe).and_then(|resp| {
+            match resp {
+                ResponseKind::Ok => Ok(()),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    fn set(&mut self, value: bool) -> AsyncResult<(), Error> {
+        Box::new(self.mux.do_request(&self.device, RequestKind::PinSet(Value{value})).and_then(|resp| {
+            match resp {
+                ResponseKind::Ok => Ok(()),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        }))
+    }
+
+    fn get(&self) -> AsyncResult<bool, Error> {
         let mut mux = self.mux.clone();
-        let resp = mux.do_request(&self.device, RequestKind::PinGet).wait()?;
-        match resp {
-            ResponseKind::PinGet(v) => Ok(v),
-             _ => Err(Error::InvalidResponse(resp)),
-        }
+        Box::new(mux.do_request(&self.device, RequestKind::PinGet).and_then(|resp| {
+            match resp {
+                ResponseKind::PinGet(v) => Ok(v),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        }))
     }
 }
 
-impl Drop for Pin {
-    fn drop(&mut self) {
-        self.mux.do_request(&self.device, RequestKind::PinDisconnect).wait().unwrap();
+impl asynch::digital::InputPin for Pin {
+    type Error = Error;
+
+    fn is_high(&self) -> AsyncResult<bool, Error> {
+        self.get()
+    }
+
+    fn is_low(&self) -> AsyncResult<bool, Error> {
+        Box::new(self.get().map(|v| !v ))
+    }
+}
+
+impl asynch::digital::OutputPin for Pin {
+    type Error = Error;
+
+    fn set_high(&mut self) -> AsyncResult<(), Error> {
+        self.set(true)
+    }
+
+    fn set_low(&mut self) -> AsyncResult<(), Error> {
+        self.set(false)
     }
 }
 
 impl digital::InputPin for Pin {
-    //type Error = Error;
+    type Error = Error;
 
-    fn is_high(&self) -> bool {
-        self.get().unwrap() == true
+    fn is_high(&self) -> Result<bool, Error> {
+        self.get().wait()
     }
 
-    fn is_low(&self) -> bool {
-        self.get().unwrap() == false
+    fn is_low(&self) -> Result<bool, Error> {
+        self.get().wait().map(|v| !v )
     }
 }
 
 impl digital::OutputPin for Pin {
-    //type Error = Error;
+    type Error = Error;
 
-    fn set_high(&mut self) {
-        self.set(true).unwrap();
+    fn set_high(&mut self) -> Result<(), Error> {
+        self.set(true).wait()
     }
 
-    fn set_low(&mut self) {
-        self.set(false).unwrap();
+    fn set_low(&mut self) -> Result<(), Error> {
+        self.set(false).wait()
     }
 }
 
+impl hal1::digital::ErrorType for Pin {
+    type Error = Error;
+}
+
+impl hal1::digital::InputPin for Pin {
+    fn is_high(&mut self) -> Result<bool, Error> {
+        self.get().wait()
+    }
 
+    fn is_low(&mut self) -> Result<bool, Error> {
+        self.get().wait().map(|v| !v )
+    }
+}
+
+impl hal1::digital::OutputPin for Pin {
+    fn set_high(&mut self) -> Result<(), Error> {
+        self.set(true).wait()
+    }
+
+    fn set_low(&mut self) -> Result<(), Error> {
+        self.set(false).wait()
+    }
+}
+
+impl hal1::digital::StatefulOutputPin for Pin {
+    fn is_set_high(&mut self) -> Result<bool, Error> {
+        self.get().wait()
+    }
+
+    fn is_set_low(&mut self) -> Result<bool, Error> {
+        self.get().wait().map(|v| !v )
+    }
+}
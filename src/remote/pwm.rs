@@ -0,0 +1,132 @@
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use embedded_hal::{Pwm as PwmTrait, PwmPin};
+
+use futures::prelude::*;
+
+use crate::common::*;
+use crate::error::Error;
+use super::{Mux, Requester, Binding};
+
+/// Remote PWM channel, times are in nanoseconds
+#[derive(Clone)]
+pub struct Pwm {
+    device: String,
+    mux: Mux,
+    /// Disconnects the device once the last handle is dropped
+    _binding: Arc<Binding>,
+}
+
+impl Pwm {
+    pub (crate) fn new(device: String, mux: Mux) -> Self {
+        let _binding = Binding::new(&device, mux.clone(), RequestKind::PwmDisconnect);
+        Pwm{device, mux, _binding}
+    }
+
+    fn request(&mut self, req: RequestKind) -> impl Future<Item=(), Error=Error> {
+        self.mux.do_request(&self.device, req).and_then(|resp| {
+            match resp {
+                ResponseKind::Ok => Ok(()),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+
+    /// Set the channel period
+    pub fn set_period(&mut self, period_ns: u64) -> impl Future<Item=(), Error=Error> {
+        self.request(RequestKind::PwmSetPeriod(PwmPeriod{period_ns}))
+    }
+
+    /// Set the channel duty cycle (active time), this must not exceed the period
+    pub fn set_duty(&mut self, duty_ns: u64) -> impl Future<Item=(), Error=Error> {
+        self.request(RequestKind::PwmSetDuty(PwmDuty{duty_ns}))
+    }
+
+    /// Set the channel polarity, this may only be changed while disabled
+    pub fn set_polarity(&mut self, polarity: Polarity) -> impl Future<Item=(), Error=Error> {
+        self.request(RequestKind::PwmSetPolarity(polarity))
+    }
+
+    /// Enable or disable the channel output
+    pub fn set_enabled(&mut self, enabled: bool) -> impl Future<Item=(), Error=Error> {
+        self.request(if enabled { RequestKind::PwmEnable } else { RequestKind::PwmDisable })
+    }
+
+    /// Fetch the current channel state
+    pub fn state(&self) -> impl Future<Item=PwmState, Error=Error> {
+        let mut mux = self.mux.clone();
+        mux.do_request(&self.device, RequestKind::PwmGet).and_then(|resp| {
+            match resp {
+                ResponseKind::PwmState(s) => Ok(s),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+}
+
+/// Single channel PWM, duty is in nanoseconds (with the maximum duty equal to the period)
+///
+/// As the embedded-hal traits are infallible, request errors cause a panic
+impl PwmPin for Pwm {
+    type Duty = u64;
+
+    fn disable(&mut self) {
+        self.set_enabled(false).wait().unwrap()
+    }
+
+    fn enable(&mut self) {
+        self.set_enabled(true).wait().unwrap()
+    }
+
+    fn get_duty(&self) -> u64 {
+        self.state().wait().unwrap().duty_ns
+    }
+
+    fn get_max_duty(&self) -> u64 {
+        self.state().wait().unwrap().period_ns
+    }
+
+    fn set_duty(&mut self, duty: u64) {
+        Pwm::set_duty(self, duty).wait().unwrap()
+    }
+}
+
+impl PwmTrait for Pwm {
+    type Channel = ();
+    type Time = Duration;
+    type Duty = u64;
+
+    fn disable(&mut self, _channel: ()) {
+        PwmPin::disable(self)
+    }
+
+    fn enable(&mut self, _channel: ()) {
+        PwmPin::enable(self)
+    }
+
+    fn get_period(&self) -> Duration {
+        Duration::from_nanos(self.state().wait().unwrap().period_ns)
+    }
+
+    fn get_duty(&self, _channel: ()) -> u64 {
+        PwmPin::get_duty(self)
+    }
+
+    fn get_max_duty(&self) -> u64 {
+        PwmPin::get_max_duty(self)
+    }
+
+    fn set_duty(&mut self, _channel: (), duty: u64) {
+        PwmPin::set_duty(self, duty)
+    }
+
+    fn set_period<P>(&mut self, period: P)
+    where
+        P: Into<Duration>,
+    {
+        let p = period.into();
+        Pwm::set_period(self, p.as_secs() * 1_000_000_000 + p.subsec_nanos() as u64).wait().unwrap()
+    }
+}
@@ -0,0 +1,128 @@
+
+use std::sync::Arc;
+use std::collections::VecDeque;
+
+use embedded_hal::serial;
+use embedded_hal::blocking::serial as blocking_serial;
+
+use futures::prelude::*;
+
+use crate::common::*;
+use crate::error::Error;
+use super::{Mux, Requester, Binding};
+
+/// Maximum number of bytes fetched per non-blocking read request
+const READ_CHUNK: u16 = 256;
+
+pub struct Serial {
+    device: String,
+    mux: Mux,
+    /// Disconnects the device once the last handle is dropped
+    _binding: Arc<Binding>,
+    rx_buff: VecDeque<u8>,
+    tx_buff: Vec<u8>,
+}
+
+impl Serial {
+    pub (crate) fn new(device: String, mux: Mux) -> Self {
+        let _binding = Binding::new(&device, mux.clone(), RequestKind::SerialDisconnect);
+        Serial{device, mux, _binding, rx_buff: VecDeque::new(), tx_buff: Vec::new()}
+    }
+
+    /// Read up to `buff.len()` bytes, waiting at most `timeout_ms` on the server for data to arrive
+    pub fn read_timeout(&mut self, buff: &mut [u8], timeout_ms: u32) -> Result<usize, Error> {
+        // Drain locally buffered data first
+        if !self.rx_buff.is_empty() {
+            let n = buff.len().min(self.rx_buff.len());
+            for (b, v) in buff.iter_mut().zip(self.rx_buff.drain(..n)) {
+                *b = v;
+            }
+            return Ok(n);
+        }
+
+        let max_len = buff.len().min(u16::MAX as usize) as u16;
+        let d = self.fetch(max_len, timeout_ms)?;
+
+        // Servers must not return more data than requested
+        if d.len() > buff.len() {
+            return Err(Error::InvalidResponse(ResponseKind::SerialRead(d)));
+        }
+
+        buff[..d.len()].copy_from_slice(&d);
+        Ok(d.len())
+    }
+
+    /// Write all provided data to the serial port
+    pub fn write_all(&mut self, data: &[u8]) -> Result<(), Error> {
+        let resp = self.mux.do_request(&self.device, RequestKind::SerialWrite{write_data: Data{data: data.to_vec()}}).wait()?;
+        match resp {
+            ResponseKind::Ok => Ok(()),
+            _ => Err(Error::InvalidResponse(resp)),
+        }
+    }
+
+    fn fetch(&mut self, max_len: u16, timeout_ms: u32) -> Result<Vec<u8>, Error> {
+        let resp = self.mux.do_request(&self.device, RequestKind::SerialRead(SerialRead{max_len, timeout_ms})).wait()?;
+        match resp {
+            ResponseKind::SerialRead(d) => Ok(d),
+            _ => Err(Error::InvalidResponse(resp)),
+        }
+    }
+
+    fn flush_all(&mut self) -> Result<(), Error> {
+        if !self.tx_buff.is_empty() {
+            let data = std::mem::take(&mut self.tx_buff);
+            self.write_all(&data)?;
+        }
+
+        let resp = self.mux.do_request(&self.device, RequestKind::SerialFlush).wait()?;
+        match resp {
+            ResponseKind::Ok => Ok(()),
+            _ => Err(Error::InvalidResponse(resp)),
+        }
+    }
+}
+
+impl serial::Read<u8> for Serial {
+    type Error = Error;
+
+    fn read(&mut self) -> nb::Result<u8, Error> {
+        if self.rx_buff.is_empty() {
+            let d = self.fetch(READ_CHUNK, 0).map_err(nb::Error::Other)?;
+            self.rx_buff.extend(d);
+        }
+
+        match self.rx_buff.pop_front() {
+            Some(b) => Ok(b),
+            None => Err(nb::Error::WouldBlock),
+        }
+    }
+}
+
+/// Words are buffered locally and sent to the server on `flush`
+impl serial::Write<u8> for Serial {
+    type Error = Error;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Error> {
+        self.tx_buff.push(word);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Error> {
+        self.flush_all().map_err(nb::Error::Other)
+    }
+}
+
+impl blocking_serial::Write<u8> for Serial {
+    type Error = Error;
+
+    fn bwrite_all(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.tx_buff.extend_from_slice(data);
+        let data = std::mem::take(&mut self.tx_buff);
+        self.write_all(&data)
+    }
+
+    fn bflush(&mut self) -> Result<(), Error> {
+        self.flush_all()
+    }
+}
@@ -0,0 +1,464 @@
+
+use std::cmp;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use futures::prelude::*;
+use futures::future::{self, Either, Loop, Shared};
+use futures::sync::oneshot;
+use tokio::prelude::*;
+use tokio::codec::Framed;
+use tokio::timer::{Delay as TimerDelay, Interval};
+
+use crate::common::*;
+use crate::codec::WireCodec;
+use crate::transport::{self, Transport};
+use crate::error::Error;
+use super::{Mux, Requester, Subscriptions, ClientOptions};
+
+/// Attempts to re-bind each device where the server reports it as already bound,
+/// as the server may not yet have released the device from the lost connection
+const REBIND_ATTEMPTS: u32 = 5;
+
+/// Resolves when the client is closed
+pub (crate) type Closed = Shared<Box<Future<Item=(), Error=()> + Send>>;
+
+type StateHandler = Box<Fn(ConnectionState) + Send>;
+
+/// Reconnection policy, the delay between attempts doubles from `backoff` up to `max_backoff`
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Maximum number of attempts per disconnection, unlimited if unset
+    pub max_attempts: Option<u32>,
+    /// Delay before the first attempt
+    pub backoff: Duration,
+    /// Maximum delay between attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: Some(10),
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Client connection state changes, reported via `Client::on_state_change`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    /// The connection to the server was lost
+    Disconnected,
+    /// Attempting to reconnect (starting from attempt 1)
+    Reconnecting{attempt: u32},
+    /// Reconnected to the server and re-bound previously connected devices
+    Connected,
+    /// Reconnected to the server, but the listed devices could not be re-bound
+    Degraded{failed: Vec<String>},
+    /// Reconnection attempts exhausted, the client will not reconnect
+    Failed,
+}
+
+/// Devices (and subscriptions) bound by the client, re-bound on reconnection
+#[derive(Clone, Default)]
+pub (crate) struct Bindings(Arc<Mutex<BindingState>>);
+
+#[derive(Default)]
+struct BindingState {
+    /// Binding requests awaiting responses
+    pending: Vec<Request>,
+    /// Successful binding requests in order
+    bound: Vec<Request>,
+}
+
+impl Bindings {
+    /// Track outgoing requests
+    fn request(&self, req: &Request) {
+        let mut s = self.0.lock().unwrap();
+
+        match &req.kind {
+            RequestKind::SpiConnect(_) | RequestKind::PinConnect(_) | RequestKind::I2cConnect |
+            RequestKind::SerialConnect(_) | RequestKind::PwmConnect | RequestKind::AdcConnect |
+            RequestKind::CanConnect | RequestKind::PinSubscribe(_) | RequestKind::CanSubscribe(_) => {
+                s.pending.push(req.clone());
+            },
+            RequestKind::SpiDisconnect | RequestKind::PinDisconnect | RequestKind::I2cDisconnect |
+            RequestKind::SerialDisconnect | RequestKind::PwmDisconnect | RequestKind::AdcDisconnect |
+            RequestKind::CanDisconnect => {
+                s.bound.retain(|b| b.device != req.device || b.kind.device_kind() != req.kind.device_kind() );
+            },
+            RequestKind::PinUnsubscribe | RequestKind::CanUnsubscribe => {
+                s.bound.retain(|b| b.device != req.device || !is_subscription(&b.kind) );
+            },
+            _ => (),
+        }
+    }
+
+    /// Track responses to binding requests
+    fn response(&self, resp: &Response) {
+        let mut s = self.0.lock().unwrap();
+
+        let req = match s.pending.iter().position(|r| r.id == resp.id ) {
+            Some(i) => s.pending.remove(i),
+            None => return,
+        };
+
+        if let ResponseKind::Ok = resp.kind {
+            let sub = is_subscription(&req.kind);
+            s.bound.retain(|b| b.device != req.device || b.kind.device_kind() != req.kind.device_kind() || is_subscription(&b.kind) != sub );
+            s.bound.push(req);
+        }
+    }
+
+    /// Forget a binding without sending the disconnect request (ie. while disconnected)
+    pub (crate) fn forget(&self, device: &str, disconnect: &RequestKind) {
+        self.request(&Request::new(device.to_owned(), disconnect.clone()));
+    }
+
+    /// Fetch binding requests to re-issue, split into connections and subscriptions
+    fn requests(&self) -> (Vec<Request>, Vec<Request>) {
+        let s = self.0.lock().unwrap();
+
+        s.bound.iter().cloned().partition(|r| !is_subscription(&r.kind) )
+    }
+}
+
+fn is_subscription(kind: &RequestKind) -> bool {
+    matches!(kind, RequestKind::PinSubscribe(_) | RequestKind::CanSubscribe(_))
+}
+
+/// Client session, shared between the client and connection tasks
+#[derive(Clone)]
+pub (crate) struct Session {
+    addr: SocketAddr,
+    options: ClientOptions,
+    mux: Mux,
+    subscriptions: Subscriptions,
+    handler: Arc<Mutex<Option<StateHandler>>>,
+    closed: Closed,
+    /// Abort handle for the current connection, dropping this stops the connection tasks
+    current: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+    /// Time the last message was received from the server
+    received: Arc<Mutex<Instant>>,
+}
+
+impl Session {
+    pub (crate) fn new(addr: SocketAddr, options: ClientOptions, mux: Mux, subscriptions: Subscriptions, closed: Closed) -> Self {
+        Self {
+            addr, options, mux, subscriptions, closed,
+            handler: Arc::new(Mutex::new(None)),
+            current: Arc::new(Mutex::new(None)),
+            received: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Set the connection state change handler
+    pub (crate) fn on_state_change(&self, handler: StateHandler) {
+        *self.handler.lock().unwrap() = Some(handler);
+    }
+
+    fn notify(&self, state: ConnectionState) {
+        info!("client connection state: {:?}", state);
+
+        if let Some(h) = &*self.handler.lock().unwrap() {
+            h(state);
+        }
+    }
+
+    /// Connect to the server and exchange versions, returning the server capabilities
+    pub (crate) fn connect(&self) -> impl Future<Item=Capabilities, Error=Error> {
+        let s = self.clone();
+
+        transport::connect(&self.addr, self.options.tls.clone()).timeout(self.options.connect_timeout).map_err(Error::from).and_then(move |stream| {
+            info!("client connected");
+
+            let dead = s.spawn(stream);
+
+            let (s1, s2) = (s.clone(), s.clone());
+            s.hello().map(move |c| {
+                s1.heartbeat(&c, dead);
+                c
+            }).map_err(move |e| {
+                // Stop the connection without reconnecting
+                s2.current.lock().unwrap().take();
+                e
+            })
+        })
+    }
+
+    /// Spawn tasks to forward requests and responses using the provided transport,
+    /// returning a handle to close the connection if the server is deemed dead
+    fn spawn(&self, stream: Box<Transport>) -> oneshot::Sender<()> {
+        let (tx, rx) = Framed::new(stream, WireCodec::<Request, Response, Error>::client(self.options.codec)).split();
+
+        // Map mux output to tx, tracking device bindings
+        let b = self.mux.bindings.clone();
+        let requests = self.mux.inner.clone().map(move |(_req_id, _target, msg, _ctx)| {
+            let req = msg.req().unwrap();
+            b.request(&req);
+            req
+        }).map_err(|_e| Error::Disconnected );
+        let tx_handle = tx.send_all(requests).map(|_v| () );
+
+        // Map rx to mux input, diverting pushed events to subscribers
+        let mut m = self.mux.inner.clone();
+        let s = self.subscriptions.clone();
+        let b = self.mux.bindings.clone();
+        let p = self.mux.pending.clone();
+        let received = self.received.clone();
+        *received.lock().unwrap() = Instant::now();
+
+        let rx_handle = rx.for_each(move |resp| {
+            *received.lock().unwrap() = Instant::now();
+            b.response(&resp);
+
+            match resp.kind {
+                ResponseKind::Ping if resp.id == 0 => {
+                    trace!("received server heartbeat");
+                    Either::A(future::ok(()))
+                },
+                ResponseKind::PinEvent(_) | ResponseKind::CanFrame(_) => {
+                    s.dispatch(resp.id, resp.kind);
+                    Either::A(future::ok(()))
+                },
+                _ => Either::B(p.deliver(resp.id, || m.handle_resp(resp.id, (), resp, ()) ).into_future()),
+            }
+        });
+
+        // Resolves when heartbeats fail, the sender is dropped where heartbeats are disabled
+        let (dead_tx, dead_rx) = oneshot::channel::<()>();
+        let dead = dead_rx.then(|r| {
+            match r {
+                Ok(_) => Either::A(future::ok::<(), ()>(())),
+                Err(_) => Either::B(future::empty()),
+            }
+        });
+
+        // Resolves when either direction fails, the stream closes or heartbeats fail
+        let lost = tx_handle.select2(rx_handle).then(|r| {
+            match r {
+                Ok(_) => warn!("client connection closed by server"),
+                Err(Either::A((e, _))) | Err(Either::B((e, _))) => error!("client connection error: {}", e),
+            }
+            Ok::<_, ()>(())
+        }).select2(dead).then(|_r| Ok::<_, ()>(()) );
+
+        let (abort_tx, abort_rx) = oneshot::channel::<()>();
+        *self.current.lock().unwrap() = Some(abort_tx);
+
+        // Pending requests fail when the connection ends
+        let (lost_tx, lost_rx) = oneshot::channel::<()>();
+        *self.mux.lost.lock().unwrap() = Some(lost_rx.shared());
+        self.mux.connected.store(true, Ordering::SeqCst);
+
+        let session = self.clone();
+        let handle = lost.select2(abort_rx.then(|_r| Ok::<_, ()>(()) )).select2(self.closed.clone())
+        .then(move |r| {
+            session.mux.connected.store(false, Ordering::SeqCst);
+            let _ = lost_tx.send(());
+
+            match r {
+                // Connection lost, attempt to reconnect
+                Ok(Either::A((Either::A(_), _))) => session.reconnect(),
+                // Aborted or client closed
+                _ => Box::new(future::ok(())) as Box<Future<Item=(), Error=()> + Send>,
+            }
+        });
+
+        tokio::spawn(handle);
+
+        dead_tx
+    }
+
+    /// Spawn a task sending periodic pings, closing the connection via `dead` once
+    /// the configured number of consecutive pings have failed
+    ///
+    /// Heartbeats are sent at the client interval, or the server interval where
+    /// this is shorter (or the client has no heartbeat configured). Where the server
+    /// sends heartbeats the connection is also closed if nothing is received from
+    /// the server for the heartbeat period.
+    fn heartbeat(&self, capabilities: &Capabilities, dead: oneshot::Sender<()>) {
+        let server = capabilities.heartbeat_ms.map(|ms| Duration::from_millis(ms as u64) );
+
+        let heartbeat = match (self.options.heartbeat.clone(), server) {
+            (Some(h), Some(s)) => Heartbeat{interval: cmp::min(h.interval, s), ..h},
+            (Some(h), None) => h,
+            (None, Some(s)) => Heartbeat{interval: s, ..Heartbeat::default()},
+            (None, None) => return,
+        };
+
+        info!("sending heartbeats (interval: {:?}, misses: {})", heartbeat.interval, heartbeat.misses);
+
+        // Pings time out at the heartbeat interval
+        let mut mux = self.mux.clone();
+        mux.timeout = heartbeat.interval;
+        mux.retries = 0;
+
+        let misses = heartbeat.misses;
+        let silence = server.map(|s| Heartbeat{interval: s, misses}.period() );
+        let received = self.received.clone();
+
+        let pings = Interval::new(Instant::now() + heartbeat.interval, heartbeat.interval)
+        .map_err(|_e| () )
+        .fold(0, move |missed, _| {
+            let elapsed = received.lock().unwrap().elapsed();
+            if let Some(p) = silence.filter(|p| elapsed > *p ) {
+                warn!("nothing received from server for {:?} (limit: {:?})", elapsed, p);
+                return Either::A(future::err(()));
+            }
+
+            Either::B(mux.do_request("", RequestKind::Ping).then(move |r| {
+                match r {
+                    Ok(_) => Ok(0),
+                    Err(e) => {
+                        let missed = missed + 1;
+                        warn!("heartbeat missed ({}/{}): {}", missed, misses, e);
+                        if missed < misses { Ok(missed) } else { Err(()) }
+                    },
+                }
+            }))
+        });
+
+        // Stop with the connection
+        let lost = match self.mux.lost.lock().unwrap().clone() {
+            Some(l) => l,
+            None => return,
+        };
+
+        let handle = pings.select2(lost).then(move |r| {
+            if let Err(Either::A(_)) = r {
+                error!("server not responding to heartbeats, closing connection");
+                let _ = dead.send(());
+            }
+            Ok(())
+        });
+
+        tokio::spawn(handle);
+    }
+
+    /// Exchange versions and fetch server capabilities
+    fn hello(&self) -> impl Future<Item=Capabilities, Error=Error> {
+        let mut mux = self.mux.clone();
+
+        mux.do_request("", RequestKind::Hello(Hello::new())).then(|res| {
+            match res {
+                Ok(ResponseKind::Capabilities(c)) => {
+                    if c.protocol_version == PROTOCOL_VERSION {
+                        Ok(c)
+                    } else {
+                        Err(Error::IncompatibleServer(format!("server protocol version {} (server version {}) does not match client version {}", c.protocol_version, c.server_version, PROTOCOL_VERSION)))
+                    }
+                },
+                Ok(r) => Err(Error::IncompatibleServer(format!("unexpected hello response: {:?}", r))),
+                Err(e) => Err(Error::IncompatibleServer(format!("hello failed: {}", e))),
+            }
+        })
+    }
+
+    /// Re-issue binding requests for previously connected devices and subscriptions,
+    /// failing with the devices that could not be re-bound
+    ///
+    /// Devices are re-bound before subscriptions, and requests reported as already
+    /// bound are retried with the reconnection backoff. Subscription requests are
+    /// re-issued with their original ids, so existing event streams continue to
+    /// receive events.
+    fn rebind(&self, backoff: Duration) -> impl Future<Item=(), Error=Vec<String>> {
+        let (connections, subscriptions) = self.mux.bindings.requests();
+
+        let s = self.clone();
+        s.rebind_all(connections, backoff).then(move |r1| {
+            s.rebind_all(subscriptions, backoff).then(move |r2| {
+                let mut failed = r1.err().unwrap_or_default();
+                failed.extend(r2.err().unwrap_or_default());
+
+                if failed.is_empty() { Ok(()) } else { Err(failed) }
+            })
+        })
+    }
+
+    fn rebind_all(&self, requests: Vec<Request>, backoff: Duration) -> impl Future<Item=(), Error=Vec<String>> {
+        let f: Vec<_> = requests.into_iter().map(|req| {
+            info!("re-binding device: {} ({:?})", req.device, req.kind);
+            let mux = self.mux.clone();
+
+            future::loop_fn((1, backoff), move |(attempt, backoff)| {
+                let device = req.device.clone();
+
+                mux.clone().send_request(req.clone()).then(move |r| {
+                    match r {
+                        Ok(_) => Either::A(future::ok::<_, ()>(Loop::Break(None))),
+                        Err(Error::DeviceAlreadyBound) if attempt < REBIND_ATTEMPTS => {
+                            warn!("device {} already bound, retrying ({}/{})", device, attempt, REBIND_ATTEMPTS);
+                            Either::B(TimerDelay::new(Instant::now() + backoff).then(move |_r| {
+                                Ok(Loop::Continue((attempt + 1, backoff * 2)))
+                            }))
+                        },
+                        Err(e) => {
+                            warn!("error re-binding device {}: {}", device, e);
+                            Either::A(future::ok(Loop::Break(Some(device))))
+                        },
+                    }
+                })
+            })
+        }).collect();
+
+        future::join_all(f).then(|r: Result<Vec<Option<String>>, ()>| {
+            let failed: Vec<_> = r.unwrap_or_default().into_iter().flatten().collect();
+            if failed.is_empty() { Ok(()) } else { Err(failed) }
+        })
+    }
+
+    /// Reconnect following a connection loss, as configured by the reconnect policy
+    fn reconnect(self) -> Box<Future<Item=(), Error=()> + Send> {
+        self.notify(ConnectionState::Disconnected);
+
+        let policy = match &self.options.reconnect {
+            Some(p) => p.clone(),
+            None => return Box::new(future::ok(())),
+        };
+
+        let session = self.clone();
+        let attempts = future::loop_fn((1, policy.backoff), move |(attempt, backoff)| {
+            let s = session.clone();
+            let policy = policy.clone();
+
+            TimerDelay::new(Instant::now() + backoff).map_err(|_e| () ).and_then(move |_| {
+                s.notify(ConnectionState::Reconnecting{attempt});
+
+                let s1 = s.clone();
+                s.connect().then(move |r| {
+                    match r {
+                        Ok(_c) => Either::A(s1.rebind(policy.backoff).then(|r| Ok::<_, ()>(Loop::Break(Some(r))) )),
+                        Err(e) => {
+                            warn!("reconnection attempt {} failed: {}", attempt, e);
+
+                            let done = policy.max_attempts.map(|m| attempt >= m ).unwrap_or(false);
+                            let next = if done {
+                                Loop::Break(None)
+                            } else {
+                                Loop::Continue((attempt + 1, cmp::min(backoff * 2, policy.max_backoff)))
+                            };
+                            Either::B(future::ok(next))
+                        },
+                    }
+                })
+            })
+        });
+
+        // Stop reconnecting if the client is closed
+        let s = self.clone();
+        Box::new(attempts.select2(self.closed.clone()).then(move |r| {
+            match r {
+                Ok(Either::A((Some(Ok(())), _))) => s.notify(ConnectionState::Connected),
+                Ok(Either::A((Some(Err(failed)), _))) => s.notify(ConnectionState::Degraded{failed}),
+                Ok(Either::A((None, _))) => s.notify(ConnectionState::Failed),
+                _ => (),
+            }
+            Ok(())
+        }))
+    }
+}
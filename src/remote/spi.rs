@@ -1,28 +1,89 @@
 
+use std::sync::Arc;
+use std::time::Duration;
+
 use embedded_hal::blocking::spi;
+use embedded_hal_1 as hal1;
 
 use futures::prelude::*;
 
 use crate::common::*;
+use crate::asynch::{self, AsyncResult};
+use crate::eh1;
 use crate::error::Error;
-use super::{Mux, Requester};
+use super::{Mux, Requester, Binding};
 
 #[derive(Clone)]
 pub struct Spi {
     device: String,
     mux: Mux,
+    /// Disconnects the device once the last handle is dropped
+    _binding: Arc<Binding>,
+    cs: Option<String>,
 }
 
 
 impl Spi {
     pub (crate) fn new(device: String, mux: Mux) -> Self {
-        Spi{device, mux}
+        let _binding = Binding::new(&device, mux.clone(), RequestKind::SpiDisconnect);
+        Spi{device, mux, _binding, cs: None}
+    }
+
+    /// Override the client request timeout for requests from this handle
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.mux.timeout = timeout;
+    }
+
+    /// Override the number of retries for idempotent requests from this handle
+    pub fn set_retries(&mut self, retries: u32) {
+        self.mux.retries = retries;
+    }
+
+    /// Set a GPIO chip select pin to be driven low for the duration of transactions,
+    /// this must be connected as an output using the same client
+    pub fn set_cs(&mut self, cs: Option<&str>) {
+        self.cs = cs.map(|c| c.to_owned() );
+    }
+
+    /// Execute a sequence of operations in a single request, returning the data read
+    /// by each operation (empty for writes and delays)
+    pub fn transaction(&mut self, ops: Vec<SpiOp>) -> impl Future<Item=Vec<Vec<u8>>, Error=Error> {
+        let req = RequestKind::SpiTransaction(SpiTransaction{cs: self.cs.clone(), ops});
+
+        self.mux.do_request(&self.device, req).and_then(|resp| {
+            match resp {
+                ResponseKind::SpiTransaction(d) => Ok(d),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        })
+    }
+}
+
+impl asynch::spi::Transfer for Spi {
+    type Error = Error;
+
+    fn transfer(&mut self, data: Vec<u8>) -> AsyncResult<Vec<u8>, Error> {
+        debug!("spi transfer request {}", self.device);
+        Box::new(self.mux.do_request(&self.device, RequestKind::SpiTransfer{write_data: Data{data}}).and_then(|resp| {
+            debug!("spi transfer response");
+            match resp {
+                ResponseKind::SpiTransfer(d) => Ok(d),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        }))
     }
 }
 
-impl Drop for Spi {
-    fn drop(&mut self) {
-        self.mux.do_request(&self.device, RequestKind::SpiDisconnect).wait().unwrap();
+impl asynch::spi::Write for Spi {
+    type Error = Error;
+
+    fn write(&mut self, data: Vec<u8>) -> AsyncResult<(), Error> {
+        Box::new(self.mux.do_request(&self.device, RequestKind::SpiWrite{write_data: Data{data}}).and_then(|resp| {
+            match resp {
+                ResponseKind::Ok => Ok(()),
+                _ => Err(Error::InvalidResponse(resp)),
+            }
+        }))
     }
 }
 
@@ -30,16 +91,9 @@ impl spi::Transfer<u8> for Spi {
     type Error = Error;
 
     fn transfer<'w>(&mut self, data: &'w mut [u8]) -> Result<&'w [u8], Error> {
-        debug!("spi transfer request {}", self.device);
-        let resp = self.mux.do_request(&self.device, RequestKind::SpiTransfer{write_data: Data{data: data.to_vec()}}).wait()?;
-        debug!("spi transfer response");
-        match resp {
-            ResponseKind::SpiTransfer(d) => {
-                data.clone_from_slice(&d);
-                Ok(data)
-            },
-            _ => Err(Error::InvalidResponse(resp)),
-        }
+        let d = asynch::spi::Transfer::transfer(self, data.to_vec()).wait()?;
+        data.clone_from_slice(&d);
+        Ok(data)
     }
 }
 
@@ -47,12 +101,66 @@ impl spi::Write<u8> for Spi {
     type Error = Error;
 
     fn write(&mut self, data: &[u8]) -> Result<(), Error> {
-        let resp = self.mux.do_request(&self.device, RequestKind::SpiWrite{write_data: Data{data: data.to_vec()}}).wait()?;
-        match resp {
-            ResponseKind::Ok => Ok(()),
-            _ => Err(Error::InvalidResponse(resp)),
+        asynch::spi::Write::write(self, data.to_vec()).wait()
+    }
+}
+
+impl spi::Transactional<u8> for Spi {
+    type Error = Error;
+
+    fn exec<'a>(&mut self, operations: &mut [spi::Operation<'a, u8>]) -> Result<(), Error> {
+        let ops = operations.iter().map(|op| {
+            match op {
+                spi::Operation::Write(d) => SpiOp::Write(d.to_vec()),
+                spi::Operation::Transfer(d) => SpiOp::Transfer(d.to_vec()),
+            }
+        }).collect();
+
+        let data = self.transaction(ops).wait()?;
+        if data.len() != operations.len() {
+            return Err(Error::InvalidResponse(ResponseKind::SpiTransaction(data)));
+        }
+
+        for (op, d) in operations.iter_mut().zip(&data) {
+            if let spi::Operation::Transfer(r) = op {
+                r.clone_from_slice(d);
+            }
         }
+
+        Ok(())
+    }
+}
+
+impl hal1::spi::ErrorType for Spi {
+    type Error = Error;
+}
+
+impl hal1::spi::SpiDevice<u8> for Spi {
+    fn transaction(&mut self, operations: &mut [hal1::spi::Operation<'_, u8>]) -> Result<(), Error> {
+        let data = Spi::transaction(self, eh1::spi_ops(operations)?).wait()?;
+        eh1::spi_results(operations, &data)
     }
 }
 
+impl hal1::spi::SpiBus<u8> for Spi {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        eh1::spi_transaction(self, &mut [hal1::spi::Operation::Read(words)])
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Error> {
+        spi::Write::write(self, words)
+    }
 
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Error> {
+        eh1::spi_transaction(self, &mut [hal1::spi::Operation::Transfer(read, write)])
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Error> {
+        spi::Transfer::transfer(self, words)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,268 @@
+
+use std::thread;
+use std::time::Duration;
+
+use crate::common::*;
+use crate::error::Error;
+
+/// Backend used by the server to open devices (ie. `local::Client` or `sim::Client`)
+pub trait Backend: Send {
+    fn spi(&mut self, path: &str, baud: u32, mode: SpiMode) -> Result<Box<SpiDevice>, Error>;
+    fn i2c(&mut self, path: &str) -> Result<Box<I2cDevice>, Error>;
+    fn pin(&mut self, path: &str, mode: PinMode) -> Result<Box<PinDevice>, Error>;
+    fn serial(&mut self, path: &str, config: &SerialConnect) -> Result<Box<SerialDevice>, Error>;
+    fn pwm(&mut self, path: &str) -> Result<Box<PwmDevice>, Error>;
+    fn adc(&mut self, path: &str) -> Result<Box<AdcDevice>, Error>;
+    fn can(&mut self, path: &str) -> Result<Box<CanDevice>, Error>;
+}
+
+/// SPI device operations exposed by the server
+pub trait SpiDevice: Send {
+    /// Transfer data in place
+    fn transfer(&mut self, data: &mut [u8]) -> Result<(), Error>;
+    fn write(&mut self, data: &[u8]) -> Result<(), Error>;
+
+    /// Execute a sequence of operations with chip select asserted throughout,
+    /// returning the data read by each operation (empty for writes and delays)
+    ///
+    /// By default operations are executed as a single transfer, and delays are
+    /// rejected as the (hardware) chip select cannot be held across them. Devices
+    /// supporting delays should implement transactions directly, otherwise delays
+    /// are only available with a software chip select (see `software_cs_transaction`)
+    fn transaction(&mut self, ops: &[SpiOp]) -> Result<Vec<Vec<u8>>, Error> {
+        if ops.iter().any(|op| matches!(op, SpiOp::DelayUs(_))) {
+            return Err(Error::Unsupported("SPI delays require a software chip select".to_owned()));
+        }
+
+        spi_transfer(self, ops)
+    }
+}
+
+/// Execute a sequence of operations as transfers split around delays
+///
+/// This may release a hardware chip select between transfers, and so must only be
+/// used while a software chip select is held asserted by the caller
+pub (crate) fn software_cs_transaction<D: SpiDevice + ?Sized>(dev: &mut D, ops: &[SpiOp]) -> Result<Vec<Vec<u8>>, Error> {
+    let mut data = Vec::with_capacity(ops.len());
+    let mut start = 0;
+
+    for (i, op) in ops.iter().enumerate() {
+        if let SpiOp::DelayUs(us) = op {
+            data.extend(spi_transfer(dev, &ops[start..i])?);
+            thread::sleep(Duration::from_micros(*us as u64));
+            data.push(vec![]);
+            start = i + 1;
+        }
+    }
+
+    data.extend(spi_transfer(dev, &ops[start..])?);
+
+    Ok(data)
+}
+
+/// Execute SPI operations (excluding delays) as a single transfer
+fn spi_transfer<D: SpiDevice + ?Sized>(dev: &mut D, ops: &[SpiOp]) -> Result<Vec<Vec<u8>>, Error> {
+    let mut buff = Vec::new();
+    for op in ops {
+        match op {
+            SpiOp::Write(d) | SpiOp::Transfer(d) => buff.extend_from_slice(d),
+            SpiOp::Read(n) => buff.resize(buff.len() + *n as usize, 0),
+            SpiOp::DelayUs(_) => (),
+        }
+    }
+
+    if !buff.is_empty() {
+        dev.transfer(&mut buff)?;
+    }
+
+    let mut i = 0;
+    let data = ops.iter().map(|op| {
+        let n = op.data_len();
+        let d = match op {
+            SpiOp::Transfer(_) | SpiOp::Read(_) => buff[i..i+n].to_vec(),
+            _ => vec![],
+        };
+        i += n;
+        d
+    }).collect();
+
+    Ok(data)
+}
+
+/// I2C device operations exposed by the server
+pub trait I2cDevice: Send {
+    fn read(&mut self, addr: u8, buff: &mut [u8]) -> Result<(), Error>;
+    fn write(&mut self, addr: u8, data: &[u8]) -> Result<(), Error>;
+    fn write_read(&mut self, addr: u8, data: &[u8], buff: &mut [u8]) -> Result<(), Error>;
+
+    /// Execute a sequence of operations with repeated starts, returning the data
+    /// read by each operation (empty for writes)
+    ///
+    /// By default operations are executed individually, with a stop condition after
+    /// each operation rather than repeated starts. Only the local I2C backend issues
+    /// repeated starts, so devices relying on these should implement this directly
+    fn transaction(&mut self, addr: u8, ops: &[I2cOp]) -> Result<Vec<Vec<u8>>, Error> {
+        ops.iter().map(|op| {
+            match op {
+                I2cOp::Write(d) => self.write(addr, d).map(|_| vec![] ),
+                I2cOp::Read(n) => {
+                    let mut buff = vec![0u8; *n as usize];
+                    self.read(addr, &mut buff).map(|_| buff )
+                },
+            }
+        }).collect()
+    }
+
+    /// Probe for a device responding at the provided address
+    ///
+    /// By default quick probes are implemented with a zero-length write
+    fn probe(&mut self, addr: u8, method: ProbeMethod) -> Result<bool, Error> {
+        let r = match method.for_address(addr) {
+            ProbeMethod::Read => self.read(addr, &mut [0u8]),
+            _ => self.write(addr, &[]),
+        };
+
+        Ok(r.is_ok())
+    }
+
+    /// Execute an SMBus operation, returning `ResponseKind::Ok` for writes or the
+    /// `SmbusByte`, `SmbusWord` or `SmbusBlock` read
+    ///
+    /// By default operations (and PEC) are implemented using plain I2C reads and writes
+    fn smbus(&mut self, addr: u8, pec: bool, op: &SmbusOp) -> Result<ResponseKind, Error> {
+        match op {
+            SmbusOp::ReadByte{command} => {
+                let d = smbus_write_read(self, addr, pec, &[*command], 1)?;
+                Ok(ResponseKind::SmbusByte(d[0]))
+            },
+            SmbusOp::WriteByte{command, value} => {
+                smbus_write(self, addr, pec, &[*command, *value])?;
+                Ok(ResponseKind::Ok)
+            },
+            SmbusOp::ReadWord{command} => {
+                let d = smbus_write_read(self, addr, pec, &[*command], 2)?;
+                Ok(ResponseKind::SmbusWord(d[0] as u16 | ((d[1] as u16) << 8)))
+            },
+            SmbusOp::WriteWord{command, value} => {
+                smbus_write(self, addr, pec, &[*command, *value as u8, (*value >> 8) as u8])?;
+                Ok(ResponseKind::Ok)
+            },
+            SmbusOp::ReadBlock{command} => {
+                // Block length is unknown prior to reading, so read the maximum
+                let d = smbus_write_read(self, addr, false, &[*command], 1 + SMBUS_BLOCK_MAX + 1)?;
+                let n = (d[0] as usize).min(SMBUS_BLOCK_MAX);
+
+                if pec && d[1 + n] != smbus_pec(&[&[addr << 1, *command, (addr << 1) | 1], &d[..1 + n]]) {
+                    return Err(Error::invalid_data("smbus pec mismatch"));
+                }
+
+                Ok(ResponseKind::SmbusBlock(d[1..1 + n].to_vec()))
+            },
+            SmbusOp::WriteBlock{command, data} => {
+                if data.len() > SMBUS_BLOCK_MAX {
+                    return Err(Error::TransferTooLarge(data.len()));
+                }
+
+                let mut d = vec![*command, data.len() as u8];
+                d.extend_from_slice(data);
+                smbus_write(self, addr, pec, &d)?;
+
+                Ok(ResponseKind::Ok)
+            },
+            SmbusOp::ProcessCall{command, value} => {
+                let d = smbus_write_read(self, addr, pec, &[*command, *value as u8, (*value >> 8) as u8], 2)?;
+                Ok(ResponseKind::SmbusWord(d[0] as u16 | ((d[1] as u16) << 8)))
+            },
+        }
+    }
+}
+
+/// Write SMBus data, appending a PEC byte if enabled
+fn smbus_write<D: I2cDevice + ?Sized>(dev: &mut D, addr: u8, pec: bool, data: &[u8]) -> Result<(), Error> {
+    let mut d = data.to_vec();
+    if pec {
+        d.push(smbus_pec(&[&[addr << 1], data]));
+    }
+
+    dev.write(addr, &d)
+}
+
+/// Write then read SMBus data, checking the trailing PEC byte if enabled
+fn smbus_write_read<D: I2cDevice + ?Sized>(dev: &mut D, addr: u8, pec: bool, data: &[u8], len: usize) -> Result<Vec<u8>, Error> {
+    let mut buff = vec![0u8; len + if pec { 1 } else { 0 }];
+    dev.write_read(addr, data, &mut buff)?;
+
+    if pec && buff[len] != smbus_pec(&[&[addr << 1], data, &[(addr << 1) | 1], &buff[..len]]) {
+        return Err(Error::invalid_data("smbus pec mismatch"));
+    }
+
+    buff.truncate(len);
+    Ok(buff)
+}
+
+/// Compute the SMBus Packet Error Code (CRC-8, polynomial 0x07) over the provided segments
+fn smbus_pec(segments: &[&[u8]]) -> u8 {
+    let mut crc = 0u8;
+
+    for b in segments.iter().flat_map(|s| s.iter() ) {
+        crc ^= b;
+        for _i in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+    }
+
+    crc
+}
+
+/// Pin operations exposed by the server
+pub trait PinDevice: Send {
+    fn get(&self) -> Result<bool, Error>;
+    fn set(&mut self, value: bool) -> Result<(), Error>;
+
+    /// Subscribe to edge events, replacing any existing subscription
+    fn subscribe(&mut self, edge: Edge, handler: Box<FnMut(PinEvent) + Send>) -> Result<(), Error>;
+    fn unsubscribe(&mut self);
+}
+
+/// Serial port operations exposed by the server
+pub trait SerialDevice: Send {
+    /// Read up to `buff.len()` bytes, waiting at most `timeout` for data to arrive
+    fn read_timeout(&mut self, buff: &mut [u8], timeout: Duration) -> Result<usize, Error>;
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Error>;
+    fn flush(&mut self) -> Result<(), Error>;
+}
+
+/// PWM channel operations exposed by the server, times are in nanoseconds
+pub trait PwmDevice: Send {
+    fn set_period(&mut self, period_ns: u64) -> Result<(), Error>;
+    fn set_duty(&mut self, duty_ns: u64) -> Result<(), Error>;
+    fn set_polarity(&mut self, polarity: Polarity) -> Result<(), Error>;
+    fn set_enabled(&mut self, enabled: bool) -> Result<(), Error>;
+    fn state(&self) -> Result<PwmState, Error>;
+}
+
+/// ADC operations exposed by the server
+pub trait AdcDevice: Send {
+    /// Read a single value from the named channel
+    fn read(&mut self, channel: &str) -> Result<AdcReading, Error>;
+
+    /// Capture samples from the requested channels, failing with `Error::Timeout`
+    /// if the capture does not complete within `timeout`
+    fn capture(&mut self, capture: &AdcCapture, timeout: Duration) -> Result<Vec<AdcChannelData>, Error>;
+}
+
+/// CAN interface operations exposed by the server
+pub trait CanDevice: Send {
+    fn send(&mut self, frame: &CanFrame) -> Result<(), Error>;
+
+    /// Set receive filters, an empty list accepts all frames
+    fn set_filters(&mut self, filters: &[CanFilter]) -> Result<(), Error>;
+
+    /// Receive a frame, waiting at most `timeout` for one to arrive
+    fn receive(&mut self, timeout: Duration) -> Result<Option<CanFrame>, Error>;
+
+    /// Subscribe to received frames matching the provided filters (independent
+    /// of the receive filters), replacing any existing subscription
+    fn subscribe(&mut self, filters: &[CanFilter], handler: Box<FnMut(CanFrame) + Send>) -> Result<(), Error>;
+    fn unsubscribe(&mut self);
+}
@@ -0,0 +1,232 @@
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::common::*;
+use crate::error::Error;
+
+/// Daemon configuration, loaded from a TOML file
+///
+/// When a configuration is provided the server only exposes the named devices,
+/// for example:
+///
+/// ```toml
+/// [devices.radio_spi]
+/// kind = "spi"
+/// path = "/dev/spidev0.0"
+/// baud = 1000000
+/// spi_mode = "0"
+///
+/// [devices.reset_pin]
+/// kind = "pin"
+/// path = "gpiochip0:17"
+/// pin_modes = ["output"]
+///
+/// [devices.motor_pwm]
+/// kind = "pwm"
+/// path = "pwmchip0:1"
+///
+/// [devices.sense_adc]
+/// kind = "adc"
+/// path = "iio:device0"
+///
+/// [devices.bus_can]
+/// kind = "can"
+/// path = "can0"
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Config {
+    /// Named devices exposed by the server
+    #[serde(default)]
+    pub devices: HashMap<String, DeviceConfig>,
+}
+
+/// Configuration for a named device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    /// Device type
+    pub kind: DeviceKind,
+    /// Backing device path
+    pub path: String,
+
+    /// Default SPI baud rate, used when clients request a baud rate of 0
+    #[serde(default)]
+    pub baud: Option<u32>,
+    /// Default SPI mode, used when clients do not request a mode
+    #[serde(default)]
+    pub spi_mode: Option<SpiMode>,
+    /// Allowed SPI modes, unrestricted if unset
+    #[serde(default)]
+    pub spi_modes: Option<Vec<SpiMode>>,
+
+    /// Allowed pin modes, unrestricted if unset
+    #[serde(default)]
+    pub pin_modes: Option<Vec<PinDirection>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PinDirection {
+    Input,
+    Output,
+}
+
+impl Config {
+    /// Load a configuration file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let s = fs::read_to_string(path)?;
+
+        toml::from_str(&s).map_err(|e| Error::Config(format!("{}", e)) )
+    }
+
+    /// Resolve a named device to its backing path, checking the request is permitted
+    /// and applying configured defaults.
+    pub fn resolve(&self, device: &str, req: RequestKind) -> Result<(String, RequestKind), Error> {
+        let kind = match req.device_kind() {
+            Some(k) => k,
+            None => return Ok((device.to_owned(), req)),
+        };
+
+        let d = match self.devices.get(device) {
+            Some(d) => d,
+            None => return Err(Error::UnknownDevice(device.to_owned())),
+        };
+
+        if d.kind != kind {
+            return Err(Error::NotPermitted(format!("{} is not a {:?} device", device, kind)));
+        }
+
+        let req = match req {
+            RequestKind::SpiConnect(mut c) => {
+                if c.baud == 0 {
+                    c.baud = d.baud.ok_or_else(|| Error::Config(format!("no default baud rate for {}", device)) )?;
+                }
+
+                if c.mode.is_none() {
+                    c.mode = d.spi_mode.clone();
+                }
+
+                if let Some(modes) = &d.spi_modes {
+                    let mode = c.mode.clone().unwrap_or(SpiMode::Mode0);
+                    if !modes.contains(&mode) {
+                        return Err(Error::NotPermitted(format!("spi mode {:?} not allowed for {}", mode, device)));
+                    }
+                }
+
+                RequestKind::SpiConnect(c)
+            },
+            RequestKind::PinConnect(m) => {
+                let dir = match m {
+                    PinMode::Input(_) => PinDirection::Input,
+                    PinMode::Output(_) => PinDirection::Output,
+                };
+
+                if let Some(modes) = &d.pin_modes {
+                    if !modes.contains(&dir) {
+                        return Err(Error::NotPermitted(format!("pin mode {:?} not allowed for {}", dir, device)));
+                    }
+                }
+
+                RequestKind::PinConnect(m)
+            },
+            _ => req,
+        };
+
+        Ok((d.path.clone(), req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        toml::from_str(r#"
+            [devices.radio_spi]
+            kind = "spi"
+            path = "/dev/spidev0.0"
+            baud = 1000000
+            spi_mode = "1"
+            spi_modes = ["1", "3"]
+
+            [devices.flash_spi]
+            kind = "spi"
+            path = "/dev/spidev0.1"
+
+            [devices.reset_pin]
+            kind = "pin"
+            path = "gpiochip0:17"
+            pin_modes = ["output"]
+        "#).unwrap()
+    }
+
+    fn spi(c: &Config, device: &str, baud: u32, mode: Option<SpiMode>) -> Result<(String, SpiConnect), Error> {
+        match c.resolve(device, RequestKind::SpiConnect(SpiConnect{baud, mode}))? {
+            (path, RequestKind::SpiConnect(s)) => Ok((path, s)),
+            (_, r) => panic!("unexpected request: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn spi_defaults() {
+        let c = config();
+
+        // Defaults are applied independently
+        let (path, s) = spi(&c, "radio_spi", 0, None).unwrap();
+        assert_eq!(path, "/dev/spidev0.0");
+        assert_eq!((s.baud, s.mode), (1000000, Some(SpiMode::Mode1)));
+
+        let (_, s) = spi(&c, "radio_spi", 500000, None).unwrap();
+        assert_eq!((s.baud, s.mode), (500000, Some(SpiMode::Mode1)));
+
+        let (_, s) = spi(&c, "radio_spi", 0, Some(SpiMode::Mode3)).unwrap();
+        assert_eq!((s.baud, s.mode), (1000000, Some(SpiMode::Mode3)));
+
+        // Devices without defaults require a baud rate
+        assert!(spi(&c, "flash_spi", 0, None).is_err());
+        let (_, s) = spi(&c, "flash_spi", 500000, None).unwrap();
+        assert_eq!((s.baud, s.mode), (500000, None));
+    }
+
+    #[test]
+    fn device_overrides() {
+        let c = config();
+
+        let (_, s) = spi(&c, "radio_spi", 2000000, Some(SpiMode::Mode3)).unwrap();
+        assert_eq!((s.baud, s.mode), (2000000, Some(SpiMode::Mode3)));
+
+        // Overrides are limited to the allowed modes
+        match spi(&c, "radio_spi", 2000000, Some(SpiMode::Mode0)) {
+            Err(Error::NotPermitted(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        let output = RequestKind::PinConnect(PinMode::Output(PinOptions::default()));
+        assert_eq!(c.resolve("reset_pin", output).unwrap().0, "gpiochip0:17");
+
+        match c.resolve("reset_pin", RequestKind::PinConnect(PinMode::Input(PinOptions::default()))) {
+            Err(Error::NotPermitted(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn unknown_devices() {
+        let c = config();
+
+        match spi(&c, "/dev/spidev0.0", 1000000, None) {
+            Err(Error::UnknownDevice(d)) => assert_eq!(d, "/dev/spidev0.0"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        // Devices are only exposed as their configured kind
+        match c.resolve("radio_spi", RequestKind::PinGet) {
+            Err(Error::NotPermitted(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        // Requests without a device are passed through
+        assert!(c.resolve("", RequestKind::Ping).is_ok());
+    }
+}
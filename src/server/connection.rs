@@ -0,0 +1,38 @@
+
+use std::net::SocketAddr;
+
+use futures::sync::mpsc::UnboundedSender;
+use rand::random;
+
+use crate::common::Response;
+
+/// Handle to a connected client, used to send responses and push unsolicited events
+#[derive(Clone)]
+pub struct Connection {
+    id: u64,
+    addr: SocketAddr,
+    tx: UnboundedSender<Response>,
+}
+
+impl Connection {
+    pub (crate) fn new(addr: SocketAddr, tx: UnboundedSender<Response>) -> Self {
+        Connection{id: random(), addr, tx}
+    }
+
+    /// Fetch the unique id of the connection
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Fetch the remote address of the connection
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Queue a message to be sent to the client
+    pub fn send(&self, resp: Response) {
+        if let Err(e) = self.tx.unbounded_send(resp) {
+            debug!("dropping message for closed connection {}: {:?}", self.addr, e.into_inner());
+        }
+    }
+}
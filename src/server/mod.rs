@@ -1,75 +1,445 @@
 
 use std::net::{SocketAddr};
-use std::sync::{Arc, Mutex};
+use std::cmp;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::timer::{Timeout, Interval, Delay as TimerDelay};
+use std::sync::{Arc, Mutex, Once};
 use std::collections::{HashMap, hash_map::Entry};
 
-use daemon_engine::{TcpServer, JsonCodec};
 use tokio::prelude::*;
-
-use embedded_hal::blocking::spi::{Transfer as SpiTransfer, Write as SpiWrite};
-use embedded_hal::blocking::i2c::{Read as I2cRead, Write as I2cWrite, WriteRead as I2cWriteRead};
-use embedded_hal::digital::{InputPin, OutputPin};
-
+use tokio::codec::Framed;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use futures::future::Either;
+use futures::sync::mpsc::unbounded;
+use futures::sync::oneshot;
 
 use crate::common::*;
+use crate::codec::{self, Codec, WireCodec};
+use crate::transport::{self, Transport, ServerTls};
 use crate::error::Error;
 
-use crate::local::spi::Spi;
-use crate::local::i2c::I2c;
-use crate::local::pin::Pin;
+use crate::local;
+
+pub mod backend;
+pub use backend::{Backend, SpiDevice, I2cDevice, PinDevice, SerialDevice, PwmDevice, AdcDevice, CanDevice};
+pub mod connection;
+pub use connection::Connection;
+pub mod config;
+pub use config::Config;
+
+/// Bound devices by path, shared between connections
+type Devices<T> = Arc<Mutex<HashMap<String, Arc<Mutex<Box<T>>>>>>;
 
 /// remote-hal server, this exposes embedded-hal devices over TCP RPC interface
 /// 
 /// THIS MUST BE RUN IN A TOKIO CONTEXT
 #[derive(Clone)]
 pub struct Server {
-    _server: TcpServer<JsonCodec<Response, Request>>,
+    addr: SocketAddr,
+    options: ServerOptions,
+    tls: Option<TlsAcceptor>,
+
+    backend: Arc<Mutex<Box<Backend>>>,
+
+    spi: Devices<SpiDevice>,
+    i2c: Arc<Mutex<HashMap<String, Box<I2cDevice>>>>,
+    pin: Devices<PinDevice>,
+    serial: Devices<SerialDevice>,
+    pwm: Arc<Mutex<HashMap<String, Box<PwmDevice>>>>,
+    adc: Devices<AdcDevice>,
+    can: Devices<CanDevice>,
+
+    /// Bound devices and the id of the owning connection
+    owners: Arc<Mutex<HashMap<(DeviceKind, String), u64>>>,
+}
+
+/// remote-hal server options
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// Wire codecs accepted from clients, the codec for each connection is detected from the first message
+    pub codecs: Vec<Codec>,
+    /// Maximum data length for a single transfer in bytes
+    pub max_transfer: u32,
+    /// Idle lease, connections receiving no requests for this period are closed
+    /// and their devices released (used to clean up half-open connections)
+    pub lease: Option<Duration>,
+    /// Device configuration, if provided only the configured devices are exposed
+    pub config: Option<Config>,
+    /// TLS configuration, connections are unencrypted if unset
+    pub tls: Option<ServerTls>,
+    /// Maximum time a single request may delay the server (including delays
+    /// within transactions and sequences), this should be less than client timeouts
+    pub max_delay: Duration,
+    /// Heartbeats required from clients, connections sending no requests (or
+    /// pings) for the heartbeat period are closed and their devices released.
+    /// The server also pings clients at the heartbeat interval.
+    pub heartbeat: Option<Heartbeat>,
+}
 
-    spi: Arc<Mutex<HashMap<String, Spi>>>,
-    i2c: Arc<Mutex<HashMap<String, I2c>>>,
-    pin: Arc<Mutex<HashMap<String, Pin>>>,
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            codecs: vec![Codec::Json, Codec::Cbor],
+            max_transfer: 64 * 1024,
+            lease: None,
+            config: None,
+            tls: None,
+            max_delay: Duration::from_secs(1),
+            heartbeat: None,
+        }
+    }
 }
 
 impl Server {
-    pub fn new(addr: SocketAddr) -> Result<Self, Error> {
-        debug!("server binding to: {}", addr);
+    /// Create a new server exposing local devices
+    pub fn new(addr: SocketAddr, options: ServerOptions) -> Result<Self, Error> {
+        Self::with_backend(addr, options, Box::new(local::Client::default()))
+    }
+
+    /// Create a new server exposing devices from the provided backend (ie. `sim::Client`)
+    pub fn with_backend(addr: SocketAddr, options: ServerOptions, backend: Box<Backend>) -> Result<Self, Error> {
+        debug!("server binding to: {} (options: {:?})", addr, options);
 
-        let server = TcpServer::<JsonCodec<Response, Request>>::new(&addr, JsonCodec::new()).unwrap();
+        let listener = TcpListener::bind(&addr)?;
+        let addr = listener.local_addr()?;
+
+        let tls = match &options.tls {
+            Some(t) => Some(t.acceptor()?),
+            None => None,
+        };
 
         let s = Self {
-            _server: server.clone(),
+            addr,
+            options,
+            tls,
+            backend: Arc::new(Mutex::new(backend)),
             spi: Arc::new(Mutex::new(HashMap::new())),
             i2c: Arc::new(Mutex::new(HashMap::new())),
-            pin: Arc::new(Mutex::new(HashMap::new())),    
+            pin: Arc::new(Mutex::new(HashMap::new())),
+            serial: Arc::new(Mutex::new(HashMap::new())),
+            pwm: Arc::new(Mutex::new(HashMap::new())),
+            adc: Arc::new(Mutex::new(HashMap::new())),
+            can: Arc::new(Mutex::new(HashMap::new())),
+            owners: Arc::new(Mutex::new(HashMap::new())),
         };
 
-        let mut s1 = s.clone();
+        let s1 = s.clone();
 
-        let server_handle = server.clone()
+        let server_handle = listener
             .incoming()
-            .unwrap()
-            .for_each(move |r| {
-                let req = r.data();
-                info!("Received: {:?} info: {:?}", req, r.info());
-
-                let resp = match s1.handle(&req.device, req.kind) {
-                    Ok(resp) => resp,
-                    Err(e) => ResponseKind::Error(format!("{:?}", e)),
+            .map_err(|e| error!("server accept error: {:?}", e) )
+            .for_each(move |socket| {
+                let addr = match socket.peer_addr() {
+                    Ok(a) => a,
+                    Err(e) => {
+                        error!("error fetching peer address: {:?}", e);
+                        return Ok(());
+                    }
                 };
 
-                info!("Response: {:?}", resp);
+                // Complete any TLS handshake before binding the connection
+                let s = s1.clone();
+                let accept = transport::accept(socket, s1.tls.clone())
+                    .map(move |stream| s.bind(stream, addr) )
+                    .map_err(move |e| error!("error accepting connection from {}: {:?}", addr, e) );
+                tokio::spawn(accept);
 
-                r.send(Response{id: req.id, kind: resp}).map(|_v| trace!("server send complete") ).map_err(|e| error!("server error: {:?}", e))
-            }).map(|_v| () ).map_err(|_e| ());
+                Ok(())
+            });
 
         tokio::spawn(server_handle);
 
         Ok(s)
     }
 
-    pub fn handle(&mut self, device: &str, req: RequestKind) -> Result<ResponseKind, Error> {
+    /// Fetch the address the server is listening on (ie. when binding to port 0)
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Bind a new client connection to the server
+    fn bind(self, stream: Box<Transport>, addr: SocketAddr) {
+        info!("client connected: {}", addr);
+
+        let (sink, stream) = Framed::new(stream, WireCodec::<Response, Request, Error>::server(&self.options.codecs)
+            .max_frame(codec::max_frame(self.options.max_transfer))).split();
+
+        // Responses and pushed events are queued via the connection and forwarded to the socket
+        let (tx, rx) = unbounded();
+        let conn = Connection::new(addr, tx);
+
+        // Unbounded receivers never fail
+        let tx_handle = sink.send_all(rx.map_err(|_e| Error::Disconnected ))
+            .map(|_v| trace!("server send complete") )
+            .map_err(|e| error!("server send error: {:?}", e) );
+        tokio::spawn(tx_handle);
+
+        // Apply idle lease or heartbeat period to incoming requests if enabled
+        let stream: Box<Stream<Item=Request, Error=Error> + Send> = match self.idle_timeout() {
+            Some(d) => Box::new(Timeout::new(stream, d).map_err(Error::from)),
+            None => Box::new(stream),
+        };
+
+        // Push heartbeats to the client so half-open connections are detected by the client,
+        // these stop once the connection is closed
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        if let Some(h) = &self.options.heartbeat {
+            let c = conn.clone();
+            let pings = Interval::new(Instant::now() + h.interval, h.interval)
+                .map_err(|e| error!("heartbeat timer error: {:?}", e) )
+                .for_each(move |_| {
+                    c.send(Response{id: 0, kind: ResponseKind::Ping});
+                    Ok(())
+                })
+                .select2(stop_rx)
+                .then(|_r| Ok(()) );
+
+            tokio::spawn(pings);
+        }
+
+        let s = self.clone();
+        let id = conn.id();
+
+        let rx_handle = stream.for_each(move |req| {
+            info!("Received: {:?} from: {}", req, conn.addr());
+
+            let (mut server, c, reply) = (self.clone(), conn.clone(), conn.clone());
+            let id = req.id;
+
+            // Standalone delays wait on a timer, other handlers may block on device IO
+            // so are run outside the reactor
+            let delay = match &req.kind {
+                RequestKind::Delay(d) => Some(d.us),
+                _ => None,
+            };
+
+            let resp = match delay {
+                Some(us) if self.check_delay(&req.kind).is_ok() => {
+                    info!("received Delay ({} us)", us);
+                    Either::A(TimerDelay::new(Instant::now() + Duration::from_micros(us as u64))
+                        .then(|_r| Ok::<_, Error>(ResponseKind::Ok) ))
+                },
+                _ => Either::B(blocking(move || {
+                    match server.handle(&c, req.id, &req.device, req.kind) {
+                        Ok(resp) => resp,
+                        Err(e) => ResponseKind::Error(e.into()),
+                    }
+                })),
+            };
+
+            resp.map(move |resp| {
+                info!("Response: {:?}", resp);
+
+                reply.send(Response{id, kind: resp});
+            })
+        })
+        .then(move |r| {
+            match r {
+                Ok(_) => info!("client disconnected: {}", addr),
+                Err(Error::Timeout) => warn!("client {} lease expired or heartbeats missed", addr),
+                Err(e) => error!("client {} error: {:?}", addr, e),
+            }
+
+            // Release devices bound by the connection, this also drops any
+            // subscriptions holding the connection open
+            s.release(id);
+            let _ = stop_tx.send(());
+
+            Ok(())
+        });
+
+        tokio::spawn(rx_handle);
+    }
+
+    /// Fetch the period after which idle connections are closed, the shorter of
+    /// the lease and heartbeat period where both are set
+    fn idle_timeout(&self) -> Option<Duration> {
+        let heartbeat = self.options.heartbeat.as_ref().map(|h| h.period() );
+
+        match (self.options.lease, heartbeat) {
+            (Some(l), Some(h)) => Some(cmp::min(l, h)),
+            (l, h) => l.or(h),
+        }
+    }
+
+    /// Release all devices bound by the specified connection
+    fn release(&self, conn: u64) {
+        let owned: Vec<_> = {
+            let mut owners = self.owners.lock().unwrap();
+            let owned: Vec<_> = owners.iter().filter(|(_k, o)| **o == conn).map(|(k, _o)| k.clone()).collect();
+            for k in &owned {
+                owners.remove(k);
+            }
+            owned
+        };
+
+        for (kind, device) in owned {
+            info!("releasing {:?} device: {} (connection: {})", kind, device, conn);
+
+            // Dropping local devices closes them and restores pin state
+            match kind {
+                DeviceKind::Spi => { self.spi.lock().unwrap().remove(&device); },
+                DeviceKind::I2c => { self.i2c.lock().unwrap().remove(&device); },
+                DeviceKind::Pin => { self.pin.lock().unwrap().remove(&device); },
+                DeviceKind::Serial => { self.serial.lock().unwrap().remove(&device); },
+                DeviceKind::Pwm => { self.pwm.lock().unwrap().remove(&device); },
+                DeviceKind::Adc => { self.adc.lock().unwrap().remove(&device); },
+                DeviceKind::Can => { self.can.lock().unwrap().remove(&device); },
+            }
+        }
+    }
+
+    /// Record the connection owning a bound device
+    fn bind_owner(&self, conn: &Connection, kind: DeviceKind, device: &str) {
+        self.owners.lock().unwrap().insert((kind, device.to_owned()), conn.id());
+    }
+
+    /// Check whether a device is bound by the provided connection, failing where
+    /// the device is bound by another connection
+    fn check_owner(&self, conn: &Connection, kind: DeviceKind, device: &str) -> Result<bool, Error> {
+        match self.owners.lock().unwrap().get(&(kind, device.to_owned())) {
+            Some(o) if *o == conn.id() => Ok(true),
+            Some(_o) => Err(Error::NotPermitted(format!("{:?} device {} is bound by another connection", kind, device))),
+            None => Ok(false),
+        }
+    }
+
+    /// Remove ownership of a device on disconnection
+    fn unbind_owner(&self, kind: DeviceKind, device: &str) {
+        self.owners.lock().unwrap().remove(&(kind, device.to_owned()));
+    }
+
+    /// Fetch the capabilities of the server
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            protocol_version: PROTOCOL_VERSION,
+            server_version: CRATE_VERSION.to_owned(),
+            devices: vec![DeviceKind::Spi, DeviceKind::I2c, DeviceKind::Pin, DeviceKind::Serial, DeviceKind::Pwm, DeviceKind::Adc, DeviceKind::Can],
+            codecs: self.options.codecs.clone(),
+            max_transfer: self.options.max_transfer,
+            heartbeat_ms: self.options.heartbeat.as_ref().map(|h| h.interval.as_millis() as u32 ),
+            max_delay_us: Some(self.options.max_delay.as_micros() as u64),
+        }
+    }
+
+    /// Check request transfer lengths against the configured limit
+    fn check_transfer(&self, req: &RequestKind) -> Result<(), Error> {
+        let len = transfer_len(req);
+
+        if len > self.options.max_transfer as usize {
+            return Err(Error::TransferTooLarge(len));
+        }
+
+        Ok(())
+    }
+
+    /// Check requests do not delay the server beyond the configured limit
+    fn check_delay(&self, req: &RequestKind) -> Result<(), Error> {
+        let us = req.delay_us();
+
+        if us > self.options.max_delay.as_micros() as u64 {
+            return Err(Error::NotPermitted(format!("request delay of {} us exceeds limit of {:?}", us, self.options.max_delay)));
+        }
+
+        Ok(())
+    }
+
+    /// Check sequences only contain device operations and delays
+    ///
+    /// Nested sequences, hellos, device connections and subscriptions are rejected up
+    /// front, as clients only track bindings and subscriptions requested directly
+    fn check_sequence(&self, req: &RequestKind) -> Result<(), Error> {
+        use RequestKind::*;
+
+        let steps = match req {
+            Sequence(q) => &q.steps,
+            _ => return Ok(()),
+        };
+
+        for step in steps {
+            let kind = match step {
+                SequenceStep::Request{kind, ..} => kind,
+                SequenceStep::DelayUs(_) => continue,
+            };
+
+            let name = match kind {
+                Sequence(_) => "nested sequence",
+                Hello(_) => "hello",
+                SpiConnect(_) | PinConnect(_) | I2cConnect | SerialConnect(_) | PwmConnect | AdcConnect | CanConnect => "connect",
+                SpiDisconnect | PinDisconnect | I2cDisconnect | SerialDisconnect | PwmDisconnect | AdcDisconnect | CanDisconnect => "disconnect",
+                PinSubscribe(_) | PinUnsubscribe | CanSubscribe(_) | CanUnsubscribe => "subscription",
+                _ => continue,
+            };
+
+            return Err(Error::InvalidArgument(format!("{} requests are not permitted in sequences", name)));
+        }
+
+        Ok(())
+    }
+
+    pub fn handle(&mut self, conn: &Connection, id: u64, device: &str, req: RequestKind) -> Result<ResponseKind, Error> {
+        self.check_transfer(&req)?;
+        self.check_delay(&req)?;
+        self.check_sequence(&req)?;
+
+        // Resolve named devices where a configuration is provided
+        let (path, req) = match &self.options.config {
+            Some(c) => c.resolve(device, req)?,
+            None => (device.to_owned(), req),
+        };
+        let device = path.as_str();
+
         let resp = match req {
             RequestKind::Ping => ResponseKind::Ok,
+
+            // Standalone delays are answered using a timer in `bind`, so this only handles
+            // delays within sequences where handlers are already run outside the reactor
+            RequestKind::Delay(d) => {
+                info!("received Delay ({} us)", d.us);
+                thread::sleep(Duration::from_micros(d.us as u64));
+                ResponseKind::Ok
+            },
+
+            RequestKind::Sequence(q) => {
+                info!("received Sequence ({} steps)", q.steps.len());
+                let mut resps = Vec::with_capacity(q.steps.len());
+
+                for step in q.steps {
+                    let resp = match step {
+                        SequenceStep::Request{device, kind} => match self.handle(conn, id, &device, kind) {
+                            Ok(r) => r,
+                            Err(e) => ResponseKind::Error(e.into()),
+                        },
+                        // Sequences run outside the reactor (see `bind`), so may sleep
+                        SequenceStep::DelayUs(us) => {
+                            thread::sleep(Duration::from_micros(us as u64));
+                            ResponseKind::Ok
+                        },
+                    };
+
+                    // Stop at the first failed step
+                    let failed = matches!(&resp, ResponseKind::Error(_) | ResponseKind::Unhandled | ResponseKind::DeviceAlreadyBound | ResponseKind::DeviceNotBound);
+
+                    resps.push(resp);
+
+                    if failed {
+                        break;
+                    }
+                }
+
+                ResponseKind::Sequence(resps)
+            },
+
+            RequestKind::Hello(h) => {
+                info!("received Hello (protocol version: {}, client version: {})", h.protocol_version, h.client_version);
+                if h.protocol_version != PROTOCOL_VERSION {
+                    warn!("client protocol version {} does not match server version {}", h.protocol_version, PROTOCOL_VERSION);
+                }
+
+                ResponseKind::Capabilities(self.capabilities())
+            },
             
             RequestKind::SpiConnect(c) => {
                 info!("received SpiConnect (device: {}, baud: {}, mode: {:?})", device, c.baud, c.mode);
@@ -78,7 +448,9 @@ impl Server {
                 match spi_map.entry(device.to_owned()) {
                     Entry::Occupied(_e) => ResponseKind::DeviceAlreadyBound,
                     Entry::Vacant(v) => {
-                        v.insert(Spi::new(device, c.baud, c.mode)?);
+                        let spi = self.backend.lock().unwrap().spi(device, c.baud, c.mode.unwrap_or(SpiMode::Mode0))?;
+                        v.insert(Arc::new(Mutex::new(spi)));
+                        self.bind_owner(conn, DeviceKind::Spi, device);
                         ResponseKind::Ok
                     },
                 }
@@ -87,42 +459,101 @@ impl Server {
 
             RequestKind::SpiDisconnect => {
                 info!("received SpiDisconnect (device: {})", device);
+                if !self.check_owner(conn, DeviceKind::Spi, device)? {
+                    return Ok(ResponseKind::DeviceNotBound);
+                }
                 let mut spi = self.spi.lock().unwrap();
                 match spi.remove(device) {
-                    Some(_d) => ResponseKind::Ok,
+                    Some(_d) => {
+                        self.unbind_owner(DeviceKind::Spi, device);
+                        ResponseKind::Ok
+                    },
                     None => ResponseKind::DeviceNotBound,
                 }
             },
 
             RequestKind::SpiTransfer{write_data} => {
                 info!("received SpiTransfer");
-                let mut spi_map = self.spi.lock().unwrap();
-                let spi = match spi_map.get_mut(device) {
+                let spi = match bound(&self.spi, device) {
                     Some(s) => s,
                     None => return Ok(ResponseKind::DeviceNotBound),
                 };
+                let mut spi = spi.lock().unwrap();
 
                 let mut d = write_data.data.clone();
 
-                match SpiTransfer::transfer(spi, &mut d) {
-                    Ok(d) => ResponseKind::SpiTransfer(d.to_vec()),
-                    Err(e) => ResponseKind::Error(format!("{:?}", e)),
+                match spi.transfer(&mut d) {
+                    Ok(_) => ResponseKind::SpiTransfer(d),
+                    Err(e) => ResponseKind::Error(e.into()),
                 }
             },
 
             RequestKind::SpiWrite{write_data} => {
                 info!("received SpiWrite");
-                let mut spi_map = self.spi.lock().unwrap();
-                let spi = match spi_map.get_mut(device) {
+                let spi = match bound(&self.spi, device) {
                     Some(s) => s,
                     None => return Ok(ResponseKind::DeviceNotBound),
                 };
+                let mut spi = spi.lock().unwrap();
 
-                let mut d = write_data.data.clone();
+                let d = write_data.data.clone();
 
-                match SpiWrite::write(spi, &mut d) {
+                match spi.write(&d) {
                     Ok(_) => ResponseKind::Ok,
-                    Err(e) => ResponseKind::Error(format!("{:?}", e)),
+                    Err(e) => ResponseKind::Error(e.into()),
+                }
+            },
+
+            RequestKind::SpiTransaction(t) => {
+                info!("received SpiTransaction (device: {}, cs: {:?}, ops: {})", device, t.cs, t.ops.len());
+
+                // Resolve named chip select pins
+                let cs = match (t.cs, &self.options.config) {
+                    (Some(cs), Some(c)) => Some(c.resolve(&cs, RequestKind::PinGet)?.0),
+                    (cs, _) => cs,
+                };
+
+                // Chip select pins must be bound by the requesting connection
+                let cs = match cs {
+                    Some(c) => {
+                        if !self.check_owner(conn, DeviceKind::Pin, &c)? {
+                            return Ok(ResponseKind::DeviceNotBound);
+                        }
+                        match bound(&self.pin, &c) {
+                            Some(p) => Some(p),
+                            None => return Ok(ResponseKind::DeviceNotBound),
+                        }
+                    },
+                    None => None,
+                };
+
+                let spi = match bound(&self.spi, device) {
+                    Some(s) => s,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+
+                // Device locks are held for the transaction, map locks are released by `bound`
+                let mut spi = spi.lock().unwrap();
+                let mut cs = cs.as_ref().map(|p| p.lock().unwrap() );
+
+                let res = match &mut cs {
+                    Some(p) => {
+                        p.set(false)?;
+                        // Devices without native delays are split around these while the
+                        // software chip select is held asserted
+                        let res = match spi.transaction(&t.ops) {
+                            Err(Error::Unsupported(_)) => backend::software_cs_transaction(&mut **spi, &t.ops),
+                            r => r,
+                        };
+                        p.set(true)?;
+                        res
+                    },
+                    None => spi.transaction(&t.ops),
+                };
+
+                match res {
+                    Ok(d) => ResponseKind::SpiTransaction(d),
+                    Err(e) => ResponseKind::Error(e.into()),
                 }
             },
 
@@ -133,7 +564,8 @@ impl Server {
                 match i2c.entry(device.to_owned()) {
                     Entry::Occupied(_e) => ResponseKind::DeviceAlreadyBound,
                     Entry::Vacant(v) => {
-                        v.insert(I2c::new(device)?);
+                        v.insert(self.backend.lock().unwrap().i2c(device)?);
+                        self.bind_owner(conn, DeviceKind::I2c, device);
                         ResponseKind::Ok
                     },
                 }
@@ -141,9 +573,15 @@ impl Server {
 
             RequestKind::I2cDisconnect => {
                 info!("received I2cDisconnect (device: {})", device);
+                if !self.check_owner(conn, DeviceKind::I2c, device)? {
+                    return Ok(ResponseKind::DeviceNotBound);
+                }
                 let mut i2c = self.i2c.lock().unwrap();
                 match i2c.remove(device) {
-                    Some(_d) => ResponseKind::Ok,
+                    Some(_d) => {
+                        self.unbind_owner(DeviceKind::I2c, device);
+                        ResponseKind::Ok
+                    },
                     None => ResponseKind::DeviceNotBound,
                 }
             },
@@ -156,9 +594,9 @@ impl Server {
                     None => return Ok(ResponseKind::DeviceNotBound),
                 };
 
-                match I2cWrite::write(i2c, c.addr, &c.write_data.data) {
+                match i2c.write(c.addr, &c.write_data.data) {
                     Ok(_) => ResponseKind::Ok,
-                    Err(e) => ResponseKind::Error(format!("{:?}", e)),
+                    Err(e) => ResponseKind::Error(e.into()),
                 }
             },
 
@@ -172,9 +610,9 @@ impl Server {
 
                 let mut buff = vec![0; c.read_len as usize];
 
-                match I2cRead::read(i2c, c.addr, &mut buff) {
+                match i2c.read(c.addr, &mut buff) {
                     Ok(_) => ResponseKind::I2cRead(buff),
-                    Err(e) => ResponseKind::Error(format!("{:?}", e)),
+                    Err(e) => ResponseKind::Error(e.into()),
                 }
             },
 
@@ -188,9 +626,65 @@ impl Server {
 
                 let mut buff = vec![0; c.read_len as usize];
 
-                match I2cWriteRead::write_read(i2c, c.addr, &c.write_data.data, &mut buff) {
+                match i2c.write_read(c.addr, &c.write_data.data, &mut buff) {
                     Ok(_) => ResponseKind::I2cRead(buff),
-                    Err(e) => ResponseKind::Error(format!("{:?}", e)),
+                    Err(e) => ResponseKind::Error(e.into()),
+                }
+            },
+
+            RequestKind::I2cTransaction(t) => {
+                info!("received I2cTransaction (address: {}, ops: {:?})", t.addr, t.ops);
+                let mut i2c_map = self.i2c.lock().unwrap();
+                let i2c = match i2c_map.get_mut(device) {
+                    Some(s) => s,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+
+                match i2c.transaction(t.addr, &t.ops) {
+                    Ok(d) => ResponseKind::I2cTransaction(d),
+                    Err(e) => ResponseKind::Error(e.into()),
+                }
+            },
+
+            RequestKind::I2cScan(c) => {
+                info!("received I2cScan (method: {:?}, first: 0x{:02x}, last: 0x{:02x})", c.method, c.first, c.last);
+                if c.first > c.last || c.last > 0x7f {
+                    return Err(Error::NotPermitted(format!("invalid scan range 0x{:02x}-0x{:02x}", c.first, c.last)));
+                }
+
+                let mut i2c_map = self.i2c.lock().unwrap();
+                let i2c = match i2c_map.get_mut(device) {
+                    Some(s) => s,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+
+                let mut found = vec![];
+                for addr in c.first..=c.last {
+                    if i2c.probe(addr, c.method)? {
+                        found.push(addr);
+                    }
+                }
+
+                ResponseKind::I2cScan(found)
+            },
+
+            RequestKind::SmbusReadByte(_) | RequestKind::SmbusWriteByte(_) | RequestKind::SmbusReadWord(_) | RequestKind::SmbusWriteWord(_) |
+            RequestKind::SmbusReadBlock(_) | RequestKind::SmbusWriteBlock(_) | RequestKind::SmbusProcessCall(_) => {
+                let (addr, pec, op) = match req.smbus_op() {
+                    Some(v) => v,
+                    None => return Ok(ResponseKind::Unhandled),
+                };
+
+                info!("received SMBus request (address: {}, pec: {}, op: {:?})", addr, pec, op);
+                let mut i2c_map = self.i2c.lock().unwrap();
+                let i2c = match i2c_map.get_mut(device) {
+                    Some(s) => s,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+
+                match i2c.smbus(addr, pec, &op) {
+                    Ok(r) => r,
+                    Err(e) => ResponseKind::Error(e.into()),
                 }
             },
 
@@ -201,8 +695,9 @@ impl Server {
                 match pin.entry(device.to_owned()) {
                     Entry::Occupied(_e) => ResponseKind::DeviceAlreadyBound,
                     Entry::Vacant(v) => {
-                        let p = Pin::new(device, mode)?;
-                        v.insert(p);
+                        let p = self.backend.lock().unwrap().pin(device, mode)?;
+                        v.insert(Arc::new(Mutex::new(p)));
+                        self.bind_owner(conn, DeviceKind::Pin, device);
                         ResponseKind::Ok
                     },
                 }
@@ -210,9 +705,13 @@ impl Server {
 
             RequestKind::PinDisconnect => {
                 info!("received PinDisconnect (device: {})", device);
+                if !self.check_owner(conn, DeviceKind::Pin, device)? {
+                    return Ok(ResponseKind::DeviceNotBound);
+                }
                 let mut pins = self.pin.lock().unwrap();
                 match pins.remove(device) {
                     Some(_p) => {
+                        self.unbind_owner(DeviceKind::Pin, device);
                         ResponseKind::Ok
                     },
                     None => ResponseKind::DeviceNotBound
@@ -221,40 +720,415 @@ impl Server {
 
             RequestKind::PinSet(c) => {
                 info!("received PinSet");
-                let mut pin_map = self.pin.lock().unwrap();
-                let pin = match pin_map.get_mut(device) {
-                    Some(s) => s,
+                let pin = match bound(&self.pin, device) {
+                    Some(p) => p,
                     None => return Ok(ResponseKind::DeviceNotBound),
                 };
+                let mut pin = pin.lock().unwrap();
 
-                let res: Result<_, ()> = Ok(match c.value {
-                    true => OutputPin::set_high(pin),
-                    false => OutputPin::set_low(pin),
-                });
-
-                match res {
+                match pin.set(c.value) {
                     Ok(_) => ResponseKind::Ok,
-                    Err(e) => ResponseKind::Error(format!("{:?}", e)),
+                    Err(e) => ResponseKind::Error(e.into()),
                 }
             },
 
             RequestKind::PinGet => {
                 info!("received PinGet");
-                let mut pin_map = self.pin.lock().unwrap();
-                let pin = match pin_map.get_mut(device) {
+                let pin = match bound(&self.pin, device) {
+                    Some(p) => p,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+                let pin = pin.lock().unwrap();
+
+                match pin.get() {
+                    Ok(v) => ResponseKind::PinGet(v),
+                    Err(e) => ResponseKind::Error(e.into()),
+                }
+            },
+
+            RequestKind::PinSubscribe(edge) => {
+                info!("received PinSubscribe (device: {}, edge: {:?})", device, edge);
+                let pin = match bound(&self.pin, device) {
+                    Some(p) => p,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+                let mut pin = pin.lock().unwrap();
+
+                // Events are pushed using the id of the subscription request
+                let conn = conn.clone();
+                pin.subscribe(edge, Box::new(move |e| conn.send(Response{id, kind: ResponseKind::PinEvent(e)}) ))?;
+
+                ResponseKind::Ok
+            },
+
+            RequestKind::PinUnsubscribe => {
+                info!("received PinUnsubscribe (device: {})", device);
+                let pin = match bound(&self.pin, device) {
+                    Some(p) => p,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+                let mut pin = pin.lock().unwrap();
+
+                pin.unsubscribe();
+
+                ResponseKind::Ok
+            },
+
+            RequestKind::SerialConnect(c) => {
+                info!("received SerialConnect (device: {}, config: {:?})", device, c);
+                let mut serial = self.serial.lock().unwrap();
+
+                match serial.entry(device.to_owned()) {
+                    Entry::Occupied(_e) => ResponseKind::DeviceAlreadyBound,
+                    Entry::Vacant(v) => {
+                        let serial = self.backend.lock().unwrap().serial(device, &c)?;
+                        v.insert(Arc::new(Mutex::new(serial)));
+                        self.bind_owner(conn, DeviceKind::Serial, device);
+                        ResponseKind::Ok
+                    },
+                }
+            },
+
+            RequestKind::SerialDisconnect => {
+                info!("received SerialDisconnect (device: {})", device);
+                if !self.check_owner(conn, DeviceKind::Serial, device)? {
+                    return Ok(ResponseKind::DeviceNotBound);
+                }
+                let mut serial = self.serial.lock().unwrap();
+                match serial.remove(device) {
+                    Some(_d) => {
+                        self.unbind_owner(DeviceKind::Serial, device);
+                        ResponseKind::Ok
+                    },
+                    None => ResponseKind::DeviceNotBound,
+                }
+            },
+
+            RequestKind::SerialWrite{write_data} => {
+                info!("received SerialWrite (data: {:?})", write_data);
+                let serial = match bound(&self.serial, device) {
                     Some(s) => s,
                     None => return Ok(ResponseKind::DeviceNotBound),
                 };
+                let mut serial = serial.lock().unwrap();
 
-                let v: Result<_, ()> = Ok(InputPin::is_high(pin));
+                match serial.write_all(&write_data.data) {
+                    Ok(_) => ResponseKind::Ok,
+                    Err(e) => ResponseKind::Error(e.into()),
+                }
+            },
 
-                match v {
-                    Ok(v) => ResponseKind::PinGet(v),
-                    Err(e) => ResponseKind::Error(format!("{:?}", e)),
+            RequestKind::SerialRead(c) => {
+                info!("received SerialRead (len: {}, timeout: {} ms)", c.max_len, c.timeout_ms);
+                let serial = match bound(&self.serial, device) {
+                    Some(s) => s,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+                let mut serial = serial.lock().unwrap();
+
+                // Reads are bounded by the delay limit so clients cannot hold a handler indefinitely
+                let timeout = Duration::from_millis(c.timeout_ms as u64).min(self.options.max_delay);
+                let mut buff = vec![0; c.max_len as usize];
+
+                match serial.read_timeout(&mut buff, timeout) {
+                    Ok(n) => ResponseKind::SerialRead(buff[..n].to_vec()),
+                    Err(e) => ResponseKind::Error(e.into()),
+                }
+            },
+
+            RequestKind::SerialFlush => {
+                info!("received SerialFlush");
+                let serial = match bound(&self.serial, device) {
+                    Some(s) => s,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+                let mut serial = serial.lock().unwrap();
+
+                match serial.flush() {
+                    Ok(_) => ResponseKind::Ok,
+                    Err(e) => ResponseKind::Error(e.into()),
+                }
+            },
+
+            RequestKind::PwmConnect => {
+                info!("received PwmConnect (device: {})", device);
+                let mut pwm = self.pwm.lock().unwrap();
+
+                match pwm.entry(device.to_owned()) {
+                    Entry::Occupied(_e) => ResponseKind::DeviceAlreadyBound,
+                    Entry::Vacant(v) => {
+                        v.insert(self.backend.lock().unwrap().pwm(device)?);
+                        self.bind_owner(conn, DeviceKind::Pwm, device);
+                        ResponseKind::Ok
+                    },
+                }
+            },
+
+            RequestKind::PwmDisconnect => {
+                info!("received PwmDisconnect (device: {})", device);
+                if !self.check_owner(conn, DeviceKind::Pwm, device)? {
+                    return Ok(ResponseKind::DeviceNotBound);
+                }
+                let mut pwm = self.pwm.lock().unwrap();
+                match pwm.remove(device) {
+                    Some(_d) => {
+                        self.unbind_owner(DeviceKind::Pwm, device);
+                        ResponseKind::Ok
+                    },
+                    None => ResponseKind::DeviceNotBound,
+                }
+            },
+
+            RequestKind::PwmSetPeriod(_) | RequestKind::PwmSetDuty(_) | RequestKind::PwmSetPolarity(_) |
+            RequestKind::PwmEnable | RequestKind::PwmDisable => {
+                info!("received {:?} (device: {})", req, device);
+                let mut pwm_map = self.pwm.lock().unwrap();
+                let pwm = match pwm_map.get_mut(device) {
+                    Some(p) => p,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+
+                let res = match req {
+                    RequestKind::PwmSetPeriod(c) => pwm.set_period(c.period_ns),
+                    RequestKind::PwmSetDuty(c) => pwm.set_duty(c.duty_ns),
+                    RequestKind::PwmSetPolarity(p) => pwm.set_polarity(p),
+                    RequestKind::PwmEnable => pwm.set_enabled(true),
+                    _ => pwm.set_enabled(false),
+                };
+
+                match res {
+                    Ok(_) => ResponseKind::Ok,
+                    Err(e) => ResponseKind::Error(e.into()),
+                }
+            },
+
+            RequestKind::PwmGet => {
+                info!("received PwmGet (device: {})", device);
+                let mut pwm_map = self.pwm.lock().unwrap();
+                let pwm = match pwm_map.get_mut(device) {
+                    Some(p) => p,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+
+                match pwm.state() {
+                    Ok(s) => ResponseKind::PwmState(s),
+                    Err(e) => ResponseKind::Error(e.into()),
+                }
+            },
+
+            RequestKind::AdcConnect => {
+                info!("received AdcConnect (device: {})", device);
+                let mut adc = self.adc.lock().unwrap();
+
+                match adc.entry(device.to_owned()) {
+                    Entry::Occupied(_e) => ResponseKind::DeviceAlreadyBound,
+                    Entry::Vacant(v) => {
+                        let adc = self.backend.lock().unwrap().adc(device)?;
+                        v.insert(Arc::new(Mutex::new(adc)));
+                        self.bind_owner(conn, DeviceKind::Adc, device);
+                        ResponseKind::Ok
+                    },
+                }
+            },
+
+            RequestKind::AdcDisconnect => {
+                info!("received AdcDisconnect (device: {})", device);
+                if !self.check_owner(conn, DeviceKind::Adc, device)? {
+                    return Ok(ResponseKind::DeviceNotBound);
+                }
+                let mut adc = self.adc.lock().unwrap();
+                match adc.remove(device) {
+                    Some(_d) => {
+                        self.unbind_owner(DeviceKind::Adc, device);
+                        ResponseKind::Ok
+                    },
+                    None => ResponseKind::DeviceNotBound,
+                }
+            },
+
+            RequestKind::AdcRead(c) => {
+                info!("received AdcRead (device: {}, channel: {})", device, c.channel);
+                let adc = match bound(&self.adc, device) {
+                    Some(a) => a,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+                let mut adc = adc.lock().unwrap();
+
+                match adc.read(&c.channel) {
+                    Ok(r) => ResponseKind::AdcRead(r),
+                    Err(e) => ResponseKind::Error(e.into()),
+                }
+            },
+
+            RequestKind::AdcCapture(c) => {
+                info!("received AdcCapture (device: {}, channels: {:?}, samples: {}, frequency: {:?})", device, c.channels, c.samples, c.frequency);
+                let adc = match bound(&self.adc, device) {
+                    Some(a) => a,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+                let mut adc = adc.lock().unwrap();
+
+                // Captures are bounded by the delay limit so clients cannot hold a handler indefinitely
+                match adc.capture(&c, self.options.max_delay) {
+                    Ok(d) => ResponseKind::AdcCapture(d),
+                    Err(e) => ResponseKind::Error(e.into()),
+                }
+            },
+
+            RequestKind::CanConnect => {
+                info!("received CanConnect (device: {})", device);
+                let mut can = self.can.lock().unwrap();
+
+                match can.entry(device.to_owned()) {
+                    Entry::Occupied(_e) => ResponseKind::DeviceAlreadyBound,
+                    Entry::Vacant(v) => {
+                        let can = self.backend.lock().unwrap().can(device)?;
+                        v.insert(Arc::new(Mutex::new(can)));
+                        self.bind_owner(conn, DeviceKind::Can, device);
+                        ResponseKind::Ok
+                    },
+                }
+            },
+
+            RequestKind::CanDisconnect => {
+                info!("received CanDisconnect (device: {})", device);
+                if !self.check_owner(conn, DeviceKind::Can, device)? {
+                    return Ok(ResponseKind::DeviceNotBound);
+                }
+                let mut can = self.can.lock().unwrap();
+                match can.remove(device) {
+                    Some(_d) => {
+                        self.unbind_owner(DeviceKind::Can, device);
+                        ResponseKind::Ok
+                    },
+                    None => ResponseKind::DeviceNotBound,
                 }
             },
+
+            RequestKind::CanSend(c) => {
+                info!("received CanSend (device: {}, frame: {:?})", device, c.frame);
+                let can = match bound(&self.can, device) {
+                    Some(d) => d,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+                let mut can = can.lock().unwrap();
+
+                match can.send(&c.frame) {
+                    Ok(_) => ResponseKind::Ok,
+                    Err(e) => ResponseKind::Error(e.into()),
+                }
+            },
+
+            RequestKind::CanSetFilters(c) => {
+                info!("received CanSetFilters (device: {}, filters: {:?})", device, c.filters);
+                let can = match bound(&self.can, device) {
+                    Some(d) => d,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+                let mut can = can.lock().unwrap();
+
+                match can.set_filters(&c.filters) {
+                    Ok(_) => ResponseKind::Ok,
+                    Err(e) => ResponseKind::Error(e.into()),
+                }
+            },
+
+            RequestKind::CanReceive(c) => {
+                info!("received CanReceive (device: {}, timeout: {} ms)", device, c.timeout_ms);
+                let can = match bound(&self.can, device) {
+                    Some(d) => d,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+                let mut can = can.lock().unwrap();
+
+                // Receives are bounded by the delay limit so clients cannot hold a handler indefinitely
+                let timeout = Duration::from_millis(c.timeout_ms as u64).min(self.options.max_delay);
+
+                match can.receive(timeout) {
+                    Ok(f) => ResponseKind::CanReceive(f),
+                    Err(e) => ResponseKind::Error(e.into()),
+                }
+            },
+
+            RequestKind::CanSubscribe(c) => {
+                info!("received CanSubscribe (device: {}, filters: {:?})", device, c.filters);
+                let can = match bound(&self.can, device) {
+                    Some(d) => d,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+                let mut can = can.lock().unwrap();
+
+                // Frames are pushed using the id of the subscription request
+                let conn = conn.clone();
+                can.subscribe(&c.filters, Box::new(move |f| conn.send(Response{id, kind: ResponseKind::CanFrame(f)}) ))?;
+
+                ResponseKind::Ok
+            },
+
+            RequestKind::CanUnsubscribe => {
+                info!("received CanUnsubscribe (device: {})", device);
+                let can = match bound(&self.can, device) {
+                    Some(d) => d,
+                    None => return Ok(ResponseKind::DeviceNotBound),
+                };
+                let mut can = can.lock().unwrap();
+
+                can.unsubscribe();
+
+                ResponseKind::Ok
+            },
         };
 
         Ok(resp)
     }
 }
+
+/// Fetch a bound device handle, releasing the device map before the device is used
+fn bound<T: ?Sized>(map: &Mutex<HashMap<String, Arc<Mutex<Box<T>>>>>, device: &str) -> Option<Arc<Mutex<Box<T>>>> {
+    map.lock().unwrap().get(device).cloned()
+}
+
+/// Warns (once) where blocking handlers are run in place
+static BLOCKING_FALLBACK: Once = Once::new();
+
+/// Run a blocking function on the threadpool, falling back to running in place
+/// where the blocking API is unavailable (ie. outside a threadpool executor)
+///
+/// Handlers run in place block the executor, stalling other connections, so servers
+/// should be run on the (default) threadpool runtime
+fn blocking<T, F>(f: F) -> impl Future<Item=T, Error=Error>
+where
+    F: FnOnce() -> T,
+{
+    let mut f = Some(f);
+
+    future::poll_fn(move || {
+        match tokio_threadpool::blocking(|| (f.take().unwrap())() ) {
+            Ok(r) => Ok(r),
+            Err(e) => {
+                BLOCKING_FALLBACK.call_once(|| warn!("running blocking handlers on the executor ({}), other connections will be stalled", e) );
+                Ok(Async::Ready((f.take().unwrap())()))
+            },
+        }
+    })
+}
+
+/// Fetch the data length transferred by a request
+fn transfer_len(req: &RequestKind) -> usize {
+    match req {
+        RequestKind::SpiTransfer{write_data} | RequestKind::SpiWrite{write_data} | RequestKind::SerialWrite{write_data} => write_data.data.len(),
+        RequestKind::I2cWrite(c) => c.write_data.data.len(),
+        RequestKind::I2cRead(c) => c.read_len as usize,
+        RequestKind::I2cWriteRead(c) => c.write_data.data.len().max(c.read_len as usize),
+        RequestKind::SerialRead(c) => c.max_len as usize,
+        RequestKind::SpiTransaction(t) => t.ops.iter().map(|o| o.data_len() ).sum(),
+        RequestKind::I2cTransaction(t) => t.ops.iter().map(|o| o.data_len() ).sum(),
+        RequestKind::SmbusWriteBlock(c) => c.data.data.len(),
+        RequestKind::AdcCapture(c) => c.samples as usize * c.channels.len() * std::mem::size_of::<i64>(),
+        RequestKind::Sequence(q) => q.steps.iter().map(|s| match s {
+            SequenceStep::Request{kind, ..} => transfer_len(kind),
+            SequenceStep::DelayUs(_) => 0,
+        }).sum(),
+        _ => 0,
+    }
+}
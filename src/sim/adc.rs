@@ -0,0 +1,81 @@
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use embedded_hal::adc::{Channel, OneShot};
+
+use crate::adc::voltage_channel;
+use crate::common::{AdcReading, AdcCapture, AdcChannelData};
+use crate::server::backend::AdcDevice;
+use crate::error::Error;
+
+/// Simulated ADC, channels return fixed readings set via the simulated client
+#[derive(Clone)]
+pub struct Adc {
+    channels: Arc<Mutex<HashMap<String, AdcReading>>>,
+}
+
+impl Adc {
+    pub(crate) fn new(channels: Arc<Mutex<HashMap<String, AdcReading>>>) -> Self {
+        Self{channels}
+    }
+
+    pub fn read(&mut self, channel: &str) -> Result<AdcReading, Error> {
+        match self.channels.lock().unwrap().get(channel) {
+            Some(r) => Ok(r.clone()),
+            None => Err(Error::UnknownDevice(format!("no adc channel {}", channel))),
+        }
+    }
+
+    /// Capture samples, each sample is the current channel reading
+    pub fn capture(&mut self, capture: &AdcCapture) -> Result<Vec<AdcChannelData>, Error> {
+        if capture.channels.is_empty() || capture.samples == 0 {
+            return Err(Error::NotPermitted("captures require at least one channel and sample".to_owned()));
+        }
+
+        capture.channels.iter().map(|c| {
+            let r = self.read(c)?;
+            Ok(AdcChannelData {
+                channel: c.clone(),
+                scale: r.scale,
+                offset: r.offset,
+                samples: vec![r.raw; capture.samples as usize],
+            })
+        }).collect()
+    }
+}
+
+impl<P> OneShot<Adc, i32, P> for Adc
+where
+    P: Channel<Adc, ID=u8>,
+{
+    type Error = Error;
+
+    fn read(&mut self, _pin: &mut P) -> nb::Result<i32, Error> {
+        let r = Adc::read(self, &voltage_channel(P::channel())).map_err(nb::Error::Other)?;
+        Ok(r.raw as i32)
+    }
+}
+
+impl<P> OneShot<Adc, u16, P> for Adc
+where
+    P: Channel<Adc, ID=u8>,
+{
+    type Error = Error;
+
+    fn read(&mut self, _pin: &mut P) -> nb::Result<u16, Error> {
+        let r = Adc::read(self, &voltage_channel(P::channel())).map_err(nb::Error::Other)?;
+        Ok(r.raw as u16)
+    }
+}
+
+impl AdcDevice for Adc {
+    fn read(&mut self, channel: &str) -> Result<AdcReading, Error> {
+        Adc::read(self, channel)
+    }
+
+    fn capture(&mut self, capture: &AdcCapture, _timeout: Duration) -> Result<Vec<AdcChannelData>, Error> {
+        Adc::capture(self, capture)
+    }
+}
@@ -0,0 +1,138 @@
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::random;
+
+use crate::common::{CanFrame, CanFilter};
+use crate::server::backend::CanDevice;
+use crate::error::Error;
+
+type Handler = Box<FnMut(CanFrame) + Send>;
+
+/// Simulated CAN bus, frames sent by a member are delivered to all other members
+#[derive(Default)]
+pub struct CanBus {
+    members: HashMap<u64, Member>,
+}
+
+#[derive(Default)]
+struct Member {
+    filters: Vec<CanFilter>,
+    rx: VecDeque<CanFrame>,
+    subscription: Option<(Vec<CanFilter>, Handler)>,
+}
+
+impl CanBus {
+    /// Send a frame on the bus, `from` (if provided) does not receive the frame
+    pub fn send(&mut self, from: Option<u64>, frame: &CanFrame) {
+        for (id, m) in self.members.iter_mut() {
+            if Some(*id) == from {
+                continue;
+            }
+
+            if CanFilter::accepts(&m.filters, frame) {
+                m.rx.push_back(frame.clone());
+            }
+
+            if let Some((filters, handler)) = &mut m.subscription {
+                if CanFilter::accepts(filters, frame) {
+                    handler(frame.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Simulated CAN interface
+pub struct Can {
+    id: u64,
+    bus: Arc<Mutex<CanBus>>,
+}
+
+impl Can {
+    pub(crate) fn new(bus: Arc<Mutex<CanBus>>) -> Self {
+        let id = random();
+        bus.lock().unwrap().members.insert(id, Member::default());
+        Self{id, bus}
+    }
+
+    fn member<R, F: FnOnce(&mut Member) -> R>(&self, f: F) -> R {
+        let mut bus = self.bus.lock().unwrap();
+        f(bus.members.get_mut(&self.id).unwrap())
+    }
+
+    pub fn send(&mut self, frame: &CanFrame) -> Result<(), Error> {
+        self.bus.lock().unwrap().send(Some(self.id), frame);
+        Ok(())
+    }
+
+    pub fn set_filters(&mut self, filters: &[CanFilter]) -> Result<(), Error> {
+        self.member(|m| m.filters = filters.to_vec() );
+        Ok(())
+    }
+
+    /// Receive a previously delivered frame, this never blocks
+    pub fn receive(&mut self, _timeout: Duration) -> Result<Option<CanFrame>, Error> {
+        Ok(self.member(|m| m.rx.pop_front() ))
+    }
+
+    pub fn subscribe<F>(&mut self, filters: &[CanFilter], handler: F) -> Result<(), Error>
+    where
+        F: FnMut(CanFrame) + Send + 'static,
+    {
+        self.member(|m| m.subscription = Some((filters.to_vec(), Box::new(handler))) );
+        Ok(())
+    }
+
+    pub fn unsubscribe(&mut self) {
+        self.member(|m| m.subscription = None );
+    }
+}
+
+impl Drop for Can {
+    fn drop(&mut self) {
+        self.bus.lock().unwrap().members.remove(&self.id);
+    }
+}
+
+impl embedded_can::blocking::Can for Can {
+    type Frame = CanFrame;
+    type Error = Error;
+
+    fn transmit(&mut self, frame: &CanFrame) -> Result<(), Error> {
+        self.send(frame)
+    }
+
+    /// Frames must be delivered prior to calling `receive`, as the simulation
+    /// is not threaded this fails with `Error::Timeout` rather than blocking
+    fn receive(&mut self) -> Result<CanFrame, Error> {
+        match Can::receive(self, Duration::from_millis(0))? {
+            Some(f) => Ok(f),
+            None => Err(Error::Timeout),
+        }
+    }
+}
+
+impl CanDevice for Can {
+    fn send(&mut self, frame: &CanFrame) -> Result<(), Error> {
+        Can::send(self, frame)
+    }
+
+    fn set_filters(&mut self, filters: &[CanFilter]) -> Result<(), Error> {
+        Can::set_filters(self, filters)
+    }
+
+    fn receive(&mut self, timeout: Duration) -> Result<Option<CanFrame>, Error> {
+        Can::receive(self, timeout)
+    }
+
+    fn subscribe(&mut self, filters: &[CanFilter], handler: Box<FnMut(CanFrame) + Send>) -> Result<(), Error> {
+        Can::subscribe(self, filters, handler)
+    }
+
+    fn unsubscribe(&mut self) {
+        Can::unsubscribe(self)
+    }
+}
@@ -0,0 +1,133 @@
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use embedded_hal::blocking::i2c;
+
+use crate::server::backend::I2cDevice;
+use crate::error::Error;
+
+/// Simulated I2C register-map device
+///
+/// Writes set the register pointer from the first byte then write any following
+/// bytes, reads return registers from the current pointer, and the pointer
+/// auto-increments (wrapping) on each byte.
+#[derive(Debug, Clone)]
+pub struct RegisterMap {
+    registers: Vec<u8>,
+    pointer: usize,
+}
+
+impl RegisterMap {
+    /// Create a register map with the provided number of (zeroed) registers
+    pub fn new(size: usize) -> Self {
+        Self{ registers: vec![0u8; size.max(1)], pointer: 0 }
+    }
+
+    /// Create a register map with the provided initial register values
+    pub fn with_registers(registers: &[u8]) -> Self {
+        let mut r = Self::new(registers.len());
+        r.registers[..registers.len()].copy_from_slice(registers);
+        r
+    }
+
+    /// Fetch register values
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    /// Set a register value
+    pub fn set(&mut self, reg: u8, value: u8) {
+        let n = self.registers.len();
+        self.registers[reg as usize % n] = value;
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        let (reg, data) = match data.split_first() {
+            Some(v) => v,
+            None => return,
+        };
+
+        let n = self.registers.len();
+        self.pointer = *reg as usize % n;
+
+        for b in data {
+            self.registers[self.pointer] = *b;
+            self.pointer = (self.pointer + 1) % n;
+        }
+    }
+
+    fn read(&mut self, buff: &mut [u8]) {
+        let n = self.registers.len();
+
+        for b in buff.iter_mut() {
+            *b = self.registers[self.pointer];
+            self.pointer = (self.pointer + 1) % n;
+        }
+    }
+}
+
+/// Simulated I2C bus
+#[derive(Clone)]
+pub struct I2c {
+    devices: Arc<Mutex<HashMap<u8, RegisterMap>>>,
+}
+
+impl I2c {
+    pub(crate) fn new(devices: Arc<Mutex<HashMap<u8, RegisterMap>>>) -> Self {
+        Self{devices}
+    }
+
+    fn with_device<F: FnOnce(&mut RegisterMap)>(&mut self, addr: u8, f: F) -> Result<(), Error> {
+        let mut devices = self.devices.lock().unwrap();
+
+        match devices.get_mut(&addr) {
+            Some(d) => {
+                f(d);
+                Ok(())
+            },
+            None => Err(Error::UnknownDevice(format!("no acknowledge from address 0x{:02x}", addr))),
+        }
+    }
+}
+
+impl i2c::Read for I2c {
+    type Error = Error;
+
+    fn read(&mut self, addr: u8, buff: &mut [u8]) -> Result<(), Self::Error> {
+        self.with_device(addr, |d| d.read(buff) )
+    }
+}
+
+impl i2c::Write for I2c {
+    type Error = Error;
+
+    fn write(&mut self, addr: u8, data: &[u8]) -> Result<(), Self::Error> {
+        self.with_device(addr, |d| d.write(data) )
+    }
+}
+
+impl i2c::WriteRead for I2c {
+    type Error = Error;
+
+    fn write_read(&mut self, addr: u8, data: &[u8], buff: &mut [u8]) -> Result<(), Self::Error> {
+        self.with_device(addr, |d| {
+            d.write(data);
+            d.read(buff);
+        })
+    }
+}
+
+impl I2cDevice for I2c {
+    fn read(&mut self, addr: u8, buff: &mut [u8]) -> Result<(), Error> {
+        i2c::Read::read(self, addr, buff)
+    }
+
+    fn write(&mut self, addr: u8, data: &[u8]) -> Result<(), Error> {
+        i2c::Write::write(self, addr, data)
+    }
+
+    fn write_read(&mut self, addr: u8, data: &[u8], buff: &mut [u8]) -> Result<(), Error> {
+        i2c::WriteRead::write_read(self, addr, data, buff)
+    }
+}
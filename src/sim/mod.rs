@@ -0,0 +1,404 @@
+//! Simulated devices for hardware-free testing
+//!
+//! `sim::Client` implements `Manager` (for use directly in driver tests) and the
+//! server `Backend` (for use in `rhd --sim`), exposing programmable virtual devices.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use futures::prelude::*;
+use futures::future::{ok, err};
+
+use crate::common::*;
+use crate::manager::Manager;
+use crate::server::backend::{Backend, SpiDevice, I2cDevice, PinDevice, SerialDevice, PwmDevice, AdcDevice, CanDevice};
+use crate::error::Error;
+
+pub mod spi;
+pub use spi::{Spi, SpiModel, SpiFlash};
+pub mod i2c;
+pub use i2c::{I2c, RegisterMap};
+pub mod pin;
+pub use pin::{Pin, Wiring, Notify};
+pub mod serial;
+pub use serial::Serial;
+pub mod pwm;
+pub use pwm::Pwm;
+pub mod adc;
+pub use adc::Adc;
+pub mod can;
+pub use can::{Can, CanBus};
+
+/// Simulated devices by path
+type Devices<T> = Arc<Mutex<HashMap<String, Arc<Mutex<T>>>>>;
+
+/// Simulated device client, virtual devices should be added prior to connecting
+#[derive(Clone, Default)]
+pub struct Client {
+    spi: Devices<SpiModel>,
+    i2c: Devices<HashMap<u8, RegisterMap>>,
+    serial: Arc<Mutex<HashMap<String, Serial>>>,
+    pwm: Devices<PwmState>,
+    adc: Devices<HashMap<String, AdcReading>>,
+    can: Devices<CanBus>,
+    wiring: Arc<Mutex<Wiring>>,
+}
+
+impl Client {
+    /// Create a new simulated client instance
+    pub fn new() -> impl Future<Item=Client, Error=Error> {
+        ok(Client::default())
+    }
+
+    /// Create a simulated client with devices from the provided configuration
+    pub fn from_config(config: &Config) -> Self {
+        let mut c = Client::default();
+
+        for s in &config.spi {
+            match &s.flash {
+                Some(f) => c.add_spi_flash(&s.path, SpiFlash::new(f.size, f.jedec_id)),
+                None => c.add_spi_loopback(&s.path),
+            }
+        }
+
+        for d in &config.i2c {
+            let mut regs = RegisterMap::new(d.registers);
+            for (i, v) in d.values.iter().enumerate() {
+                regs.set(i as u8, *v);
+            }
+            c.add_i2c_device(&d.bus, d.address, regs);
+        }
+
+        for a in &config.adc {
+            c.set_adc_reading(&a.path, &a.channel, AdcReading{raw: a.raw, scale: a.scale, offset: a.offset});
+        }
+
+        for w in &config.wires {
+            for p in w.iter().skip(1) {
+                c.wire(&w[0], p);
+            }
+        }
+
+        c
+    }
+
+    /// Add an SPI device which returns the data written
+    pub fn add_spi_loopback(&mut self, path: &str) {
+        self.spi.lock().unwrap().insert(path.to_owned(), Arc::new(Mutex::new(SpiModel::Loopback)));
+    }
+
+    /// Add a simulated SPI NOR flash device
+    pub fn add_spi_flash(&mut self, path: &str, flash: SpiFlash) {
+        self.spi.lock().unwrap().insert(path.to_owned(), Arc::new(Mutex::new(SpiModel::Flash(flash))));
+    }
+
+    /// Fetch the contents of a simulated SPI flash
+    pub fn spi_flash_data(&self, path: &str) -> Option<Vec<u8>> {
+        let spi = self.spi.lock().unwrap();
+        let d = spi.get(path)?.lock().unwrap();
+        match &*d {
+            SpiModel::Flash(f) => Some(f.data().to_vec()),
+            _ => None,
+        }
+    }
+
+    /// Add a register-map device at the provided address on an I2C bus
+    pub fn add_i2c_device(&mut self, bus: &str, addr: u8, regs: RegisterMap) {
+        let mut i2c = self.i2c.lock().unwrap();
+        let b = i2c.entry(bus.to_owned()).or_insert_with(|| Arc::new(Mutex::new(HashMap::new())) );
+        b.lock().unwrap().insert(addr, regs);
+    }
+
+    /// Fetch the registers of a simulated I2C device
+    pub fn i2c_registers(&self, bus: &str, addr: u8) -> Option<Vec<u8>> {
+        let i2c = self.i2c.lock().unwrap();
+        let b = i2c.get(bus)?.lock().unwrap();
+        b.get(&addr).map(|r| r.registers().to_vec() )
+    }
+
+    /// Wire two pins together, values set on either are visible on both
+    pub fn wire(&mut self, a: &str, b: &str) {
+        self.wiring.lock().unwrap().wire(a, b);
+    }
+
+    /// Drive a pin (and any pins wired to it) from outside the simulation
+    pub fn set_pin(&mut self, path: &str, value: bool) {
+        let notify = self.wiring.lock().unwrap().set(path, value);
+        notify.notify();
+    }
+
+    /// Fetch the value of a pin
+    pub fn pin_value(&self, path: &str) -> bool {
+        self.wiring.lock().unwrap().get(path)
+    }
+
+    /// Fetch the state of a simulated PWM channel
+    pub fn pwm_state(&self, path: &str) -> Option<PwmState> {
+        let pwm = self.pwm.lock().unwrap();
+        pwm.get(path).map(|s| s.lock().unwrap().clone() )
+    }
+
+    /// Set the reading returned by a simulated ADC channel, creating the ADC if required
+    pub fn set_adc_reading(&mut self, path: &str, channel: &str, reading: AdcReading) {
+        let mut adc = self.adc.lock().unwrap();
+        let a = adc.entry(path.to_owned()).or_insert_with(|| Arc::new(Mutex::new(HashMap::new())) );
+        a.lock().unwrap().insert(channel.to_owned(), reading);
+    }
+
+    /// Send a frame on a simulated CAN bus from outside the simulation, creating the bus if required
+    pub fn send_can_frame(&mut self, path: &str, frame: &CanFrame) {
+        self.can_bus(path).lock().unwrap().send(None, frame);
+    }
+
+    fn can_bus(&mut self, path: &str) -> Arc<Mutex<CanBus>> {
+        let mut can = self.can.lock().unwrap();
+        can.entry(path.to_owned()).or_insert_with(|| Arc::new(Mutex::new(CanBus::default())) ).clone()
+    }
+
+    fn open_spi(&mut self, path: &str) -> Result<Spi, Error> {
+        match self.spi.lock().unwrap().get(path) {
+            Some(m) => Ok(Spi::new(m.clone())),
+            None => Err(Error::UnknownDevice(path.to_owned())),
+        }
+    }
+
+    fn open_i2c(&mut self, path: &str) -> Result<I2c, Error> {
+        match self.i2c.lock().unwrap().get(path) {
+            Some(b) => Ok(I2c::new(b.clone())),
+            None => Err(Error::UnknownDevice(path.to_owned())),
+        }
+    }
+
+    fn open_pin(&mut self, path: &str, mode: PinMode) -> Result<Pin, Error> {
+        Ok(Pin::new(path, mode, self.wiring.clone()))
+    }
+
+    fn open_serial(&mut self, path: &str) -> Result<Serial, Error> {
+        // Serial ports are created on demand as loopbacks
+        let mut serial = self.serial.lock().unwrap();
+        Ok(serial.entry(path.to_owned()).or_default().clone())
+    }
+
+    fn open_adc(&mut self, path: &str) -> Result<Adc, Error> {
+        match self.adc.lock().unwrap().get(path) {
+            Some(a) => Ok(Adc::new(a.clone())),
+            None => Err(Error::UnknownDevice(path.to_owned())),
+        }
+    }
+
+    fn open_pwm(&mut self, path: &str) -> Result<Pwm, Error> {
+        // PWM channels are created on demand, retaining state between connections
+        let mut pwm = self.pwm.lock().unwrap();
+        Ok(Pwm::new(pwm.entry(path.to_owned()).or_insert_with(|| Arc::new(Mutex::new(PwmState::default())) ).clone()))
+    }
+
+    fn open_can(&mut self, path: &str) -> Result<Can, Error> {
+        // CAN buses are created on demand, with each connection joining the bus
+        Ok(Can::new(self.can_bus(path)))
+    }
+}
+
+impl Manager for Client {
+    type Spi = Spi;
+    type Pin = Pin;
+    type I2c = I2c;
+    type Serial = Serial;
+    type Pwm = Pwm;
+    type Adc = Adc;
+    type Can = Can;
+
+    /// Connect to a new Spi instance
+    fn spi(&mut self, path: &str, _baud: u32, _mode: SpiMode) -> Box<Future<Item=Spi, Error=Error> + Send> {
+        debug!("attempting connection to simulated SPI device: {}", path);
+        let d = match self.open_spi(path) {
+            Ok(d) => ok(d),
+            Err(e) => err(e),
+        };
+        Box::new(d)
+    }
+
+    /// Connect to a new Pin instance
+    fn pin(&mut self, path: &str, mode: PinMode) -> Box<Future<Item=Pin, Error=Error> + Send> {
+        debug!("attempting connection to simulated Pin: {}", path);
+        let d = match self.open_pin(path, mode) {
+            Ok(d) => ok(d),
+            Err(e) => err(e),
+        };
+        Box::new(d)
+    }
+
+    /// Connect to a new I2c instance
+    fn i2c(&mut self, path: &str) -> Box<Future<Item=I2c, Error=Error> + Send> {
+        debug!("attempting connection to simulated I2c: {}", path);
+        let d = match self.open_i2c(path) {
+            Ok(d) => ok(d),
+            Err(e) => err(e),
+        };
+        Box::new(d)
+    }
+
+    /// Connect to a new Serial instance
+    fn serial(&mut self, path: &str, _config: SerialConnect) -> Box<Future<Item=Serial, Error=Error> + Send> {
+        debug!("attempting connection to simulated Serial port: {}", path);
+        let d = match self.open_serial(path) {
+            Ok(d) => ok(d),
+            Err(e) => err(e),
+        };
+        Box::new(d)
+    }
+
+    /// Connect to a new Pwm instance
+    fn pwm(&mut self, path: &str) -> Box<Future<Item=Pwm, Error=Error> + Send> {
+        debug!("attempting connection to simulated PWM channel: {}", path);
+        let d = match self.open_pwm(path) {
+            Ok(d) => ok(d),
+            Err(e) => err(e),
+        };
+        Box::new(d)
+    }
+
+    /// Connect to a new Adc instance
+    fn adc(&mut self, path: &str) -> Box<Future<Item=Adc, Error=Error> + Send> {
+        debug!("attempting connection to simulated ADC: {}", path);
+        let d = match self.open_adc(path) {
+            Ok(d) => ok(d),
+            Err(e) => err(e),
+        };
+        Box::new(d)
+    }
+
+    /// Connect to a new Can instance
+    fn can(&mut self, path: &str) -> Box<Future<Item=Can, Error=Error> + Send> {
+        debug!("attempting connection to simulated CAN interface: {}", path);
+        let d = match self.open_can(path) {
+            Ok(d) => ok(d),
+            Err(e) => err(e),
+        };
+        Box::new(d)
+    }
+}
+
+impl Backend for Client {
+    fn spi(&mut self, path: &str, _baud: u32, _mode: SpiMode) -> Result<Box<SpiDevice>, Error> {
+        Ok(Box::new(self.open_spi(path)?))
+    }
+
+    fn i2c(&mut self, path: &str) -> Result<Box<I2cDevice>, Error> {
+        Ok(Box::new(self.open_i2c(path)?))
+    }
+
+    fn pin(&mut self, path: &str, mode: PinMode) -> Result<Box<PinDevice>, Error> {
+        Ok(Box::new(self.open_pin(path, mode)?))
+    }
+
+    fn serial(&mut self, path: &str, _config: &SerialConnect) -> Result<Box<SerialDevice>, Error> {
+        Ok(Box::new(self.open_serial(path)?))
+    }
+
+    fn pwm(&mut self, path: &str) -> Result<Box<PwmDevice>, Error> {
+        Ok(Box::new(self.open_pwm(path)?))
+    }
+
+    fn adc(&mut self, path: &str) -> Result<Box<AdcDevice>, Error> {
+        Ok(Box::new(self.open_adc(path)?))
+    }
+
+    fn can(&mut self, path: &str) -> Result<Box<CanDevice>, Error> {
+        Ok(Box::new(self.open_can(path)?))
+    }
+}
+
+/// Simulated device configuration, loaded from a TOML file
+///
+/// ```toml
+/// [[spi]]
+/// path = "/dev/spidev0.0"
+///
+/// [[spi]]
+/// path = "/dev/spidev0.1"
+/// flash = { size = 1048576, jedec_id = [0xef, 0x40, 0x14] }
+///
+/// [[i2c]]
+/// bus = "/dev/i2c-1"
+/// address = 0x48
+/// values = [0x00, 0x12]
+///
+/// [[adc]]
+/// path = "iio:device0"
+/// channel = "voltage0"
+/// raw = 2048
+/// scale = 0.805664062
+///
+/// wires = [["gpiochip0:1", "gpiochip0:2"]]
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub wires: Vec<Vec<String>>,
+    #[serde(default)]
+    pub spi: Vec<SpiConfig>,
+    #[serde(default)]
+    pub i2c: Vec<I2cConfig>,
+    #[serde(default)]
+    pub adc: Vec<AdcConfig>,
+}
+
+/// Simulated SPI device, a loopback device unless flash is configured
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpiConfig {
+    pub path: String,
+    #[serde(default)]
+    pub flash: Option<FlashConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlashConfig {
+    /// Flash size in bytes
+    pub size: usize,
+    /// JEDEC manufacturer and device id
+    pub jedec_id: [u8; 3],
+}
+
+/// Simulated I2C register-map device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct I2cConfig {
+    pub bus: String,
+    pub address: u8,
+    /// Number of registers
+    #[serde(default = "default_registers")]
+    pub registers: usize,
+    /// Initial register values, starting from register 0
+    #[serde(default)]
+    pub values: Vec<u8>,
+}
+
+fn default_registers() -> usize {
+    256
+}
+
+/// Simulated ADC channel reading
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdcConfig {
+    pub path: String,
+    pub channel: String,
+    pub raw: i64,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub offset: f64,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+impl Config {
+    /// Load a simulated device configuration file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let s = fs::read_to_string(path)?;
+
+        toml::from_str(&s).map_err(|e| Error::Config(format!("{}", e)) )
+    }
+}
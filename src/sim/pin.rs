@@ -0,0 +1,206 @@
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use embedded_hal::digital::v2 as digital;
+use rand::random;
+
+use crate::common::{PinMode, PinEvent, Edge};
+use crate::server::backend::PinDevice;
+use crate::error::Error;
+
+type Handler = Arc<Mutex<Box<FnMut(PinEvent) + Send>>>;
+
+/// Pending edge notifications, returned by `Wiring::set` so that handlers are called
+/// once the wiring lock is released (as handlers may themselves access the wiring)
+#[must_use]
+pub struct Notify(Vec<(Handler, PinEvent)>);
+
+impl Notify {
+    /// Call the subscribed handlers
+    pub fn notify(self) {
+        for (h, e) in self.0 {
+            let mut h = h.lock().unwrap();
+            (*h)(e);
+        }
+    }
+}
+
+/// Simulated pin wiring, pins wired together share a single net value
+#[derive(Default)]
+pub struct Wiring {
+    nets: HashMap<String, usize>,
+    values: HashMap<usize, bool>,
+    next: usize,
+    handlers: HashMap<u64, (String, Edge, Handler)>,
+}
+
+impl Wiring {
+    fn net(&mut self, pin: &str) -> usize {
+        if let Some(n) = self.nets.get(pin) {
+            return *n;
+        }
+
+        let n = self.next;
+        self.next += 1;
+        self.nets.insert(pin.to_owned(), n);
+
+        n
+    }
+
+    /// Wire two pins together, the combined net takes the value of the first pin
+    pub fn wire(&mut self, a: &str, b: &str) {
+        let (na, nb) = (self.net(a), self.net(b));
+        if na == nb {
+            return;
+        }
+
+        for n in self.nets.values_mut() {
+            if *n == nb {
+                *n = na;
+            }
+        }
+
+        self.values.remove(&nb);
+    }
+
+    /// Fetch the value of a pin
+    pub fn get(&mut self, pin: &str) -> bool {
+        let n = self.net(pin);
+        *self.values.get(&n).unwrap_or(&false)
+    }
+
+    /// Set the value of a pin, returning notifications for subscribers on the same net
+    /// of any edge, these should be delivered after releasing the wiring
+    pub fn set(&mut self, pin: &str, value: bool) -> Notify {
+        let n = self.net(pin);
+        let prev = self.values.insert(n, value).unwrap_or(false);
+        if prev == value {
+            return Notify(vec![]);
+        }
+
+        let edge = if value { Edge::Rising } else { Edge::Falling };
+        let event = PinEvent{ edge, timestamp: timestamp() };
+
+        let nets = &self.nets;
+        let handlers = self.handlers.values()
+            .filter(|(p, e, _h)| nets.get(p) == Some(&n) && (*e == Edge::Both || *e == edge) )
+            .map(|(_p, _e, h)| (h.clone(), event.clone()) )
+            .collect();
+
+        Notify(handlers)
+    }
+
+    fn subscribe(&mut self, id: u64, pin: &str, edge: Edge, handler: Box<FnMut(PinEvent) + Send>) {
+        self.net(pin);
+        self.handlers.insert(id, (pin.to_owned(), edge, Arc::new(Mutex::new(handler))));
+    }
+
+    fn unsubscribe(&mut self, id: u64) {
+        self.handlers.remove(&id);
+    }
+}
+
+/// Fetch the current time in nanoseconds since the UNIX epoch
+fn timestamp() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() * 1_000_000_000 + d.subsec_nanos() as u64,
+        Err(_) => 0,
+    }
+}
+
+/// Simulated GPIO pin
+pub struct Pin {
+    id: u64,
+    path: String,
+    mode: PinMode,
+    wiring: Arc<Mutex<Wiring>>,
+}
+
+impl Pin {
+    pub(crate) fn new(path: &str, mode: PinMode, wiring: Arc<Mutex<Wiring>>) -> Self {
+        Self{ id: random(), path: path.to_owned(), mode, wiring }
+    }
+
+    pub fn subscribe<F>(&mut self, edge: Edge, handler: F) -> Result<(), Error>
+    where
+        F: FnMut(PinEvent) + Send + 'static,
+    {
+        if let PinMode::Output(_) = self.mode {
+            return Err(Error::NotPermitted(format!("{} is not an input", self.path)));
+        }
+
+        self.wiring.lock().unwrap().subscribe(self.id, &self.path, edge, Box::new(handler));
+        Ok(())
+    }
+
+    pub fn unsubscribe(&mut self) {
+        self.wiring.lock().unwrap().unsubscribe(self.id);
+    }
+
+    fn get(&self) -> Result<bool, Error> {
+        let v = self.wiring.lock().unwrap().get(&self.path);
+        Ok(v != self.mode.options().active_low)
+    }
+
+    fn set(&mut self, value: bool) -> Result<(), Error> {
+        if let PinMode::Input(_) = self.mode {
+            return Err(Error::NotPermitted(format!("{} is not an output", self.path)));
+        }
+
+        let value = value != self.mode.options().active_low;
+        let notify = self.wiring.lock().unwrap().set(&self.path, value);
+        notify.notify();
+
+        Ok(())
+    }
+}
+
+impl Drop for Pin {
+    fn drop(&mut self) {
+        self.unsubscribe();
+    }
+}
+
+impl digital::InputPin for Pin {
+    type Error = Error;
+
+    fn is_high(&self) -> Result<bool, Error> {
+        self.get()
+    }
+
+    fn is_low(&self) -> Result<bool, Error> {
+        self.get().map(|v| !v )
+    }
+}
+
+impl digital::OutputPin for Pin {
+    type Error = Error;
+
+    fn set_high(&mut self) -> Result<(), Error> {
+        self.set(true)
+    }
+
+    fn set_low(&mut self) -> Result<(), Error> {
+        self.set(false)
+    }
+}
+
+impl PinDevice for Pin {
+    fn get(&self) -> Result<bool, Error> {
+        Pin::get(self)
+    }
+
+    fn set(&mut self, value: bool) -> Result<(), Error> {
+        Pin::set(self, value)
+    }
+
+    fn subscribe(&mut self, edge: Edge, handler: Box<FnMut(PinEvent) + Send>) -> Result<(), Error> {
+        Pin::subscribe(self, edge, handler)
+    }
+
+    fn unsubscribe(&mut self) {
+        Pin::unsubscribe(self)
+    }
+}
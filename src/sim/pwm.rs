@@ -0,0 +1,144 @@
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use embedded_hal::{Pwm as PwmTrait, PwmPin};
+
+use crate::common::{Polarity, PwmState};
+use crate::server::backend::PwmDevice;
+use crate::error::Error;
+
+/// Simulated PWM channel, state is shared with the simulated client for inspection
+#[derive(Clone)]
+pub struct Pwm {
+    state: Arc<Mutex<PwmState>>,
+}
+
+impl Pwm {
+    pub(crate) fn new(state: Arc<Mutex<PwmState>>) -> Self {
+        Self{state}
+    }
+
+    /// Set the period in nanoseconds, this may not be less than the duty cycle
+    pub fn set_period(&mut self, period_ns: u64) -> Result<(), Error> {
+        let mut s = self.state.lock().unwrap();
+        if period_ns < s.duty_ns {
+            return Err(Error::NotPermitted(format!("period {} ns less than duty cycle {} ns", period_ns, s.duty_ns)));
+        }
+        s.period_ns = period_ns;
+        Ok(())
+    }
+
+    /// Set the duty cycle in nanoseconds, this may not exceed the period
+    pub fn set_duty(&mut self, duty_ns: u64) -> Result<(), Error> {
+        let mut s = self.state.lock().unwrap();
+        if duty_ns > s.period_ns {
+            return Err(Error::NotPermitted(format!("duty cycle {} ns exceeds period {} ns", duty_ns, s.period_ns)));
+        }
+        s.duty_ns = duty_ns;
+        Ok(())
+    }
+
+    /// Set the polarity, as with sysfs this may only be changed while disabled
+    pub fn set_polarity(&mut self, polarity: Polarity) -> Result<(), Error> {
+        let mut s = self.state.lock().unwrap();
+        if s.enabled {
+            return Err(Error::NotPermitted("polarity may not be changed while enabled".to_owned()));
+        }
+        s.polarity = polarity;
+        Ok(())
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        self.state.lock().unwrap().enabled = enabled;
+        Ok(())
+    }
+
+    pub fn state(&self) -> Result<PwmState, Error> {
+        Ok(self.state.lock().unwrap().clone())
+    }
+}
+
+impl PwmPin for Pwm {
+    type Duty = u64;
+
+    fn disable(&mut self) {
+        self.set_enabled(false).unwrap()
+    }
+
+    fn enable(&mut self) {
+        self.set_enabled(true).unwrap()
+    }
+
+    fn get_duty(&self) -> u64 {
+        self.state.lock().unwrap().duty_ns
+    }
+
+    fn get_max_duty(&self) -> u64 {
+        self.state.lock().unwrap().period_ns
+    }
+
+    fn set_duty(&mut self, duty: u64) {
+        Pwm::set_duty(self, duty).unwrap()
+    }
+}
+
+impl PwmTrait for Pwm {
+    type Channel = ();
+    type Time = Duration;
+    type Duty = u64;
+
+    fn disable(&mut self, _channel: ()) {
+        PwmPin::disable(self)
+    }
+
+    fn enable(&mut self, _channel: ()) {
+        PwmPin::enable(self)
+    }
+
+    fn get_period(&self) -> Duration {
+        Duration::from_nanos(self.state.lock().unwrap().period_ns)
+    }
+
+    fn get_duty(&self, _channel: ()) -> u64 {
+        PwmPin::get_duty(self)
+    }
+
+    fn get_max_duty(&self) -> u64 {
+        PwmPin::get_max_duty(self)
+    }
+
+    fn set_duty(&mut self, _channel: (), duty: u64) {
+        PwmPin::set_duty(self, duty)
+    }
+
+    fn set_period<P>(&mut self, period: P)
+    where
+        P: Into<Duration>,
+    {
+        let p = period.into();
+        Pwm::set_period(self, p.as_secs() * 1_000_000_000 + p.subsec_nanos() as u64).unwrap()
+    }
+}
+
+impl PwmDevice for Pwm {
+    fn set_period(&mut self, period_ns: u64) -> Result<(), Error> {
+        Pwm::set_period(self, period_ns)
+    }
+
+    fn set_duty(&mut self, duty_ns: u64) -> Result<(), Error> {
+        Pwm::set_duty(self, duty_ns)
+    }
+
+    fn set_polarity(&mut self, polarity: Polarity) -> Result<(), Error> {
+        Pwm::set_polarity(self, polarity)
+    }
+
+    fn set_enabled(&mut self, enabled: bool) -> Result<(), Error> {
+        Pwm::set_enabled(self, enabled)
+    }
+
+    fn state(&self) -> Result<PwmState, Error> {
+        Pwm::state(self)
+    }
+}
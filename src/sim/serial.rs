@@ -0,0 +1,76 @@
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use embedded_hal::serial;
+
+use crate::server::backend::SerialDevice;
+use crate::error::Error;
+
+/// Simulated loopback serial port, data written is available to be read back
+#[derive(Clone, Default)]
+pub struct Serial {
+    buff: Arc<Mutex<VecDeque<u8>>>,
+}
+
+impl Serial {
+    /// Read up to `buff.len()` bytes of available data, this never blocks
+    pub fn read_timeout(&mut self, buff: &mut [u8], _timeout: Duration) -> Result<usize, Error> {
+        let mut rx = self.buff.lock().unwrap();
+        let n = buff.len().min(rx.len());
+
+        for (b, v) in buff.iter_mut().zip(rx.drain(..n)) {
+            *b = v;
+        }
+
+        Ok(n)
+    }
+
+    pub fn write_all(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.buff.lock().unwrap().extend(data);
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl serial::Read<u8> for Serial {
+    type Error = Error;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        match self.buff.lock().unwrap().pop_front() {
+            Some(b) => Ok(b),
+            None => Err(nb::Error::WouldBlock),
+        }
+    }
+}
+
+impl serial::Write<u8> for Serial {
+    type Error = Error;
+
+    fn write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        self.buff.lock().unwrap().push_back(word);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl SerialDevice for Serial {
+    fn read_timeout(&mut self, buff: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        Serial::read_timeout(self, buff, timeout)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Error> {
+        Serial::write_all(self, data)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Serial::flush(self)
+    }
+}
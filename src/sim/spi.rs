@@ -0,0 +1,188 @@
+
+use std::sync::{Arc, Mutex};
+
+use embedded_hal::blocking::spi;
+
+use crate::server::backend::SpiDevice;
+use crate::error::Error;
+
+// SPI NOR flash commands
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_READ: u8 = 0x03;
+const CMD_WRITE_DISABLE: u8 = 0x04;
+const CMD_READ_STATUS: u8 = 0x05;
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_FAST_READ: u8 = 0x0b;
+const CMD_SECTOR_ERASE: u8 = 0x20;
+const CMD_JEDEC_ID: u8 = 0x9f;
+const CMD_CHIP_ERASE: u8 = 0xc7;
+const CMD_CHIP_ERASE_ALT: u8 = 0x60;
+const CMD_BLOCK_ERASE: u8 = 0xd8;
+
+const STATUS_WEL: u8 = 1 << 1;
+
+const PAGE_SIZE: usize = 256;
+const SECTOR_SIZE: usize = 4 * 1024;
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Simulated SPI device model
+#[derive(Debug, Clone)]
+pub enum SpiModel {
+    /// Returns the data written
+    Loopback,
+    /// SPI NOR flash
+    Flash(SpiFlash),
+}
+
+impl SpiModel {
+    fn transfer(&mut self, data: &mut [u8]) {
+        match self {
+            SpiModel::Loopback => (),
+            SpiModel::Flash(f) => f.transfer(data),
+        }
+    }
+}
+
+/// Simulated SPI NOR flash, supporting JEDEC ID, status, read, page program and erase commands
+#[derive(Debug, Clone)]
+pub struct SpiFlash {
+    data: Vec<u8>,
+    jedec_id: [u8; 3],
+    write_enable: bool,
+}
+
+impl SpiFlash {
+    /// Create a new (erased) flash with the provided size and JEDEC ID
+    pub fn new(size: usize, jedec_id: [u8; 3]) -> Self {
+        Self{ data: vec![0xff; size], jedec_id, write_enable: false }
+    }
+
+    /// Fetch the flash contents
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Execute a single transaction (one chip-select assertion)
+    fn transfer(&mut self, data: &mut [u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        let cmd = data[0];
+        let out = data[1..].to_vec();
+
+        // Lines not driven by the device read high
+        data.iter_mut().for_each(|b| *b = 0xff );
+
+        match cmd {
+            CMD_JEDEC_ID => {
+                for (i, b) in data[1..].iter_mut().take(3).enumerate() {
+                    *b = self.jedec_id[i];
+                }
+            },
+            CMD_READ_STATUS => {
+                let status = if self.write_enable { STATUS_WEL } else { 0 };
+                data[1..].iter_mut().for_each(|b| *b = status );
+            },
+            CMD_WRITE_ENABLE => self.write_enable = true,
+            CMD_WRITE_DISABLE => self.write_enable = false,
+            CMD_READ | CMD_FAST_READ => {
+                let skip = if cmd == CMD_FAST_READ { 4 } else { 3 };
+                let addr = match address(&out) {
+                    Some(a) => a,
+                    None => return,
+                };
+
+                for (i, b) in data.iter_mut().skip(1 + skip).enumerate() {
+                    *b = self.data[(addr + i) % self.data.len()];
+                }
+            },
+            CMD_PAGE_PROGRAM => {
+                let addr = match address(&out) {
+                    Some(a) if self.write_enable => a,
+                    _ => return,
+                };
+
+                // Writes wrap within the addressed page
+                let page = addr - addr % PAGE_SIZE;
+                for (i, b) in out[3..].iter().enumerate() {
+                    let a = (page + (addr + i) % PAGE_SIZE) % self.data.len();
+                    self.data[a] &= b;
+                }
+
+                self.write_enable = false;
+            },
+            CMD_SECTOR_ERASE | CMD_BLOCK_ERASE => {
+                let addr = match address(&out) {
+                    Some(a) if self.write_enable => a,
+                    _ => return,
+                };
+
+                let size = if cmd == CMD_SECTOR_ERASE { SECTOR_SIZE } else { BLOCK_SIZE };
+                let start = (addr - addr % size) % self.data.len();
+                let end = (start + size).min(self.data.len());
+                self.data[start..end].iter_mut().for_each(|b| *b = 0xff );
+
+                self.write_enable = false;
+            },
+            CMD_CHIP_ERASE | CMD_CHIP_ERASE_ALT if self.write_enable => {
+                self.data.iter_mut().for_each(|b| *b = 0xff );
+                self.write_enable = false;
+            },
+            _ => {
+                debug!("unhandled simulated flash command: 0x{:02x}", cmd);
+            }
+        }
+    }
+}
+
+/// Parse a 24-bit big-endian address
+fn address(data: &[u8]) -> Option<usize> {
+    if data.len() < 3 {
+        return None;
+    }
+
+    Some((data[0] as usize) << 16 | (data[1] as usize) << 8 | data[2] as usize)
+}
+
+/// Simulated SPI device
+#[derive(Clone)]
+pub struct Spi {
+    model: Arc<Mutex<SpiModel>>,
+}
+
+impl Spi {
+    pub(crate) fn new(model: Arc<Mutex<SpiModel>>) -> Self {
+        Self{model}
+    }
+}
+
+impl spi::Transfer<u8> for Spi {
+    type Error = Error;
+
+    fn transfer<'w>(&mut self, data: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        self.model.lock().unwrap().transfer(data);
+        Ok(data)
+    }
+}
+
+impl spi::Write<u8> for Spi {
+    type Error = Error;
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        let mut d = data.to_vec();
+        self.model.lock().unwrap().transfer(&mut d);
+        Ok(())
+    }
+}
+
+impl SpiDevice for Spi {
+    fn transfer(&mut self, data: &mut [u8]) -> Result<(), Error> {
+        spi::Transfer::transfer(self, data)?;
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        spi::Write::write(self, data)
+    }
+}
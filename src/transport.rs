@@ -0,0 +1,151 @@
+
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use futures::future::{self, Either};
+use tokio::prelude::*;
+use tokio::net::TcpStream;
+
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore, ClientConfig, ServerConfig, NoClientAuth, AllowAnyAuthenticatedClient};
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::webpki::DNSNameRef;
+
+use crate::error::Error;
+
+/// Stream over which remote-hal messages are exchanged (plain TCP or TLS)
+pub trait Transport: AsyncRead + AsyncWrite + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Send> Transport for T {}
+
+/// Server TLS configuration
+#[derive(Debug, Clone)]
+pub struct ServerTls {
+    /// Server certificate chain (PEM)
+    pub cert: String,
+    /// Server private key (PEM, PKCS8 or RSA)
+    pub key: String,
+    /// CA certificates used to authenticate clients (PEM), client certificates are required if set
+    pub client_ca: Option<String>,
+}
+
+impl ServerTls {
+    /// Build a TLS acceptor from the configuration
+    pub fn acceptor(&self) -> Result<TlsAcceptor, Error> {
+        let verifier = match &self.client_ca {
+            Some(ca) => AllowAnyAuthenticatedClient::new(load_roots(ca)?),
+            None => NoClientAuth::new(),
+        };
+
+        let mut config = ServerConfig::new(verifier);
+        config.set_single_cert(load_certs(&self.cert)?, load_key(&self.key)?)
+            .map_err(|e| Error::Tls("invalid server certificate".to_owned(), Some(Box::new(e))) )?;
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// Client TLS configuration
+#[derive(Debug, Clone)]
+pub struct ClientTls {
+    /// CA certificates used to authenticate the server (PEM)
+    pub ca: String,
+    /// Client certificate chain (PEM), required where the server authenticates clients
+    pub cert: Option<String>,
+    /// Client private key (PEM, PKCS8 or RSA)
+    pub key: Option<String>,
+    /// Server name to verify against the server certificate
+    pub domain: String,
+}
+
+impl ClientTls {
+    /// Build a TLS connector from the configuration
+    pub fn connector(&self) -> Result<TlsConnector, Error> {
+        let mut config = ClientConfig::new();
+        config.root_store = load_roots(&self.ca)?;
+
+        match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => config.set_single_client_cert(load_certs(cert)?, load_key(key)?),
+            (None, None) => (),
+            _ => return Err(Error::Tls("client certificate and key must be provided together".to_owned(), None)),
+        }
+
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+}
+
+/// Connect to a server, optionally using TLS
+pub fn connect(addr: &SocketAddr, tls: Option<ClientTls>) -> Box<Future<Item=Box<Transport>, Error=Error> + Send> {
+    let connect = TcpStream::connect(addr).map_err(Error::from);
+
+    let tls = match tls {
+        Some(t) => t,
+        None => return Box::new(connect.map(|s| Box::new(s) as Box<Transport> )),
+    };
+
+    let connector = match tls.connector() {
+        Ok(c) => c,
+        Err(e) => return Box::new(future::err(e)),
+    };
+
+    Box::new(connect.and_then(move |s| {
+        let domain = match DNSNameRef::try_from_ascii_str(&tls.domain) {
+            Ok(d) => d,
+            Err(_) => return Either::A(future::err(Error::Tls(format!("invalid server name: {}", tls.domain), None))),
+        };
+
+        debug!("starting TLS handshake with: {}", tls.domain);
+
+        Either::B(connector.connect(domain, s)
+            .map(|s| Box::new(s) as Box<Transport> )
+            .map_err(Error::from))
+    }))
+}
+
+/// Accept an incoming connection, optionally using TLS
+pub fn accept(socket: TcpStream, tls: Option<TlsAcceptor>) -> Box<Future<Item=Box<Transport>, Error=Error> + Send> {
+    match tls {
+        Some(acceptor) => Box::new(acceptor.accept(socket)
+            .map(|s| Box::new(s) as Box<Transport> )
+            .map_err(Error::from)),
+        None => Box::new(future::ok(Box::new(socket) as Box<Transport>)),
+    }
+}
+
+fn load_certs(path: &str) -> Result<Vec<Certificate>, Error> {
+    let mut r = BufReader::new(File::open(path)?);
+
+    match pemfile::certs(&mut r) {
+        Ok(ref c) if c.is_empty() => Err(Error::Tls(format!("no certificates found in: {}", path), None)),
+        Ok(c) => Ok(c),
+        Err(_) => Err(Error::Tls(format!("invalid certificate file: {}", path), None)),
+    }
+}
+
+fn load_key(path: &str) -> Result<PrivateKey, Error> {
+    // Try PKCS8 keys first, falling back to RSA keys
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(path)?))
+        .map_err(|_e| Error::Tls(format!("invalid key file: {}", path), None) )?;
+
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut BufReader::new(File::open(path)?))
+            .map_err(|_e| Error::Tls(format!("invalid key file: {}", path), None) )?;
+    }
+
+    match keys.into_iter().next() {
+        Some(k) => Ok(k),
+        None => Err(Error::Tls(format!("no private keys found in: {}", path), None)),
+    }
+}
+
+fn load_roots(path: &str) -> Result<RootCertStore, Error> {
+    let mut roots = RootCertStore::empty();
+
+    for c in load_certs(path)? {
+        roots.add(&c).map_err(|e| Error::Tls(format!("invalid CA certificate in {}: {:?}", path, e), None) )?;
+    }
+
+    Ok(roots)
+}
@@ -0,0 +1,142 @@
+//! Client handle lifecycle tests, using a server with the simulated backend
+
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+
+extern crate tokio;
+use tokio::prelude::*;
+use tokio::runtime::{Runtime, current_thread};
+use tokio::timer::Delay;
+
+extern crate remote_hal;
+use remote_hal::common::{PinMode, PinOptions, SpiMode, RequestKind};
+use remote_hal::error::Error;
+use remote_hal::manager::Manager;
+use remote_hal::remote::{Client, ClientOptions, ConnectionState, ReconnectPolicy};
+use remote_hal::server::{Server, ServerOptions};
+use remote_hal::sim::{self, RegisterMap};
+
+use remote_hal::asynch::digital::OutputPin;
+
+fn server(rt: &mut Runtime, sim: &sim::Client) -> Server {
+    let backend = sim.clone();
+    rt.block_on(future::lazy(move || {
+        Server::with_backend("127.0.0.1:0".parse().unwrap(), ServerOptions::default(), Box::new(backend))
+    })).unwrap()
+}
+
+/// Start a server at the provided address, retrying while the previous listener is released
+fn restart(rt: &mut Runtime, sim: &sim::Client, addr: std::net::SocketAddr) -> Server {
+    for _i in 0..20 {
+        let backend = sim.clone();
+        let s = rt.block_on(future::lazy(move || {
+            Server::with_backend(addr, ServerOptions::default(), Box::new(backend))
+        }));
+        match s {
+            Ok(s) => return s,
+            Err(_e) => std::thread::sleep(Duration::from_millis(50)),
+        }
+    }
+    panic!("error restarting server at {}", addr);
+}
+
+fn output() -> PinMode {
+    PinMode::Output(PinOptions::default())
+}
+
+#[test]
+fn drop_on_current_thread() {
+    let mut server_rt = Runtime::new().unwrap();
+    let server = server(&mut server_rt, &sim::Client::default());
+
+    let mut rt = current_thread::Runtime::new().unwrap();
+    let mut client = rt.block_on(Client::new(server.addr(), ClientOptions::default())).unwrap();
+    let pin = rt.block_on(client.pin("gpio0", output())).unwrap();
+
+    // Dropping a handle on the runtime thread must not block on the disconnect
+    rt.block_on(future::lazy(move || {
+        drop(pin);
+        Ok::<_, ()>(())
+    })).unwrap();
+
+    // The queued disconnect is sent once the runtime runs, releasing the device
+    rt.block_on(Delay::new(Instant::now() + Duration::from_millis(100))).unwrap();
+    rt.block_on(client.pin("gpio0", output())).expect("device not released");
+
+    client.close();
+}
+
+#[test]
+fn release_on_close() {
+    let mut server_rt = Runtime::new().unwrap();
+    let server = server(&mut server_rt, &sim::Client::default());
+
+    let mut rt = Runtime::new().unwrap();
+    let mut a = rt.block_on(Client::new(server.addr(), ClientOptions::default())).unwrap();
+    let mut b = rt.block_on(Client::new(server.addr(), ClientOptions::default())).unwrap();
+
+    let pin = rt.block_on(a.pin("gpio0", output())).unwrap();
+
+    // Devices bound by another client can not be disconnected
+    match rt.block_on(b.request("gpio0", RequestKind::PinDisconnect)) {
+        Err(Error::NotPermitted(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    match rt.block_on(b.pin("gpio0", output())) {
+        Err(Error::DeviceAlreadyBound) => (),
+        r => panic!("unexpected result: {:?}", r.map(|_| () )),
+    }
+
+    // Closing the connection releases devices, without waiting for handles to be dropped
+    a.close();
+    rt.block_on(Delay::new(Instant::now() + Duration::from_millis(100))).unwrap();
+    rt.block_on(b.pin("gpio0", output())).expect("device not released");
+
+    drop(pin);
+    b.close();
+}
+
+#[test]
+fn server_restart() {
+    let mut before = sim::Client::default();
+    before.add_i2c_device("i2c0", 0x50, RegisterMap::new(16));
+    before.add_spi_loopback("spi0");
+
+    let mut server_rt = Runtime::new().unwrap();
+    let addr = server(&mut server_rt, &before).addr();
+
+    let mut rt = Runtime::new().unwrap();
+    let reconnect = ReconnectPolicy{max_attempts: None, backoff: Duration::from_millis(50), max_backoff: Duration::from_millis(200)};
+    let options = ClientOptions{reconnect: Some(reconnect), ..ClientOptions::default()};
+    let mut client = rt.block_on(Client::new(addr, options)).unwrap();
+
+    let (states_tx, states_rx) = channel();
+    client.on_state_change(move |s| { let _ = states_tx.send(s); });
+
+    let mut pin = rt.block_on(client.pin("gpio0", output())).unwrap();
+    let mut i2c = rt.block_on(client.i2c("i2c0")).unwrap();
+    let _spi = rt.block_on(client.spi("spi0", 1_000_000, SpiMode::Mode0)).unwrap();
+
+    // Stop the server, closing the client connection
+    server_rt.shutdown_now().wait().unwrap();
+    assert_eq!(states_rx.recv_timeout(Duration::from_secs(2)), Ok(ConnectionState::Disconnected));
+
+    // Restart with a backend missing the SPI device
+    let mut after = sim::Client::default();
+    after.add_i2c_device("i2c0", 0x50, RegisterMap::new(16));
+
+    let mut server_rt = Runtime::new().unwrap();
+    let _server = restart(&mut server_rt, &after, addr);
+
+    let state = states_rx.iter().find(|s| !matches!(s, ConnectionState::Reconnecting{..}));
+    assert_eq!(state, Some(ConnectionState::Degraded{failed: vec!["spi0".to_owned()]}));
+
+    // Re-bound handles continue to work
+    rt.block_on(pin.set_high()).unwrap();
+    assert!(after.pin_value("gpio0"));
+
+    rt.block_on(i2c.smbus_write_byte(0x50, 0x02, 0xab)).unwrap();
+    assert_eq!(after.i2c_registers("i2c0", 0x50).unwrap()[0x02], 0xab);
+
+    client.close();
+}
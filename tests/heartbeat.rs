@@ -0,0 +1,129 @@
+//! Heartbeat tests, using raw connections in place of the client or server
+
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+
+extern crate tokio;
+use tokio::prelude::*;
+use tokio::codec::Framed;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+use tokio::timer::Delay;
+
+extern crate remote_hal;
+use remote_hal::common::*;
+use remote_hal::codec::{Codec, WireCodec};
+use remote_hal::error::Error;
+use remote_hal::manager::Manager;
+use remote_hal::remote::{Client, ClientOptions, ConnectionState};
+use remote_hal::server::{Server, ServerOptions};
+use remote_hal::sim;
+
+fn heartbeat() -> Heartbeat {
+    Heartbeat{interval: Duration::from_millis(50), misses: 3}
+}
+
+#[test]
+fn server_pings() {
+    let mut rt = Runtime::new().unwrap();
+
+    let options = ServerOptions{heartbeat: Some(heartbeat()), ..ServerOptions::default()};
+    let server = rt.block_on(future::lazy(move || {
+        Server::with_backend("127.0.0.1:0".parse().unwrap(), options, Box::new(sim::Client::default()))
+    })).unwrap();
+
+    // Send a hello, then wait for a pushed ping without sending anything further
+    let stream = rt.block_on(TcpStream::connect(&server.addr())).unwrap();
+    let framed = Framed::new(stream, WireCodec::<Request, Response, Error>::client(Codec::Json));
+    let hello = Request::new("".to_owned(), RequestKind::Hello(Hello::new()));
+
+    let pinged = framed.send(hello).and_then(|f| {
+        f.filter(|resp| resp.id == 0 ).into_future().map_err(|(e, _)| e )
+    }).timeout(Duration::from_secs(1)).map_err(Error::from);
+
+    match rt.block_on(pinged).expect("no ping received from server") {
+        (Some(Response{kind: ResponseKind::Ping, ..}), _) => (),
+        (r, _) => panic!("unexpected response: {:?}", r),
+    }
+}
+
+#[test]
+fn silent_client() {
+    let mut rt = Runtime::new().unwrap();
+
+    let options = ServerOptions{heartbeat: Some(heartbeat()), ..ServerOptions::default()};
+    let server = rt.block_on(future::lazy(move || {
+        Server::with_backend("127.0.0.1:0".parse().unwrap(), options, Box::new(sim::Client::default()))
+    })).unwrap();
+
+    // Raw connection that binds a pin, then never answers the server pings
+    let stream = rt.block_on(TcpStream::connect(&server.addr())).unwrap();
+    let framed = Framed::new(stream, WireCodec::<Request, Response, Error>::client(Codec::Json));
+    let hello = Request::new("".to_owned(), RequestKind::Hello(Hello::new()));
+    let connect = Request::new("gpio0".to_owned(), RequestKind::PinConnect(PinMode::Output(PinOptions::default())));
+    let connect_id = connect.id;
+
+    let bound = framed.send(hello).and_then(|f| f.send(connect) ).and_then(move |f| {
+        f.filter(move |resp| resp.id == connect_id ).into_future().map_err(|(e, _)| e )
+    }).timeout(Duration::from_secs(1)).map_err(Error::from);
+
+    let _silent = match rt.block_on(bound).expect("no connect response from server") {
+        (Some(Response{kind: ResponseKind::Ok, ..}), f) => f,
+        (r, _) => panic!("unexpected response: {:?}", r),
+    };
+
+    let mut client = rt.block_on(Client::new(server.addr(), ClientOptions::default())).unwrap();
+    match rt.block_on(client.pin("gpio0", PinMode::Output(PinOptions::default()))) {
+        Err(Error::DeviceAlreadyBound) => (),
+        r => panic!("unexpected result: {:?}", r.map(|_| () )),
+    }
+
+    // Once the heartbeat period has passed the silent connection is closed and the
+    // pin released, while the connection itself is still held open
+    rt.block_on(Delay::new(Instant::now() + heartbeat().period() * 2)).unwrap();
+    rt.block_on(client.pin("gpio0", PinMode::Output(PinOptions::default()))).expect("device not released");
+
+    client.close();
+}
+
+#[test]
+fn silent_server() {
+    let mut rt = Runtime::new().unwrap();
+
+    // Fake server that answers the hello, then stops responding without closing the connection
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let capabilities = Capabilities{
+        protocol_version: PROTOCOL_VERSION,
+        server_version: "fake".to_owned(),
+        devices: vec![],
+        codecs: vec![Codec::Json],
+        max_transfer: 1024,
+        heartbeat_ms: Some(50),
+        max_delay_us: None,
+    };
+
+    let fake = listener.incoming().into_future().map_err(|_e| () ).and_then(move |(socket, _)| {
+        let (tx, rx) = Framed::new(socket.unwrap(), WireCodec::<Response, Request, Error>::server(&[Codec::Json])).split();
+
+        rx.into_future().map_err(|(e, _)| e ).and_then(move |(hello, rx)| {
+            let id = hello.unwrap().id;
+            tx.send(Response{id, kind: ResponseKind::Capabilities(capabilities)}).map(|tx| (tx, rx) )
+        }).and_then(|(tx, rx)| {
+            // Drain requests without responding, holding the connection open
+            rx.for_each(|_req| Ok(()) ).map(move |_| drop(tx) )
+        }).map_err(|_e| () )
+    });
+    rt.spawn(fake);
+
+    let (states_tx, states_rx) = channel();
+    let mut client = rt.block_on(Client::new(addr, ClientOptions::default())).unwrap();
+    client.on_state_change(move |s| { let _ = states_tx.send(s); });
+
+    // Heartbeats are enabled by the server capabilities, so the client detects the
+    // silent server and closes the connection
+    assert_eq!(states_rx.recv_timeout(Duration::from_secs(2)), Ok(ConnectionState::Disconnected));
+
+    client.close();
+}
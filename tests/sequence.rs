@@ -0,0 +1,57 @@
+//! Server sequence tests, using a server with the simulated backend
+
+extern crate tokio;
+use tokio::prelude::*;
+use tokio::runtime::Runtime;
+
+extern crate remote_hal;
+use remote_hal::common::*;
+use remote_hal::error::Error;
+use remote_hal::manager::Manager;
+use remote_hal::remote::{Client, ClientOptions};
+use remote_hal::server::{Server, ServerOptions};
+use remote_hal::sim;
+
+fn output() -> PinMode {
+    PinMode::Output(PinOptions::default())
+}
+
+#[test]
+fn sequence_steps() {
+    let mut rt = Runtime::new().unwrap();
+    let sim = sim::Client::default();
+
+    let backend = Box::new(sim.clone());
+    let server = rt.block_on(future::lazy(move || {
+        Server::with_backend("127.0.0.1:0".parse().unwrap(), ServerOptions::default(), backend)
+    })).unwrap();
+
+    let mut client = rt.block_on(Client::new(server.addr(), ClientOptions::default())).unwrap();
+    let _pin = rt.block_on(client.pin("gpio0", output())).unwrap();
+
+    let set = |v| RequestKind::PinSet(Value{value: v});
+    let resps = rt.block_on(client.sequence(Sequence::new().request("gpio0", set(true)).delay_us(10).request("gpio0", RequestKind::PinGet))).unwrap();
+    assert_eq!(resps.len(), 3);
+    assert!(sim.pin_value("gpio0"));
+
+    // Sequences containing nested sequences, hellos, connections or subscriptions are
+    // rejected without executing any steps
+    let invalid = vec![
+        ("", RequestKind::Sequence(Sequence::new().request("gpio0", set(true)))),
+        ("", RequestKind::Hello(Hello::new())),
+        ("gpio1", RequestKind::PinConnect(output())),
+        ("gpio0", RequestKind::PinDisconnect),
+        ("gpio0", RequestKind::PinSubscribe(Edge::Both)),
+    ];
+
+    for (device, kind) in invalid {
+        let q = Sequence::new().request("gpio0", set(false)).request(device, kind);
+        match rt.block_on(client.sequence(q)) {
+            Err(Error::InvalidArgument(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(sim.pin_value("gpio0"));
+    }
+
+    client.close();
+}
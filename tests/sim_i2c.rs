@@ -0,0 +1,126 @@
+//! Simulated I2C scan and SMBus tests, run via a server and remote client
+
+extern crate tokio;
+use tokio::prelude::*;
+use tokio::runtime::Runtime;
+
+extern crate remote_hal;
+use remote_hal::common::{I2cScan, ProbeMethod};
+use remote_hal::manager::Manager;
+use remote_hal::remote::{Client, ClientOptions};
+use remote_hal::remote::i2c::I2c;
+use remote_hal::server::{Server, ServerOptions};
+use remote_hal::sim::{self, RegisterMap};
+
+const BUS: &str = "/dev/i2c-1";
+const ADDR: u8 = 0x50;
+
+/// Start a server exposing the simulated client and connect to the test bus
+fn connect(rt: &mut Runtime, sim: &sim::Client) -> I2c {
+    let backend = Box::new(sim.clone());
+    let server = rt.block_on(future::lazy(move || {
+        Server::with_backend("127.0.0.1:0".parse().unwrap(), ServerOptions::default(), backend)
+    })).unwrap();
+
+    rt.block_on(Client::new(server.addr(), ClientOptions::default()).and_then(|mut c| c.i2c(BUS) )).unwrap()
+}
+
+/// SMBus PEC (CRC-8, polynomial 0x07) computed independently of the server
+fn pec(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, b| {
+        crc ^= b;
+        for _i in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+#[test]
+fn scan_range() {
+    let mut rt = Runtime::new().unwrap();
+    let mut sim = sim::Client::default();
+    for addr in &[0x08, 0x20, ADDR, 0x77] {
+        sim.add_i2c_device(BUS, *addr, RegisterMap::new(16));
+    }
+
+    let mut i2c = connect(&mut rt, &sim);
+
+    let found = rt.block_on(i2c.scan(I2cScan::default())).unwrap();
+    assert_eq!(found, vec![0x08, 0x20, ADDR, 0x77]);
+
+    // Only addresses within the range are probed, using each method
+    for method in &[ProbeMethod::Auto, ProbeMethod::Quick, ProbeMethod::Read] {
+        let found = rt.block_on(i2c.scan(I2cScan{method: *method, first: 0x10, last: 0x50})).unwrap();
+        assert_eq!(found, vec![0x20, ADDR]);
+    }
+
+    // Invalid ranges are rejected
+    assert!(rt.block_on(i2c.scan(I2cScan{method: ProbeMethod::Auto, first: 0x50, last: 0x10})).is_err());
+    assert!(rt.block_on(i2c.scan(I2cScan{method: ProbeMethod::Auto, first: 0x00, last: 0x80})).is_err());
+}
+
+#[test]
+fn smbus_ops() {
+    let mut rt = Runtime::new().unwrap();
+    let mut sim = sim::Client::default();
+    sim.add_i2c_device(BUS, ADDR, RegisterMap::new(256));
+
+    let mut i2c = connect(&mut rt, &sim);
+    let regs = |s: &sim::Client| s.i2c_registers(BUS, ADDR).unwrap();
+
+    // Bytes
+    rt.block_on(i2c.smbus_write_byte(ADDR, 0x10, 0xab)).unwrap();
+    assert_eq!(regs(&sim)[0x10], 0xab);
+    assert_eq!(rt.block_on(i2c.smbus_read_byte(ADDR, 0x10)).unwrap(), 0xab);
+
+    // Words are little endian
+    rt.block_on(i2c.smbus_write_word(ADDR, 0x20, 0x1234)).unwrap();
+    assert_eq!(&regs(&sim)[0x20..0x22], &[0x34, 0x12]);
+    assert_eq!(rt.block_on(i2c.smbus_read_word(ADDR, 0x20)).unwrap(), 0x1234);
+
+    // Blocks are prefixed with their length
+    rt.block_on(i2c.smbus_write_block(ADDR, 0x30, &[1, 2, 3])).unwrap();
+    assert_eq!(&regs(&sim)[0x30..0x34], &[3, 1, 2, 3]);
+    assert_eq!(rt.block_on(i2c.smbus_read_block(ADDR, 0x30)).unwrap(), vec![1, 2, 3]);
+    assert!(rt.block_on(i2c.smbus_write_block(ADDR, 0x30, &[0u8; 33])).is_err());
+
+    // Process calls write a word then read the following word
+    rt.block_on(i2c.smbus_write_word(ADDR, 0x42, 0xbeef)).unwrap();
+    assert_eq!(rt.block_on(i2c.smbus_process_call(ADDR, 0x40, 0x5678)).unwrap(), 0xbeef);
+    assert_eq!(&regs(&sim)[0x40..0x42], &[0x78, 0x56]);
+
+    // Devices not present fail
+    assert!(rt.block_on(i2c.smbus_read_byte(0x51, 0x10)).is_err());
+}
+
+#[test]
+fn smbus_pec() {
+    let mut rt = Runtime::new().unwrap();
+    let mut sim = sim::Client::default();
+    sim.add_i2c_device(BUS, ADDR, RegisterMap::new(256));
+
+    let mut i2c = connect(&mut rt, &sim);
+    i2c.set_pec(true);
+
+    let (w, r) = (ADDR << 1, (ADDR << 1) | 1);
+
+    // Writes append the PEC byte, written to the following register by the sim
+    rt.block_on(i2c.smbus_write_byte(ADDR, 0x10, 0xab)).unwrap();
+    assert_eq!(sim.i2c_registers(BUS, ADDR).unwrap()[0x11], pec(&[w, 0x10, 0xab]));
+
+    // Reads check the trailing PEC byte
+    rt.block_on(i2c.smbus_write_word(ADDR, 0x20, 0x1234)).unwrap();
+    i2c.set_pec(false);
+    rt.block_on(i2c.smbus_write_byte(ADDR, 0x22, pec(&[w, 0x20, r, 0x34, 0x12]))).unwrap();
+    i2c.set_pec(true);
+    assert_eq!(rt.block_on(i2c.smbus_read_word(ADDR, 0x20)).unwrap(), 0x1234);
+
+    // Mismatched PEC bytes fail, unless PEC is disabled
+    rt.block_on(i2c.smbus_write_byte(ADDR, 0x30, 0x01)).unwrap();
+    i2c.set_pec(false);
+    rt.block_on(i2c.smbus_write_byte(ADDR, 0x31, !pec(&[w, 0x30, r, 0x01]))).unwrap();
+    assert_eq!(rt.block_on(i2c.smbus_read_byte(ADDR, 0x30)).unwrap(), 0x01);
+    i2c.set_pec(true);
+    assert!(rt.block_on(i2c.smbus_read_byte(ADDR, 0x30)).is_err());
+}
@@ -0,0 +1,91 @@
+//! Simulated pin wiring tests
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+
+extern crate remote_hal;
+use remote_hal::common::{PinMode, PinOptions, Edge};
+use remote_hal::server::Backend;
+use remote_hal::sim;
+
+fn output() -> PinMode {
+    PinMode::Output(PinOptions::default())
+}
+
+fn input() -> PinMode {
+    PinMode::Input(PinOptions::default())
+}
+
+#[test]
+fn wired_pins() {
+    let mut sim = sim::Client::default();
+    sim.wire("out", "in");
+
+    let mut out = sim.pin("out", output()).unwrap();
+    let input = sim.pin("in", input()).unwrap();
+
+    out.set(true).unwrap();
+    assert!(input.get().unwrap());
+    assert!(sim.pin_value("in"));
+
+    out.set(false).unwrap();
+    assert!(!input.get().unwrap());
+
+    // Unwired pins are independent
+    sim.set_pin("other", true);
+    assert!(!input.get().unwrap());
+
+    // Inputs can not be driven, and outputs can not be subscribed to
+    let mut input = input;
+    assert!(input.set(true).is_err());
+    assert!(out.subscribe(Edge::Both, Box::new(|_e| ())).is_err());
+}
+
+#[test]
+fn pin_events() {
+    let mut sim = sim::Client::default();
+    sim.wire("out", "in");
+
+    let mut out = sim.pin("out", output()).unwrap();
+    let mut input = sim.pin("in", input()).unwrap();
+
+    let (tx, rx) = channel();
+    input.subscribe(Edge::Rising, Box::new(move |e| tx.send(e.edge).unwrap() )).unwrap();
+
+    out.set(true).unwrap();
+    out.set(true).unwrap();
+    out.set(false).unwrap();
+    sim.set_pin("in", true);
+
+    // Only rising edges are reported, and unchanged values do not create edges
+    let edges: Vec<_> = rx.try_iter().collect();
+    assert_eq!(edges, vec![Edge::Rising, Edge::Rising]);
+
+    input.unsubscribe();
+    sim.set_pin("in", false);
+    sim.set_pin("in", true);
+    assert_eq!(rx.try_iter().count(), 0);
+}
+
+#[test]
+fn handlers_access_wiring() {
+    let mut sim = sim::Client::default();
+    sim.wire("out", "in");
+
+    let mut out = sim.pin("out", output()).unwrap();
+    let mut input = sim.pin("in", input()).unwrap();
+
+    // Handlers reading and driving pins must not deadlock on the wiring
+    let seen = Arc::new(Mutex::new(vec![]));
+    let (s, mut h) = (seen.clone(), sim.clone());
+    input.subscribe(Edge::Both, Box::new(move |_e| {
+        s.lock().unwrap().push(h.pin_value("in"));
+        h.set_pin("echo", h.pin_value("in"));
+    })).unwrap();
+
+    out.set(true).unwrap();
+    sim.set_pin("out", false);
+
+    assert_eq!(*seen.lock().unwrap(), vec![true, false]);
+    assert!(!sim.pin_value("echo"));
+}
@@ -0,0 +1,81 @@
+//! Simulated SPI transaction tests, using a server with the simulated backend
+
+extern crate tokio;
+use tokio::prelude::*;
+use tokio::runtime::Runtime;
+
+extern crate remote_hal;
+use remote_hal::common::{SpiMode, SpiOp, PinMode, PinOptions};
+use remote_hal::error::Error;
+use remote_hal::manager::Manager;
+use remote_hal::remote::{Client, ClientOptions};
+use remote_hal::server::{Server, ServerOptions};
+use remote_hal::sim;
+
+fn setup(rt: &mut Runtime) -> (Server, sim::Client) {
+    let mut sim = sim::Client::default();
+    sim.add_spi_loopback("spi0");
+    sim.set_pin("cs0", true);
+
+    let backend = sim.clone();
+    let server = rt.block_on(future::lazy(move || {
+        Server::with_backend("127.0.0.1:0".parse().unwrap(), ServerOptions::default(), Box::new(backend))
+    })).unwrap();
+
+    (server, sim)
+}
+
+fn output() -> PinMode {
+    PinMode::Output(PinOptions::default())
+}
+
+fn ops() -> Vec<SpiOp> {
+    vec![SpiOp::Transfer(vec![0x01, 0x02]), SpiOp::DelayUs(10), SpiOp::Read(1)]
+}
+
+#[test]
+fn delays_require_software_cs() {
+    let mut rt = Runtime::new().unwrap();
+    let (server, sim) = setup(&mut rt);
+
+    let mut client = rt.block_on(Client::new(server.addr(), ClientOptions::default())).unwrap();
+    let mut spi = rt.block_on(client.spi("spi0", 1_000_000, SpiMode::Mode0)).unwrap();
+    let _cs = rt.block_on(client.pin("cs0", output())).unwrap();
+
+    // Hardware chip selects can not be held across delays
+    match rt.block_on(spi.transaction(ops())) {
+        Err(Error::Unsupported(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    // Delays are split around with a software chip select
+    spi.set_cs(Some("cs0"));
+    let data = rt.block_on(spi.transaction(ops())).unwrap();
+    assert_eq!(data, vec![vec![0x01, 0x02], vec![], vec![0x00]]);
+    assert!(sim.pin_value("cs0"));
+}
+
+#[test]
+fn cs_must_be_owned() {
+    let mut rt = Runtime::new().unwrap();
+    let (server, _sim) = setup(&mut rt);
+
+    let mut a = rt.block_on(Client::new(server.addr(), ClientOptions::default())).unwrap();
+    let mut b = rt.block_on(Client::new(server.addr(), ClientOptions::default())).unwrap();
+
+    let mut spi = rt.block_on(a.spi("spi0", 1_000_000, SpiMode::Mode0)).unwrap();
+    spi.set_cs(Some("cs0"));
+
+    // Chip selects must be bound before use
+    match rt.block_on(spi.transaction(ops())) {
+        Err(Error::DeviceNotBound) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    // And may not be driven when bound by another client
+    let _cs = rt.block_on(b.pin("cs0", output())).unwrap();
+    match rt.block_on(spi.transaction(ops())) {
+        Err(Error::NotPermitted(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
@@ -0,0 +1,332 @@
+//! Client request timeout and retry tests, using a simulated backend that stalls
+//! device operations (and blocks serial reads for the full timeout)
+
+#![allow(bare_trait_objects)]
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+extern crate tokio;
+use tokio::prelude::*;
+use tokio::codec::Framed;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::timer::Delay;
+
+extern crate remote_hal;
+use remote_hal::common::*;
+use remote_hal::asynch::digital::InputPin;
+use remote_hal::asynch::spi::Transfer;
+use remote_hal::codec::{Codec, WireCodec};
+use remote_hal::error::Error;
+use remote_hal::manager::Manager;
+use remote_hal::remote::{Client, ClientOptions};
+use remote_hal::server::{Server, ServerOptions};
+use remote_hal::server::backend::*;
+use remote_hal::sim;
+
+const TIMEOUT: Duration = Duration::from_millis(100);
+const STALL: Duration = Duration::from_millis(250);
+
+/// Device operations stalled by the backend
+#[derive(Default)]
+struct Stall {
+    /// Number of operations to stall
+    remaining: AtomicUsize,
+    /// Number of operations executed
+    calls: AtomicUsize,
+}
+
+impl Stall {
+    fn call(&self) {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+
+        let stalled = self.remaining.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1) ).is_ok();
+        if stalled {
+            thread::sleep(STALL);
+        }
+    }
+}
+
+/// Simulated backend with pin reads and SPI transfers stalled
+struct Stalled {
+    sim: sim::Client,
+    stall: Arc<Stall>,
+}
+
+struct StalledPin {
+    inner: Box<PinDevice>,
+    stall: Arc<Stall>,
+}
+
+impl PinDevice for StalledPin {
+    fn get(&self) -> Result<bool, Error> {
+        self.stall.call();
+        self.inner.get()
+    }
+
+    fn set(&mut self, value: bool) -> Result<(), Error> {
+        self.inner.set(value)
+    }
+
+    fn subscribe(&mut self, edge: Edge, handler: Box<FnMut(PinEvent) + Send>) -> Result<(), Error> {
+        self.inner.subscribe(edge, handler)
+    }
+
+    fn unsubscribe(&mut self) {
+        self.inner.unsubscribe()
+    }
+}
+
+struct StalledSpi {
+    inner: Box<SpiDevice>,
+    stall: Arc<Stall>,
+}
+
+impl SpiDevice for StalledSpi {
+    fn transfer(&mut self, data: &mut [u8]) -> Result<(), Error> {
+        self.stall.call();
+        self.inner.transfer(data)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.inner.write(data)
+    }
+}
+
+/// Serial port without data, blocking reads for the full timeout
+struct StalledSerial;
+
+impl SerialDevice for StalledSerial {
+    fn read_timeout(&mut self, _buff: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        thread::sleep(timeout);
+        Ok(0)
+    }
+
+    fn write_all(&mut self, _data: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl Backend for Stalled {
+    fn spi(&mut self, path: &str, baud: u32, mode: SpiMode) -> Result<Box<SpiDevice>, Error> {
+        let inner = Backend::spi(&mut self.sim, path, baud, mode)?;
+        Ok(Box::new(StalledSpi{inner, stall: self.stall.clone()}))
+    }
+
+    fn i2c(&mut self, path: &str) -> Result<Box<I2cDevice>, Error> {
+        Backend::i2c(&mut self.sim, path)
+    }
+
+    fn pin(&mut self, path: &str, mode: PinMode) -> Result<Box<PinDevice>, Error> {
+        let inner = Backend::pin(&mut self.sim, path, mode)?;
+        Ok(Box::new(StalledPin{inner, stall: self.stall.clone()}))
+    }
+
+    fn serial(&mut self, _path: &str, _config: &SerialConnect) -> Result<Box<SerialDevice>, Error> {
+        Ok(Box::new(StalledSerial))
+    }
+
+    fn pwm(&mut self, path: &str) -> Result<Box<PwmDevice>, Error> {
+        Backend::pwm(&mut self.sim, path)
+    }
+
+    fn adc(&mut self, path: &str) -> Result<Box<AdcDevice>, Error> {
+        Backend::adc(&mut self.sim, path)
+    }
+
+    fn can(&mut self, path: &str) -> Result<Box<CanDevice>, Error> {
+        Backend::can(&mut self.sim, path)
+    }
+}
+
+/// Start a stalling server
+fn server(rt: &mut Runtime) -> (Server, Arc<Stall>) {
+    let mut sim = sim::Client::default();
+    sim.add_spi_loopback("spi0");
+
+    let stall = Arc::new(Stall::default());
+    let backend = Box::new(Stalled{sim, stall: stall.clone()});
+
+    let server = rt.block_on(future::lazy(move || {
+        Server::with_backend("127.0.0.1:0".parse().unwrap(), ServerOptions::default(), backend)
+    })).unwrap();
+
+    (server, stall)
+}
+
+/// Start a stalling server and connect a client with the provided retries
+fn connect(rt: &mut Runtime, retries: u32) -> (Client, Arc<Stall>) {
+    let (server, stall) = server(rt);
+
+    let options = ClientOptions{request_timeout: TIMEOUT, retries, ..ClientOptions::default()};
+    let client = rt.block_on(Client::new(server.addr(), options)).unwrap();
+
+    (client, stall)
+}
+
+fn input() -> PinMode {
+    PinMode::Input(PinOptions::default())
+}
+
+/// Wait for stalled operations (and any retries) to complete
+fn settle(rt: &mut Runtime) {
+    rt.block_on(Delay::new(Instant::now() + STALL * 3)).unwrap();
+}
+
+#[test]
+fn request_timeout() {
+    let mut rt = Runtime::new().unwrap();
+    let (mut client, stall) = connect(&mut rt, 0);
+
+    let pin = rt.block_on(client.pin("gpio0", input())).unwrap();
+
+    stall.remaining.store(1, Ordering::SeqCst);
+    match rt.block_on(pin.is_high()) {
+        Err(Error::Timeout) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    // Late responses to timed out requests are discarded
+    settle(&mut rt);
+    assert_eq!(stall.calls.load(Ordering::SeqCst), 1);
+    assert!(!rt.block_on(pin.is_high()).unwrap());
+}
+
+#[test]
+fn idempotent_retries() {
+    let mut rt = Runtime::new().unwrap();
+    let (mut client, stall) = connect(&mut rt, 3);
+
+    let pin = rt.block_on(client.pin("gpio0", input())).unwrap();
+
+    // The first attempt stalls, so the response is from a retry
+    stall.remaining.store(1, Ordering::SeqCst);
+    assert!(!rt.block_on(pin.is_high()).unwrap());
+
+    settle(&mut rt);
+    assert!(stall.calls.load(Ordering::SeqCst) >= 2);
+    assert!(!rt.block_on(pin.is_high()).unwrap());
+}
+
+#[test]
+fn non_idempotent_not_retried() {
+    let mut rt = Runtime::new().unwrap();
+    let (mut client, stall) = connect(&mut rt, 3);
+
+    let mut spi = rt.block_on(client.spi("spi0", 1_000_000, SpiMode::Mode0)).unwrap();
+
+    stall.remaining.store(1, Ordering::SeqCst);
+    match rt.block_on(spi.transfer(vec![0x01, 0x02])) {
+        Err(Error::Timeout) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    settle(&mut rt);
+    assert_eq!(stall.calls.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn handle_timeout() {
+    let mut rt = Runtime::new().unwrap();
+    let (mut client, stall) = connect(&mut rt, 0);
+
+    let pin = rt.block_on(client.pin("gpio0", input())).unwrap();
+    let mut slow = pin.clone();
+    slow.set_timeout(STALL * 2);
+
+    // The handle timeout overrides the client timeout
+    stall.remaining.store(1, Ordering::SeqCst);
+    assert!(!rt.block_on(slow.is_high()).unwrap());
+
+    // Without affecting other handles
+    stall.remaining.store(1, Ordering::SeqCst);
+    match rt.block_on(pin.is_high()) {
+        Err(Error::Timeout) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    settle(&mut rt);
+    assert_eq!(stall.calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn slow_serial_read() {
+    let mut rt = Runtime::new().unwrap();
+    let (server, _stall) = server(&mut rt);
+
+    let mut a = rt.block_on(Client::new(server.addr(), ClientOptions::default())).unwrap();
+    let mut b = rt.block_on(Client::new(server.addr(), ClientOptions::default())).unwrap();
+
+    let config = SerialConnect{baud: 115200, data_bits: DataBits::Eight, parity: Parity::None, stop_bits: StopBits::One, flow_control: FlowControl::None};
+    let _serial = rt.block_on(a.serial("tty0", config)).unwrap();
+
+    // Reads blocking the server for their timeout do not stall other connections
+    let started = Instant::now();
+    let read = a.request("tty0", RequestKind::SerialRead(SerialRead{max_len: 16, timeout_ms: 1000}));
+    let (tx, rx) = std::sync::mpsc::channel();
+    rt.spawn(read.then(move |r| { let _ = tx.send(r.is_ok()); Ok(()) }));
+
+    rt.block_on(Delay::new(Instant::now() + TIMEOUT)).unwrap();
+    rt.block_on(b.request("", RequestKind::Ping)).unwrap();
+    assert!(started.elapsed() < Duration::from_millis(500), "ping stalled for {:?}", started.elapsed());
+
+    assert_eq!(rx.recv_timeout(Duration::from_secs(2)), Ok(true));
+    assert!(started.elapsed() >= Duration::from_millis(1000));
+}
+
+#[test]
+fn retry_ids() {
+    let mut rt = Runtime::new().unwrap();
+
+    // Fake server that answers the hello, then only answers the second ping
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let ids = Arc::new(Mutex::new(vec![]));
+
+    let capabilities = Capabilities{
+        protocol_version: PROTOCOL_VERSION,
+        server_version: "fake".to_owned(),
+        devices: vec![],
+        codecs: vec![Codec::Json],
+        max_transfer: 1024,
+        heartbeat_ms: None,
+        max_delay_us: None,
+    };
+
+    let seen = ids.clone();
+    let fake = listener.incoming().into_future().map_err(|_e| () ).and_then(move |(socket, _)| {
+        let (tx, rx) = Framed::new(socket.unwrap(), WireCodec::<Response, Request, Error>::server(&[Codec::Json])).split();
+
+        rx.filter_map(move |req| {
+            match req.kind {
+                RequestKind::Hello(_) => Some(Response{id: req.id, kind: ResponseKind::Capabilities(capabilities.clone())}),
+                _ => {
+                    let mut seen = seen.lock().unwrap();
+                    seen.push(req.id);
+                    if seen.len() > 1 { Some(Response{id: req.id, kind: ResponseKind::Ok}) } else { None }
+                },
+            }
+        }).forward(tx).map(|_| () ).map_err(|_e| () )
+    });
+    rt.spawn(fake);
+
+    let options = ClientOptions{request_timeout: TIMEOUT, retries: 1, ..ClientOptions::default()};
+    let mut client = rt.block_on(Client::new(addr, options)).unwrap();
+
+    // Retries are sent with a fresh id
+    match rt.block_on(client.request("", RequestKind::Ping)) {
+        Ok(ResponseKind::Ok) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    let ids = ids.lock().unwrap();
+    assert_eq!(ids.len(), 2);
+    assert_ne!(ids[0], ids[1]);
+}
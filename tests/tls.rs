@@ -0,0 +1,133 @@
+//! TLS loopback tests, certificates are generated using the `openssl` CLI
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+extern crate tokio;
+use tokio::prelude::*;
+use tokio::runtime::Runtime;
+
+extern crate remote_hal;
+use remote_hal::common::{RequestKind, ResponseKind};
+use remote_hal::error::Error;
+use remote_hal::remote::{Client, ClientOptions};
+use remote_hal::server::{Server, ServerOptions};
+use remote_hal::transport::{ClientTls, ServerTls};
+use remote_hal::sim;
+
+fn openssl(dir: &Path, args: &[&str]) {
+    let status = Command::new("openssl").args(args).current_dir(dir)
+        .stdout(Stdio::null()).stderr(Stdio::null()).status()
+        .expect("error running openssl");
+    assert!(status.success(), "openssl {:?} failed", args);
+}
+
+/// Generate a CA with server (for `localhost`) and client certificates signed by it,
+/// as well as an unrelated CA and a client certificate signed by this
+fn certs(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("remote-hal-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    for ca in &["ca", "untrusted"] {
+        openssl(&dir, &["req", "-x509", "-newkey", "rsa:2048", "-nodes", "-days", "1",
+            "-keyout", &format!("{}.key", ca), "-out", &format!("{}.crt", ca), "-subj", &format!("/CN=remote-hal test {}", ca)]);
+    }
+
+    fs::write(dir.join("server.ext"), "subjectAltName=DNS:localhost\nbasicConstraints=CA:FALSE\nextendedKeyUsage=serverAuth\n").unwrap();
+    openssl(&dir, &["req", "-newkey", "rsa:2048", "-nodes", "-keyout", "server.key", "-out", "server.csr", "-subj", "/CN=localhost"]);
+    openssl(&dir, &["x509", "-req", "-in", "server.csr", "-CA", "ca.crt", "-CAkey", "ca.key", "-CAcreateserial",
+        "-days", "1", "-extfile", "server.ext", "-out", "server.crt"]);
+
+    fs::write(dir.join("client.ext"), "basicConstraints=CA:FALSE\nextendedKeyUsage=clientAuth\n").unwrap();
+    for (client, ca) in &[("client", "ca"), ("rogue", "untrusted")] {
+        openssl(&dir, &["req", "-newkey", "rsa:2048", "-nodes", "-keyout", &format!("{}.key", client),
+            "-out", &format!("{}.csr", client), "-subj", &format!("/CN=remote-hal test {}", client)]);
+        openssl(&dir, &["x509", "-req", "-in", &format!("{}.csr", client), "-CA", &format!("{}.crt", ca), "-CAkey", &format!("{}.key", ca),
+            "-CAcreateserial", "-days", "1", "-extfile", "client.ext", "-out", &format!("{}.crt", client)]);
+    }
+
+    dir
+}
+
+fn path(dir: &Path, file: &str) -> String {
+    dir.join(file).to_str().unwrap().to_owned()
+}
+
+fn server_tls(dir: &Path, client_ca: Option<&str>) -> ServerTls {
+    ServerTls{cert: path(dir, "server.crt"), key: path(dir, "server.key"), client_ca: client_ca.map(|c| path(dir, c) )}
+}
+
+/// Client TLS configuration trusting the provided CA, with an optional client certificate and key
+fn client_tls(dir: &Path, ca: &str, client: Option<&str>) -> ClientTls {
+    let cert = client.map(|c| path(dir, &format!("{}.crt", c)) );
+    let key = client.map(|c| path(dir, &format!("{}.key", c)) );
+    ClientTls{ca: path(dir, ca), cert, key, domain: "localhost".to_owned()}
+}
+
+/// Start a TLS server and send a ping using the provided client configuration
+fn ping(server: ServerTls, client: ClientTls) -> Result<ResponseKind, Error> {
+    let mut rt = Runtime::new().unwrap();
+
+    let options = ServerOptions{tls: Some(server), ..ServerOptions::default()};
+
+    let server = rt.block_on(future::lazy(move || {
+        Server::with_backend("127.0.0.1:0".parse().unwrap(), options, Box::new(sim::Client::default()))
+    })).unwrap();
+
+    let options = ClientOptions{tls: Some(client), ..ClientOptions::default()};
+
+    rt.block_on(Client::new(server.addr(), options).and_then(|mut c| c.request("", RequestKind::Ping) ))
+}
+
+#[test]
+fn tls_handshake() {
+    let dir = certs("tls-handshake");
+
+    match ping(server_tls(&dir, None), client_tls(&dir, "ca.crt", None)).expect("error connecting via TLS") {
+        ResponseKind::Ok => (),
+        r => panic!("unexpected response: {:?}", r),
+    }
+}
+
+#[test]
+fn tls_untrusted_certificate() {
+    let dir = certs("tls-untrusted");
+
+    assert!(ping(server_tls(&dir, None), client_tls(&dir, "untrusted.crt", None)).is_err());
+}
+
+#[test]
+fn tls_client_auth() {
+    let dir = certs("tls-client-auth");
+
+    match ping(server_tls(&dir, Some("ca.crt")), client_tls(&dir, "ca.crt", Some("client"))).expect("error connecting via mutual TLS") {
+        ResponseKind::Ok => (),
+        r => panic!("unexpected response: {:?}", r),
+    }
+}
+
+#[test]
+fn tls_client_auth_rejected() {
+    let dir = certs("tls-client-rejected");
+
+    // Clients without a certificate, or with a certificate from another CA, are rejected
+    assert!(ping(server_tls(&dir, Some("ca.crt")), client_tls(&dir, "ca.crt", None)).is_err());
+    assert!(ping(server_tls(&dir, Some("ca.crt")), client_tls(&dir, "ca.crt", Some("rogue"))).is_err());
+}
+
+#[test]
+fn tls_client_cert_without_key() {
+    let dir = certs("tls-client-key");
+
+    let mut tls = client_tls(&dir, "ca.crt", Some("client"));
+    tls.key = None;
+
+    match tls.connector() {
+        Err(Error::Tls(msg, _)) => assert!(msg.contains("must be provided together"), "unexpected error: {}", msg),
+        Err(e) => panic!("unexpected error: {}", e),
+        Ok(_) => panic!("client certificate accepted without a key"),
+    }
+}
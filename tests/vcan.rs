@@ -0,0 +1,52 @@
+//! SocketCAN tests, these require a virtual CAN interface and are ignored by default:
+//!
+//! ```text
+//! ip link add dev vcan0 type vcan && ip link set up vcan0
+//! ```
+//!
+//! The interface may be set with `VCAN_IFACE` (default `vcan0`).
+
+use std::env;
+use std::time::{Duration, Instant};
+
+extern crate tokio;
+use tokio::prelude::*;
+use tokio::runtime::Runtime;
+
+extern crate remote_hal;
+use remote_hal::common::CanFrame;
+use remote_hal::local;
+use remote_hal::manager::Manager;
+use remote_hal::remote::{Client, ClientOptions};
+use remote_hal::server::{Server, ServerOptions};
+
+fn iface() -> String {
+    env::var("VCAN_IFACE").unwrap_or("vcan0".to_owned())
+}
+
+#[test]
+#[ignore]
+fn vcan_receive() {
+    let mut rt = Runtime::new().unwrap();
+    let iface = iface();
+
+    let options = ServerOptions{max_delay: Duration::from_millis(100), ..ServerOptions::default()};
+    let server = rt.block_on(future::lazy(move || {
+        Server::new("127.0.0.1:0".parse().unwrap(), options)
+    })).unwrap();
+
+    let i = iface.clone();
+    let mut can = rt.block_on(Client::new(server.addr(), ClientOptions::default()).and_then(move |mut c| c.can(&i) )).unwrap();
+
+    // Receive timeouts are clamped to the server delay limit, well within the client timeout
+    let start = Instant::now();
+    assert_eq!(rt.block_on(can.receive(60_000)).unwrap(), None);
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // Frames sent by other sockets on the interface are received
+    let frame = CanFrame{id: 0x123, extended: false, remote: false, data: vec![1, 2, 3]};
+    let mut local = local::Can::new(&iface).unwrap();
+    local.send(&frame).unwrap();
+
+    assert_eq!(rt.block_on(can.receive(1_000)).unwrap(), Some(frame));
+}